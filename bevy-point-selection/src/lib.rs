@@ -1,39 +1,233 @@
 //! Inspired by https://github.com/Anshorei/bevy_rei/tree/master/bevy_interact_2d
 
-use bevy::{prelude::*, render::camera::RenderTarget, utils::HashSet};
+use bevy::{
+    prelude::*,
+    render::camera::RenderTarget,
+    utils::{HashMap, HashSet},
+    window::WindowId,
+};
 
-pub struct PointSelectionPlugin;
+#[derive(Default)]
+pub struct PointSelectionPlugin {
+    /// Only select the closest [`Selectable`] under the cursor
+    pub exclusive: bool,
+    /// Interpret the sizes of [`SelectionShape::Circle`] and [`SelectionShape::Rect`] in logical
+    /// pixels instead of world units, so they keep their size on screen when the camera zooms
+    pub screen_space: bool,
+}
+
+impl PointSelectionPlugin {
+    /// Selects only the [`Selectable`] closest to the cursor, the topmost one on ties.
+    pub fn exclusive() -> Self {
+        Self {
+            exclusive: true,
+            ..Default::default()
+        }
+    }
+}
 
 impl Plugin for PointSelectionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(selection_system).add_system(update_selector);
+        app.insert_resource(SelectionMode {
+            exclusive: self.exclusive,
+            screen_space: self.screen_space,
+        })
+        .init_resource::<SelectionCursor>()
+        .init_resource::<PointerBlocked>()
+        .add_event::<SelectionChanged>()
+        .add_system(selection_system.label(SelectionSet::UpdateSelectables))
+        .add_system(
+            update_selector
+                .label(SelectionSet::UpdateIndicator)
+                .after(SelectionSet::UpdateSelectables),
+        );
     }
 }
 
+/// Labels of the systems of the [`PointSelectionPlugin`], e.g. to read the selection of the
+/// current frame with `.after(SelectionSet::UpdateIndicator)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
+pub enum SelectionSet {
+    /// Updates `is_selected` of all [`Selectable`]s
+    UpdateSelectables,
+    /// Updates the [`SelectionIndicator`]
+    UpdateIndicator,
+}
+
 /// Add this component to the Camera
 #[derive(Component)]
 pub struct SelectionSource;
 
-/// Use with a `Changed<Selectable>` filter to skip unchanged Selectables.
+/// Bitmask of selection layers on a [`SelectionSource`] or a [`Selectable`]. A source only
+/// selects sinks sharing at least one layer with it. Entities without this component are in all
+/// layers, so e.g. a minimap camera needs layers on itself and on the main view camera.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionLayer(pub u32);
+
+impl SelectionLayer {
+    pub fn intersects(&self, other: &SelectionLayer) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+/// Whether a source and a sink with these layers interact, missing layers match everything.
+fn layers_match(source: Option<&SelectionLayer>, sink: Option<&SelectionLayer>) -> bool {
+    match (source, sink) {
+        (Some(source), Some(sink)) => source.intersects(sink),
+        _ => true,
+    }
+}
+
+/// How overlapping [`Selectable`]s are selected, inserted by the [`PointSelectionPlugin`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SelectionMode {
+    /// Only select the closest one instead of all of them
+    pub exclusive: bool,
+    /// Sizes of circles and rects are in logical pixels of the [`SelectionSource`]
+    pub screen_space: bool,
+}
+
+/// Overrides the cursor position of all [`SelectionSource`] cameras, e.g. to drive the selection
+/// programmatically. Cameras rendering to an image only select with an overridden position.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SelectionCursor {
+    /// In logical pixels from the bottom left corner of the render target
+    pub position: Option<Vec2>,
+}
+
+/// While set, nothing is selected, e.g. because the pointer is over a UI element. Menus should
+/// set it every frame before the selection system runs, for example from the bevy_ui
+/// `Interaction` of their nodes in `CoreStage::PreUpdate`, so that a click on a button doesn't
+/// also select what's underneath it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PointerBlocked(pub bool);
+
+/// Sent by the selection system whenever `is_selected` of a [`Selectable`] flips.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectionChanged {
+    pub entity: Entity,
+    pub is_selected: bool,
+}
+
+/// Use with a `Changed<Selectable>` filter to skip unchanged Selectables or read the
+/// [`SelectionChanged`] events instead.
 /// Somewhat analogous to bevy_ui Interactible
 ///
 /// Entities must have a [`GlobalTransform`] components for the system to update `is_selected`.
-///
-/// todo: add other colliders, custom offset?
 #[derive(Component)]
 pub struct Selectable {
-    /// Radius from center of transform in world units
-    pub selection_radius: f32,
+    pub shape: SelectionShape,
+    /// Offset of the shape in the local space of the entity, so it is scaled, rotated and flipped
+    /// with the entity
+    pub offset: Vec2,
+    /// Disabled selectables are never selected
+    pub enabled: bool,
     pub is_selected: bool,
 }
 
 impl Selectable {
+    /// Selectable within `radius` world units of the center.
     pub fn new(radius: f32) -> Selectable {
+        Selectable::with_shape(SelectionShape::Circle { radius })
+    }
+
+    /// Selectable within a rectangle of `half_extents` world units around the center, which is
+    /// rotated with the entity.
+    pub fn rect(half_extents: Vec2) -> Selectable {
+        Selectable::with_shape(SelectionShape::Rect { half_extents })
+    }
+
+    pub fn with_shape(shape: SelectionShape) -> Selectable {
         Selectable {
-            selection_radius: radius,
+            shape,
+            offset: Vec2::ZERO,
+            enabled: true,
             is_selected: false,
         }
     }
+
+    /// Disabled selectables are deselected the next time the selection is updated.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn with_offset(mut self, offset: Vec2) -> Selectable {
+        self.offset = offset;
+        self
+    }
+
+    /// The placement of the shape for an entity at `transform`, including the offset.
+    pub fn placement(&self, transform: &GlobalTransform) -> GlobalTransform {
+        if self.offset == Vec2::ZERO {
+            return *transform;
+        }
+        transform.mul_transform(Transform::from_translation(self.offset.extend(0.)))
+    }
+
+    /// Whether the world position `point` lies inside the shape of an entity at `transform`.
+    pub fn contains(&self, transform: &GlobalTransform, point: Vec2) -> bool {
+        self.shape.contains(&self.placement(transform), point)
+    }
+}
+
+/// The area of a [`Selectable`] around its transform.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectionShape {
+    /// Radius from center of transform in world units (or logical pixels in screen space mode),
+    /// unaffected by scale and rotation
+    Circle { radius: f32 },
+    /// Rectangle centered on the transform in world units (or logical pixels in screen space
+    /// mode), rotated but not scaled with the entity
+    Rect { half_extents: Vec2 },
+    /// Axis aligned box in the local space of the entity, so it is scaled, rotated and flipped
+    /// with the entity
+    Aabb { min: Vec2, max: Vec2 },
+    /// Convex polygon in the local space of the entity, corners in counter clockwise order
+    Polygon { corners: Vec<Vec2> },
+}
+
+impl SelectionShape {
+    /// Whether the world position `point` lies inside the shape placed at `transform`.
+    pub fn contains(&self, transform: &GlobalTransform, point: Vec2) -> bool {
+        self.contains_scaled(transform, point, 1.)
+    }
+
+    /// Like [`SelectionShape::contains`], with the sizes of circles and rects multiplied by
+    /// `unit`, e.g. the world units per pixel.
+    fn contains_scaled(&self, transform: &GlobalTransform, point: Vec2, unit: f32) -> bool {
+        match self {
+            SelectionShape::Circle { radius } => {
+                let radius = radius * unit;
+                transform.translation().truncate().distance_squared(point) <= radius * radius
+            }
+            SelectionShape::Rect { half_extents } => {
+                let (_, rotation, translation) = transform.to_scale_rotation_translation();
+                let local = rotation.inverse() * (point.extend(translation.z) - translation);
+                local.truncate().abs().cmple(*half_extents * unit).all()
+            }
+            SelectionShape::Aabb { min, max } => {
+                let local = world_to_local(transform, point);
+                local.cmpge(*min).all() && local.cmple(*max).all()
+            }
+            SelectionShape::Polygon { corners } => {
+                let local = world_to_local(transform, point);
+                let next = corners.iter().cycle().skip(1);
+                corners
+                    .iter()
+                    .zip(next)
+                    .all(|(&a, &b)| (b - a).perp_dot(local - a) >= 0.)
+            }
+        }
+    }
+}
+
+/// Transforms a world position into the local space of `transform`, undoing its scale and rotation.
+fn world_to_local(transform: &GlobalTransform, point: Vec2) -> Vec2 {
+    transform
+        .affine()
+        .inverse()
+        .transform_point3(point.extend(transform.translation().z))
+        .truncate()
 }
 
 /// Entities with this component will be moved to a selected [`Selectable`] or be set to invisible
@@ -53,72 +247,510 @@ impl SelectionIndicator {
     }
 }
 
+/// A ray through the scene, e.g. of all world positions under the cursor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WorldRay {
+    /// The point on the near plane of the camera
+    pub origin: Vec3,
+    /// Normalized direction away from the camera
+    pub direction: Vec3,
+}
+
+impl WorldRay {
+    /// The point where the ray hits the plane at height `z`, or `None` if it never does.
+    pub fn intersect_z_plane(&self, z: f32) -> Option<Vec3> {
+        if self.direction.z.abs() <= f32::EPSILON {
+            return None;
+        }
+        let distance = (z - self.origin.z) / self.direction.z;
+        if distance < 0. {
+            return None;
+        }
+        Some(self.origin + distance * self.direction)
+    }
+}
+
+/// The world position under the cursor of `window`, see [`cursor_to_world`].
 pub fn viewport_to_world(
     camera: &Camera,
     cam_transform: &GlobalTransform,
     window: &Window,
 ) -> Option<Vec2> {
-    // Math from https://github.com/Anshorei/bevy_rei/tree/master/bevy_interact_2d
     let cursor_pos = window.cursor_position()?;
-    let projection_matrix = camera.projection_matrix();
     let screen_size = Vec2::new(window.width(), window.height());
-    let cursor_position_ndc = (cursor_pos / screen_size) * 2.0 - Vec2::from([1.0, 1.0]);
+    cursor_to_world(
+        camera,
+        cam_transform,
+        cursor_pos,
+        screen_size,
+        window.scale_factor() as f32,
+    )
+}
+
+/// The ray under the cursor of `window`, see [`cursor_to_world_ray`].
+pub fn viewport_to_world_ray(
+    camera: &Camera,
+    cam_transform: &GlobalTransform,
+    window: &Window,
+) -> Option<WorldRay> {
+    let cursor_pos = window.cursor_position()?;
+    let screen_size = Vec2::new(window.width(), window.height());
+    cursor_to_world_ray(
+        camera,
+        cam_transform,
+        cursor_pos,
+        screen_size,
+        window.scale_factor() as f32,
+    )
+}
+
+/// The world position of `cursor_pos` in logical pixels from the bottom left corner of a render
+/// target of `target_size` logical pixels. Returns `None` if the cursor is outside the viewport
+/// of the camera.
+///
+/// For orthographic cameras this is the point on the near plane, for perspective cameras the
+/// point on the `z = 0` plane, which is `None` if the cursor doesn't point at it.
+pub fn cursor_to_world(
+    camera: &Camera,
+    cam_transform: &GlobalTransform,
+    cursor_pos: Vec2,
+    target_size: Vec2,
+    scale_factor: f32,
+) -> Option<Vec2> {
+    let ray = cursor_to_world_ray(camera, cam_transform, cursor_pos, target_size, scale_factor)?;
+    // The last row of an orthographic projection is (0, 0, 0, 1), of a perspective (0, 0, -1, 0)
+    if camera.projection_matrix().w_axis.w == 1. {
+        Some(ray.origin.truncate())
+    } else {
+        ray.intersect_z_plane(0.).map(Vec3::truncate)
+    }
+}
+
+/// The ray of all world positions under `cursor_pos`, which works for any projection. See
+/// [`cursor_to_world`] for the arguments.
+pub fn cursor_to_world_ray(
+    camera: &Camera,
+    cam_transform: &GlobalTransform,
+    cursor_pos: Vec2,
+    target_size: Vec2,
+    scale_factor: f32,
+) -> Option<WorldRay> {
+    let cursor_pos = cursor_pos * scale_factor;
+    let target_size = target_size * scale_factor;
+    let (viewport_min, viewport_size) = viewport_rect(camera, target_size);
+    let viewport_pos = cursor_pos - viewport_min;
+    if viewport_pos.cmplt(Vec2::ZERO).any() || viewport_pos.cmpgt(viewport_size).any() {
+        return None;
+    }
+
+    // Math from https://github.com/Anshorei/bevy_rei/tree/master/bevy_interact_2d
+    let projection_matrix = camera.projection_matrix();
+    let cursor_position_ndc = (viewport_pos / viewport_size) * 2.0 - Vec2::from([1.0, 1.0]);
     let camera_matrix = cam_transform.compute_matrix();
     let ndc_to_world = camera_matrix * projection_matrix.inverse();
-    let cursor_position = ndc_to_world
-        .transform_point3(cursor_position_ndc.extend(1.0))
-        .truncate();
+    ray_from_ndc(ndc_to_world, cursor_position_ndc)
+}
 
-    Some(cursor_position)
+/// The ray through the point `ndc` in normalized device coordinates.
+fn ray_from_ndc(ndc_to_world: Mat4, ndc: Vec2) -> Option<WorldRay> {
+    // Bevy uses reversed z, so the near plane is at 1 and the (possibly infinite) far plane at 0
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(f32::EPSILON));
+    let direction = (far - near).try_normalize()?;
+    Some(WorldRay {
+        origin: near,
+        direction,
+    })
 }
 
-/// This system updates Selectable components based on the cursor position
-/// Todo: use ChangeTrackers<GlobalTransform> to update less often, but this doesn't
-/// change asymtotic complextity, thus probably is more overhead.
+/// Bottom left corner and size of the viewport of `camera` in physical pixels, given the physical
+/// size of its render target.
+fn viewport_rect(camera: &Camera, target_size: Vec2) -> (Vec2, Vec2) {
+    match &camera.viewport {
+        Some(viewport) => {
+            // The viewport position is relative to the top left corner
+            let size = viewport.physical_size.as_vec2();
+            let top_left = viewport.physical_position.as_vec2();
+            (
+                Vec2::new(top_left.x, target_size.y - top_left.y - size.y),
+                size,
+            )
+        }
+        None => (Vec2::ZERO, target_size),
+    }
+}
+
+/// Inverse of [`viewport_to_world`]: the position of `world_pos` in logical pixels from the bottom
+/// left corner of the window. Returns `None` if the point is behind the camera.
+pub fn world_to_viewport(
+    camera: &Camera,
+    cam_transform: &GlobalTransform,
+    window: &Window,
+    world_pos: Vec2,
+) -> Option<Vec2> {
+    let screen_size = Vec2::new(window.width(), window.height());
+    world_to_cursor(
+        camera,
+        cam_transform,
+        world_pos,
+        screen_size,
+        window.scale_factor() as f32,
+    )
+}
+
+/// Inverse of [`cursor_to_world`].
+pub fn world_to_cursor(
+    camera: &Camera,
+    cam_transform: &GlobalTransform,
+    world_pos: Vec2,
+    target_size: Vec2,
+    scale_factor: f32,
+) -> Option<Vec2> {
+    let target_size = target_size * scale_factor;
+    let world_to_ndc = camera.projection_matrix() * cam_transform.compute_matrix().inverse();
+    let clip = world_to_ndc * world_pos.extend(0.).extend(1.);
+    if clip.w <= 0. {
+        return None;
+    }
+    let ndc = clip.truncate() / clip.w;
+    if ndc.z < 0. || ndc.z > 1. {
+        return None;
+    }
+
+    let (viewport_min, viewport_size) = viewport_rect(camera, target_size);
+    let physical = viewport_min + (ndc.truncate() + Vec2::ONE) / 2. * viewport_size;
+    Some(physical / scale_factor)
+}
+
+/// Uniform spatial hash of the [`Selectable`]s by the center of their placement, so a pointer
+/// only has to be tested against the selectables in nearby cells.
+///
+/// The cell size is the largest extent of any shape, e.g. the largest circle radius. Thus every
+/// shape containing a pointer has its center in the cell of the pointer or one of the eight around
+/// it. Only screen space shapes at zoom levels where a pixel is larger than a world unit need to
+/// search more cells.
+#[derive(Default)]
+struct SelectionGrid {
+    cell_size: f32,
+    /// Largest extent of circles and rects, before converting pixels to world units
+    scaled_extent: f32,
+    /// Largest extent of the shapes in the local space of their entity
+    local_extent: f32,
+    cells: HashMap<IVec2, Vec<Entity>>,
+    /// The cell of every selectable, to move it when its placement changes
+    cell_of: HashMap<Entity, IVec2>,
+}
+
+impl SelectionGrid {
+    fn cell(&self, point: Vec2) -> IVec2 {
+        (point / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Grows the extents to fit the shape of `selectable` and returns the center of its
+    /// placement.
+    fn measure(&mut self, selectable: &Selectable, transform: &GlobalTransform) -> Vec2 {
+        let placement = selectable.placement(transform);
+        let center = placement.translation();
+        let corner_extent = |corners: &[Vec2]| {
+            corners
+                .iter()
+                .map(|corner| {
+                    let world = placement.affine().transform_point3(corner.extend(0.));
+                    (world - center).truncate().length()
+                })
+                .fold(0., f32::max)
+        };
+        match &selectable.shape {
+            SelectionShape::Circle { radius } => {
+                self.scaled_extent = self.scaled_extent.max(*radius)
+            }
+            SelectionShape::Rect { half_extents } => {
+                self.scaled_extent = self.scaled_extent.max(half_extents.length())
+            }
+            SelectionShape::Aabb { min, max } => {
+                let corners = [*min, *max, Vec2::new(min.x, max.y), Vec2::new(max.x, min.y)];
+                self.local_extent = self.local_extent.max(corner_extent(&corners))
+            }
+            SelectionShape::Polygon { corners } => {
+                self.local_extent = self.local_extent.max(corner_extent(corners))
+            }
+        }
+        center.truncate()
+    }
+
+    fn insert(&mut self, entity: Entity, center: Vec2) {
+        let cell = self.cell(center);
+        self.cells.entry(cell).or_default().push(entity);
+        self.cell_of.insert(entity, cell);
+    }
+
+    fn rebuild<'a>(
+        &mut self,
+        sinks: impl Iterator<Item = (Entity, &'a Selectable, &'a GlobalTransform)>,
+    ) {
+        self.scaled_extent = 0.;
+        self.local_extent = 0.;
+        let centers: Vec<(Entity, Vec2)> = sinks
+            .map(|(entity, selectable, transform)| (entity, self.measure(selectable, transform)))
+            .collect();
+
+        self.cell_size = self.scaled_extent.max(self.local_extent);
+        if self.cell_size <= 0. {
+            // Only points, any size works
+            self.cell_size = 1.;
+        }
+        self.cells.clear();
+        self.cell_of.clear();
+        for (entity, center) in centers {
+            self.insert(entity, center);
+        }
+    }
+
+    /// Moves the `moved` selectables to the cells of their new placement. Returns `false` if a
+    /// shape grew larger than the cells, then the grid has to be rebuilt.
+    fn relocate<'a>(
+        &mut self,
+        moved: impl Iterator<Item = (Entity, &'a Selectable, &'a GlobalTransform)>,
+    ) -> bool {
+        for (entity, selectable, transform) in moved {
+            if let Some(cell) = self.cell_of.remove(&entity) {
+                if let Some(entities) = self.cells.get_mut(&cell) {
+                    entities.retain(|&e| e != entity);
+                    if entities.is_empty() {
+                        self.cells.remove(&cell);
+                    }
+                }
+            }
+            let center = self.measure(selectable, transform);
+            if self.scaled_extent.max(self.local_extent) > self.cell_size {
+                return false;
+            }
+            self.insert(entity, center);
+        }
+        true
+    }
+
+    /// All selectables which might contain `pointer`, where `unit` is the size of a pixel in
+    /// screen space mode and 1 otherwise.
+    fn candidates(&self, pointer: Vec2, unit: f32) -> Vec<Entity> {
+        let extent = self.local_extent.max(self.scaled_extent * unit);
+        let rings = (extent / self.cell_size).ceil() as i32;
+        let side = 2 * rings as usize + 1;
+        if side.saturating_mul(side) >= self.cells.len() {
+            // Zoomed out so far that it is cheaper to test everything
+            return self.cells.values().flatten().copied().collect();
+        }
+        let center = self.cell(pointer);
+        let mut candidates = Vec::new();
+        for y in -rings..=rings {
+            for x in -rings..=rings {
+                if let Some(cell) = self.cells.get(&(center + IVec2::new(x, y))) {
+                    candidates.extend_from_slice(cell);
+                }
+            }
+        }
+        candidates
+    }
+}
+
+/// This system updates Selectable components based on the cursor position. Pointers are only
+/// tested against nearby selectables, see [`SelectionGrid`], which is rebuilt whenever a
+/// selectable is added, removed or changed. Moved selectables only change their cell, so
+/// animations don't rebuild it every frame.
 fn selection_system(
+    mut grid: Local<SelectionGrid>,
+    moved: Query<Entity, (With<Selectable>, Changed<GlobalTransform>)>,
+    removed: RemovedComponents<Selectable>,
     windows: Res<Windows>,
-    sources: Query<(&Camera, &GlobalTransform), With<SelectionSource>>,
-    mut sinks: Query<(&mut Selectable, &GlobalTransform)>,
+    images: Res<Assets<Image>>,
+    touches: Res<Touches>,
+    cursor: Res<SelectionCursor>,
+    blocked: Res<PointerBlocked>,
+    sources: Query<(&Camera, &GlobalTransform, Option<&SelectionLayer>), With<SelectionSource>>,
+    mut sinks: Query<(
+        Entity,
+        &mut Selectable,
+        &GlobalTransform,
+        Option<&SelectionLayer>,
+    )>,
+    mut changed: EventWriter<SelectionChanged>,
+    mode: Res<SelectionMode>,
 ) {
-    for (camera, cam_transform) in sources.iter() {
-        // todo: rewrite with iter functions or let else
-        let window = match camera.target {
-            RenderTarget::Window(id) => match windows.get(id) {
-                Some(window) => window,
+    // Iterating mutably doesn't trigger change detection, only writing does
+    let mut dirty = removed.iter().next().is_some()
+        || sinks
+            .iter_mut()
+            .any(|(_, selectable, _, _)| selectable.is_changed());
+    if !dirty {
+        dirty = !grid.relocate(moved.iter().filter_map(|entity| {
+            let (entity, selectable, transform, _) = sinks.get(entity).ok()?;
+            Some((entity, selectable, transform))
+        }));
+    }
+    if dirty {
+        grid.rebuild(
+            sinks
+                .iter()
+                .map(|(entity, selectable, transform, _)| (entity, selectable, transform)),
+        );
+    }
+
+    if blocked.0 {
+        for (entity, mut selectable, _, _) in sinks.iter_mut() {
+            if selectable.is_selected {
+                selectable.is_selected = false;
+                changed.send(SelectionChanged {
+                    entity,
+                    is_selected: false,
+                });
+            }
+        }
+        return;
+    }
+
+    for (camera, cam_transform, source_layer) in sources.iter() {
+        let (window_cursor, target_size, scale_factor) = match &camera.target {
+            RenderTarget::Window(id) => match windows.get(*id) {
+                Some(window) => (
+                    window.cursor_position(),
+                    Vec2::new(window.width(), window.height()),
+                    window.scale_factor() as f32,
+                ),
+                None => continue,
+            },
+            // Images have no cursor of their own
+            RenderTarget::Image(handle) => match images.get(handle) {
+                Some(image) => (None, image.size(), 1.),
                 None => continue,
             },
-            _ => continue,
         };
-        let cursor_position = match viewport_to_world(camera, cam_transform, window) {
-            Some(pos) => pos,
-            None => continue,
+
+        // The cursor and all active touches, which only exist for the primary window
+        let mut screen_pointers: Vec<Vec2> =
+            cursor.position.or(window_cursor).into_iter().collect();
+        if matches!(camera.target, RenderTarget::Window(id) if id == WindowId::primary()) {
+            // Touches start at the top left corner
+            screen_pointers.extend(
+                touches
+                    .iter()
+                    .map(|touch| Vec2::new(touch.position().x, target_size.y - touch.position().y)),
+            );
+        }
+        let pointers: Vec<Vec2> = screen_pointers
+            .into_iter()
+            .filter_map(|pos| {
+                cursor_to_world(camera, cam_transform, pos, target_size, scale_factor)
+            })
+            .collect();
+        if pointers.is_empty() {
+            continue;
+        }
+        let unit = if mode.screen_space {
+            match world_units_per_pixel(camera, cam_transform, target_size, scale_factor) {
+                Some(unit) => unit,
+                None => continue,
+            }
+        } else {
+            1.
+        };
+        let contains = |selectable: &Selectable, transform: &GlobalTransform, pointer: Vec2| {
+            selectable
+                .shape
+                .contains_scaled(&selectable.placement(transform), pointer, unit)
         };
 
-        // Calculationg the distance and checking for overlap does not trigger change detection
-        for (mut selectable, transform) in sinks.iter_mut() {
-            let dist = transform
-                .translation()
-                .truncate()
-                .distance_squared(cursor_position);
-            let radius_sq = selectable.selection_radius * selectable.selection_radius;
-            if dist <= radius_sq && !selectable.is_selected {
-                // this triggers change detection
-                selectable.as_mut().is_selected = true;
+        let hit = |entity: Entity, pointer: Vec2| {
+            let (_, selectable, transform, layer) = sinks.get(entity).ok()?;
+            if !selectable.enabled
+                || !layers_match(source_layer, layer)
+                || !contains(selectable, transform, pointer)
+            {
+                return None;
+            }
+            Some(selectable.placement(transform).translation())
+        };
+        let closest = |pointer: Vec2| {
+            closest_hit(
+                grid.candidates(pointer, unit)
+                    .into_iter()
+                    .filter_map(|entity| {
+                        let center = hit(entity, pointer)?;
+                        let distance = center.truncate().distance_squared(pointer);
+                        Some((entity, distance, center.z))
+                    }),
+            )
+        };
+        let hits: HashSet<Entity> = if mode.exclusive {
+            // The closest selectable for every pointer
+            pointers
+                .iter()
+                .filter_map(|&pointer| closest(pointer))
+                .collect()
+        } else {
+            pointers
+                .iter()
+                .flat_map(|&pointer| {
+                    grid.candidates(pointer, unit)
+                        .into_iter()
+                        .filter(move |&entity| hit(entity, pointer).is_some())
+                })
+                .collect()
+        };
+
+        // Checking for overlap does not trigger change detection
+        for (entity, mut selectable, _, layer) in sinks.iter_mut() {
+            // Sinks of other layers are left to their own sources
+            if !layers_match(source_layer, layer) {
+                continue;
             }
-            if dist > radius_sq && selectable.is_selected {
+            let hit = selectable.enabled && hits.contains(&entity);
+            if hit != selectable.is_selected {
                 // this triggers change detection
-                selectable.as_mut().is_selected = false;
+                selectable.as_mut().is_selected = hit;
+                changed.send(SelectionChanged {
+                    entity,
+                    is_selected: hit,
+                });
             }
         }
     }
 }
 
+/// The world distance covered by one logical pixel in the center of the viewport of `camera`.
+fn world_units_per_pixel(
+    camera: &Camera,
+    cam_transform: &GlobalTransform,
+    target_size: Vec2,
+    scale_factor: f32,
+) -> Option<f32> {
+    let (viewport_min, viewport_size) = viewport_rect(camera, target_size * scale_factor);
+    let center = (viewport_min + viewport_size / 2.) / scale_factor;
+    let a = cursor_to_world(camera, cam_transform, center, target_size, scale_factor)?;
+    let b = cursor_to_world(
+        camera,
+        cam_transform,
+        center + Vec2::X,
+        target_size,
+        scale_factor,
+    )?;
+    Some(a.distance(b))
+}
+
+/// The entity of the hit `(entity, distance_squared, z)` closest to the cursor. Ties are won
+/// by the larger z.
+fn closest_hit(hits: impl Iterator<Item = (Entity, f32, f32)>) -> Option<Entity> {
+    let cmp = |a: &f32, b: &f32| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal);
+    hits.min_by(|(_, dist_a, z_a), (_, dist_b, z_b)| cmp(dist_a, dist_b).then(cmp(z_b, z_a)))
+        .map(|(entity, _, _)| entity)
+}
+
 /// This system updates the set of selected [`Selectable`]. It also sets the visibility of the indicator
-/// and if applicable its position as well. If multiple [`Selectable`] are selected, the position is choosen
-/// arbitrary.
+/// and if applicable its position as well. If multiple [`Selectable`] are selected, the indicator is
+/// placed at their centroid.
 fn update_selector(
     mut indicator: Query<(&mut Visibility, &mut Transform, &mut SelectionIndicator)>,
-    triggers: Query<(Entity, &GlobalTransform, &Selectable), Changed<Selectable>>,
+    triggers: Query<(Entity, &Selectable), Changed<Selectable>>,
+    placements: Query<(&GlobalTransform, &Selectable)>,
     entities: Query<Entity>,
 ) {
     // Early return if there is no indicator or it hasn't been spawned yet
@@ -127,14 +759,10 @@ fn update_selector(
         Err(_) => return,
     };
 
-    for (eid, trigger_transf, sel) in triggers.iter() {
+    for (eid, sel) in triggers.iter() {
         if sel.is_selected {
             // Just added
             indic.selected_triggers.insert(eid);
-            transf.translation = trigger_transf
-                .translation()
-                .truncate()
-                .extend(transf.translation.z);
         } else {
             // Just removed
             indic.selected_triggers.remove(&eid);
@@ -152,8 +780,457 @@ fn update_selector(
         indic.selected_triggers.remove(&eid);
     }
 
+    let positions: Vec<Vec2> = placements
+        .iter_many(indic.selected_triggers.iter())
+        .map(|(trigger_transf, sel)| sel.placement(trigger_transf).translation().truncate())
+        .collect();
+    if !positions.is_empty() {
+        let centroid = positions.iter().sum::<Vec2>() / positions.len() as f32;
+        // only update when changed
+        if transf.translation.truncate() != centroid {
+            transf.translation = centroid.extend(transf.translation.z);
+        }
+    }
+
     // only update when changed
     if visi.is_visible != !indic.selected_triggers.is_empty() {
         visi.is_visible = !indic.selected_triggers.is_empty();
     }
 }
+
+#[test]
+fn test_flipped_selection_shape() {
+    // Flipped vertically like a triangle pointing down
+    let transform = GlobalTransform::from(Transform {
+        translation: Vec3::new(10., 0., 5.),
+        scale: Vec3::new(1., -1., 1.),
+        ..Default::default()
+    });
+    // Only extends upwards in local space, so downwards in the world
+    let triangle = SelectionShape::Polygon {
+        corners: vec![Vec2::ZERO, Vec2::new(10., 0.), Vec2::new(5., 8.)],
+    };
+    assert!(triangle.contains(&transform, Vec2::new(15., -4.)));
+    assert!(!triangle.contains(&transform, Vec2::new(15., 4.)));
+
+    let aabb = SelectionShape::Aabb {
+        min: Vec2::new(0., 0.),
+        max: Vec2::new(4., 2.),
+    };
+    assert!(aabb.contains(&transform, Vec2::new(12., -1.)));
+    assert!(!aabb.contains(&transform, Vec2::new(12., 1.)));
+
+    // Circles ignore the flip
+    let circle = SelectionShape::Circle { radius: 2. };
+    assert!(circle.contains(&transform, Vec2::new(10., 1.5)));
+    assert!(circle.contains(&transform, Vec2::new(10., -1.5)));
+}
+
+#[test]
+fn test_rotated_rect() {
+    // A quarter turn counter clockwise
+    let transform = GlobalTransform::from(Transform {
+        translation: Vec3::new(10., 0., 5.),
+        rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+        scale: Vec3::splat(3.),
+    });
+    let rect = Selectable::rect(Vec2::new(4., 1.)).shape;
+    // The long side now points up, regardless of the scale
+    assert!(rect.contains(&transform, Vec2::new(10.5, 3.5)));
+    assert!(!rect.contains(&transform, Vec2::new(13.5, 0.5)));
+    assert!(!rect.contains(&transform, Vec2::new(10., 4.5)));
+}
+
+#[test]
+fn test_selection_offset() {
+    // Flipped vertically like a triangle pointing down
+    let transform = GlobalTransform::from(Transform {
+        translation: Vec3::new(10., 0., 5.),
+        scale: Vec3::new(1., -1., 1.),
+        ..Default::default()
+    });
+    let selectable = Selectable::new(1.).with_offset(Vec2::new(0., 5.));
+    assert_eq!(
+        selectable.placement(&transform).translation(),
+        Vec3::new(10., -5., 5.)
+    );
+    assert!(selectable.contains(&transform, Vec2::new(10., -5.5)));
+    assert!(!selectable.contains(&transform, Vec2::new(10., 5.)));
+    assert!(!selectable.contains(&transform, Vec2::new(10., 0.)));
+}
+
+#[test]
+fn test_closest_hit() {
+    let [a, b, c] = [0, 1, 2].map(Entity::from_raw);
+    assert_eq!(closest_hit(std::iter::empty()), None);
+    assert_eq!(
+        closest_hit([(a, 4., 0.), (b, 1., 0.), (c, 9., 10.)].into_iter()),
+        Some(b)
+    );
+    // The topmost one wins ties
+    assert_eq!(
+        closest_hit([(a, 1., 0.), (b, 1., 5.), (c, 1., 2.)].into_iter()),
+        Some(b)
+    );
+}
+
+/// A world with the resources [`selection_system`] needs and a [`SelectionSource`] camera,
+/// which renders to an image of `width` x `height` pixels with the cursor at `cursor`.
+#[cfg(test)]
+fn image_target_world(
+    mode: SelectionMode,
+    width: u32,
+    height: u32,
+    cursor: Vec2,
+) -> (World, Entity) {
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+
+    let mut world = World::new();
+    world.insert_resource(Windows::default());
+    world.insert_resource(Touches::default());
+    world.insert_resource(Events::<SelectionChanged>::default());
+    world.insert_resource(mode);
+    world.insert_resource(PointerBlocked::default());
+    world.insert_resource(SelectionCursor {
+        position: Some(cursor),
+    });
+    let mut images = Assets::<Image>::default();
+    let image = images.add(Image::new_fill(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+    world.insert_resource(images);
+
+    let mut camera = Camera::default();
+    camera.target = RenderTarget::Image(image);
+    let camera = world
+        .spawn()
+        .insert(camera)
+        .insert(GlobalTransform::default())
+        .insert(SelectionSource)
+        .id();
+    (world, camera)
+}
+
+#[test]
+fn test_image_target() {
+    let (mut world, _) = image_target_world(SelectionMode::default(), 100, 50, Vec2::new(50., 25.));
+    // The cursor is in the center of the image, so at the camera position
+    let center = world
+        .spawn()
+        .insert(Selectable::new(0.1))
+        .insert(GlobalTransform::default())
+        .id();
+    let off_center = world
+        .spawn()
+        .insert(Selectable::new(0.1))
+        .insert(GlobalTransform::from_xyz(0.5, 0., 0.))
+        .id();
+    let mut selectable = Selectable::new(0.1);
+    selectable.set_enabled(false);
+    selectable.is_selected = true;
+    let disabled = world
+        .spawn()
+        .insert(selectable)
+        .insert(GlobalTransform::default())
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(selection_system);
+    stage.run(&mut world);
+    assert!(world.get::<Selectable>(center).unwrap().is_selected);
+    assert!(!world.get::<Selectable>(off_center).unwrap().is_selected);
+    assert!(!world.get::<Selectable>(disabled).unwrap().is_selected);
+
+    world
+        .get_mut::<Selectable>(disabled)
+        .unwrap()
+        .set_enabled(true);
+    stage.run(&mut world);
+    assert!(world.get::<Selectable>(disabled).unwrap().is_selected);
+}
+
+#[test]
+fn test_screen_space() {
+    let mode = SelectionMode {
+        screen_space: true,
+        ..Default::default()
+    };
+    let (mut world, camera) = image_target_world(mode, 100, 50, Vec2::new(50., 25.));
+    // 100 pixels span the 2 world units of the view, so 3 pixels are 0.06 world units
+    let selectable = world
+        .spawn()
+        .insert(Selectable::new(3.))
+        .insert(GlobalTransform::from_xyz(0.1, 0., 0.))
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(selection_system);
+    stage.run(&mut world);
+    assert!(!world.get::<Selectable>(selectable).unwrap().is_selected);
+
+    // Zooming out doubles the world size of a pixel
+    *world.get_mut::<GlobalTransform>(camera).unwrap() =
+        GlobalTransform::from_scale(Vec3::splat(2.));
+    stage.run(&mut world);
+    assert!(world.get::<Selectable>(selectable).unwrap().is_selected);
+}
+
+#[test]
+fn test_selection_layers() {
+    let (mut world, camera) = image_target_world(SelectionMode::default(), 2, 2, Vec2::new(1., 1.));
+    world.entity_mut(camera).insert(SelectionLayer(0b01));
+    let mut spawn_sink = |layer: Option<SelectionLayer>| {
+        let mut sink = world.spawn();
+        sink.insert(Selectable::new(1.))
+            .insert(GlobalTransform::default());
+        if let Some(layer) = layer {
+            sink.insert(layer);
+        }
+        sink.id()
+    };
+    let other = spawn_sink(Some(SelectionLayer(0b10)));
+    let shared = spawn_sink(Some(SelectionLayer(0b11)));
+    let any = spawn_sink(None);
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(selection_system);
+    stage.run(&mut world);
+    assert!(!world.get::<Selectable>(other).unwrap().is_selected);
+    assert!(world.get::<Selectable>(shared).unwrap().is_selected);
+    assert!(world.get::<Selectable>(any).unwrap().is_selected);
+}
+
+#[test]
+fn test_selection_grid() {
+    let mut sinks = vec![
+        (Selectable::new(1.), GlobalTransform::default()),
+        // Scaled up to an extent of 4
+        (
+            Selectable::with_shape(SelectionShape::Aabb {
+                min: Vec2::splat(-1.),
+                max: Vec2::ONE,
+            }),
+            Transform::from_xyz(-6., 0., 0.)
+                .with_scale(Vec3::new(4., 0.1, 1.))
+                .into(),
+        ),
+    ];
+    // Far away in cells of their own
+    for i in 1..=10 {
+        sinks.push((
+            Selectable::new(1.),
+            GlobalTransform::from_xyz(20. * i as f32, 0., 0.),
+        ));
+    }
+
+    let mut grid = SelectionGrid::default();
+    grid.rebuild(
+        sinks
+            .iter()
+            .enumerate()
+            .map(|(i, (selectable, transform))| {
+                (Entity::from_raw(i as u32), selectable, transform)
+            }),
+    );
+    assert!(grid.cell_size >= 4.);
+    let candidates = grid.candidates(Vec2::new(-2.5, 0.), 1.);
+    assert!(candidates.contains(&Entity::from_raw(0)));
+    assert!(candidates.contains(&Entity::from_raw(1)));
+    assert!(!candidates.contains(&Entity::from_raw(2)));
+
+    // Screen space shapes grow with the size of a pixel
+    assert!(grid
+        .candidates(Vec2::ZERO, 20.)
+        .contains(&Entity::from_raw(2)));
+}
+
+#[test]
+fn test_selectables_moved() {
+    let (mut world, _) = image_target_world(SelectionMode::default(), 100, 50, Vec2::new(50., 25.));
+    // Enough cells that only the ones around the cursor are tested
+    for x in 0..20 {
+        world
+            .spawn()
+            .insert(Selectable::new(0.1))
+            .insert(GlobalTransform::from_xyz(10. + 2. * x as f32, 0., 0.));
+    }
+    let mover = world
+        .spawn()
+        .insert(Selectable::with_shape(SelectionShape::Polygon {
+            corners: vec![Vec2::new(-1., -1.), Vec2::new(1., -1.), Vec2::new(0., 1.)],
+        }))
+        .insert(GlobalTransform::from_xyz(5., 0., 0.))
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(selection_system);
+    stage.run(&mut world);
+    assert!(!world.get::<Selectable>(mover).unwrap().is_selected);
+
+    // Found at its new place right away
+    *world.get_mut::<GlobalTransform>(mover).unwrap() = GlobalTransform::default();
+    stage.run(&mut world);
+    assert!(world.get::<Selectable>(mover).unwrap().is_selected);
+
+    // Away again
+    *world.get_mut::<GlobalTransform>(mover).unwrap() = GlobalTransform::from_xyz(30., 0., 0.);
+    stage.run(&mut world);
+    assert!(!world.get::<Selectable>(mover).unwrap().is_selected);
+
+    // Growing larger than the cells reaches the cursor from afar
+    *world.get_mut::<GlobalTransform>(mover).unwrap() = Transform::from_xyz(30., 0., 0.)
+        .with_scale(Vec3::splat(100.))
+        .into();
+    stage.run(&mut world);
+    assert!(world.get::<Selectable>(mover).unwrap().is_selected);
+}
+
+#[test]
+fn test_viewport_to_world() {
+    use bevy::render::camera::Viewport;
+
+    // The right half of a 200x100 window at a scale factor of 2
+    let mut camera = Camera::default();
+    camera.viewport = Some(Viewport {
+        physical_position: UVec2::new(200, 0),
+        physical_size: UVec2::new(200, 200),
+        ..Default::default()
+    });
+    let transform = GlobalTransform::from_xyz(10., 20., 0.);
+    let size = Vec2::new(200., 100.);
+
+    assert_eq!(
+        cursor_to_world(&camera, &transform, Vec2::new(150., 50.), size, 2.),
+        Some(Vec2::new(10., 20.))
+    );
+    assert_eq!(
+        cursor_to_world(&camera, &transform, Vec2::new(100., 0.), size, 2.),
+        Some(Vec2::new(9., 19.))
+    );
+    // In the other pane
+    assert_eq!(
+        cursor_to_world(&camera, &transform, Vec2::new(50., 50.), size, 2.),
+        None
+    );
+}
+
+#[test]
+fn test_perspective_ray() {
+    use bevy::render::camera::CameraProjection;
+
+    // Looking down on the z = 0 plane from above
+    let projection = PerspectiveProjection::default().get_projection_matrix();
+    let ndc_to_world = Transform::from_xyz(3., 4., 10.).compute_matrix() * projection.inverse();
+
+    let ray = ray_from_ndc(ndc_to_world, Vec2::ZERO).unwrap();
+    assert!((ray.direction - Vec3::NEG_Z).length() < 1e-4);
+    let hit = ray.intersect_z_plane(0.).unwrap();
+    assert!((hit - Vec3::new(3., 4., 0.)).length() < 1e-3);
+
+    // Off-center rays diverge, so they hit the plane further out than they start
+    let corner = ray_from_ndc(ndc_to_world, Vec2::ONE).unwrap();
+    let hit = corner.intersect_z_plane(0.).unwrap();
+    assert!(hit.x > corner.origin.x && hit.y > corner.origin.y);
+    // The plane above the camera is never hit
+    assert_eq!(corner.intersect_z_plane(20.), None);
+}
+
+#[test]
+fn test_pointer_blocked() {
+    let mut world = World::new();
+    world.insert_resource(Windows::default());
+    world.insert_resource(Assets::<Image>::default());
+    world.insert_resource(Touches::default());
+    world.insert_resource(Events::<SelectionChanged>::default());
+    world.insert_resource(SelectionMode::default());
+    world.insert_resource(SelectionCursor::default());
+    world.insert_resource(PointerBlocked(true));
+
+    let mut selectable = Selectable::new(1.);
+    selectable.is_selected = true;
+    let entity = world
+        .spawn()
+        .insert(selectable)
+        .insert(GlobalTransform::default())
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(selection_system);
+    stage.run(&mut world);
+    assert!(!world.get::<Selectable>(entity).unwrap().is_selected);
+
+    let events = world.resource::<Events<SelectionChanged>>();
+    let mut reader = events.get_reader();
+    let changes: Vec<&SelectionChanged> = reader.iter(events).collect();
+    assert_eq!(
+        changes,
+        vec![&SelectionChanged {
+            entity,
+            is_selected: false
+        }]
+    );
+}
+
+#[test]
+fn test_indicator_centroid() {
+    let mut world = World::new();
+    let indicator = world
+        .spawn()
+        .insert(Visibility::default())
+        .insert(Transform::from_xyz(0., 0., 7.))
+        .insert(SelectionIndicator::new())
+        .id();
+    let mut spawn_selected = |x: f32| {
+        let mut selectable = Selectable::new(1.);
+        selectable.is_selected = true;
+        world
+            .spawn()
+            .insert(selectable)
+            .insert(GlobalTransform::from_xyz(x, 10., 0.))
+            .id()
+    };
+    let left = spawn_selected(0.);
+    spawn_selected(10.);
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(update_selector);
+    stage.run(&mut world);
+    assert_eq!(
+        world.get::<Transform>(indicator).unwrap().translation,
+        Vec3::new(5., 10., 7.)
+    );
+
+    // Despawned triggers don't count
+    world.despawn(left);
+    stage.run(&mut world);
+    assert_eq!(
+        world.get::<Transform>(indicator).unwrap().translation,
+        Vec3::new(10., 10., 7.)
+    );
+}
+
+#[test]
+fn test_world_to_cursor() {
+    let camera = Camera::default();
+    let transform = GlobalTransform::from_xyz(10., 20., 0.);
+    let size = Vec2::new(200., 100.);
+
+    let cursor = Vec2::new(150., 30.);
+    let world = cursor_to_world(&camera, &transform, cursor, size, 2.).unwrap();
+    let back = world_to_cursor(&camera, &transform, world, size, 2.).unwrap();
+    assert!(back.distance(cursor) < 1e-3);
+
+    // Behind the camera
+    let transform = GlobalTransform::from_xyz(10., 20., -5.);
+    assert_eq!(
+        world_to_cursor(&camera, &transform, Vec2::ZERO, size, 2.),
+        None
+    );
+}
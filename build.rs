@@ -0,0 +1,46 @@
+//! Embeds the built-in levels named in `levels/manifest.json`, in their order. Adding a level
+//! only needs its file and an entry in the manifest. Their file names are listed too, so the
+//! game doesn't delete them.
+
+use std::{env, fs, path::Path};
+
+const MANIFEST: &str = "levels/manifest.json";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", MANIFEST);
+
+    let root = env::var("CARGO_MANIFEST_DIR").expect("Cargo should set CARGO_MANIFEST_DIR");
+    let levels_dir = Path::new(&root).join("levels");
+    let manifest = fs::read_to_string(Path::new(&root).join(MANIFEST))
+        .expect("The level manifest should exist");
+    let names: Vec<String> =
+        serde_json::from_str(&manifest).expect("The level manifest should be a list of file names");
+    assert!(
+        names.len() >= 2,
+        "The level manifest needs at least the empty and the end level"
+    );
+
+    let mut code = String::from("&[\n");
+    let mut files = String::from("&[\n");
+    for name in names {
+        let path = levels_dir.join(&name);
+        assert!(
+            path.is_file(),
+            "Level {} from the manifest is missing",
+            name
+        );
+        println!("cargo:rerun-if-changed={}", path.display());
+        code += &format!("    include_str!({:?}),\n", path.display().to_string());
+        let stem = Path::new(&name)
+            .file_stem()
+            .expect("Level file names shouldn't be empty");
+        files += &format!("    {:?},\n", stem.to_string_lossy());
+    }
+    code += "]\n";
+    files += "]\n";
+
+    let out_dir = env::var("OUT_DIR").expect("Cargo should set OUT_DIR");
+    fs::write(Path::new(&out_dir).join("levels.rs"), code).expect("Failed to write the level list");
+    fs::write(Path::new(&out_dir).join("level_files.rs"), files)
+        .expect("Failed to write the level file list");
+}
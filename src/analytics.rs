@@ -0,0 +1,208 @@
+//! Opt-in log of gameplay events for balancing levels. Press `L` to start logging, press it
+//! again to stop and flush the log. Nothing leaves the machine, the log is only printed to the
+//! console or written to the local storage as `gameplay-log`.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    level::{LevelCompleted, LevelId, LevelInfo},
+    rotation::{MergeEvent, MoveBlocked, TileMoved},
+    savegame::{write_json, LevelLoaded},
+    GameState,
+};
+
+pub(crate) const LOG_NAME: &str = "gameplay-log";
+
+pub struct MagnateAnalyticsPlugin;
+
+impl Plugin for MagnateAnalyticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameplayLog>().add_system_set(
+            SystemSet::on_update(GameState::Next)
+                .with_system(toggle_logging.before(log_gameplay))
+                .with_system(log_gameplay),
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum GameplayEvent {
+    /// A rotation moving this many triangles
+    Moved {
+        triangles: usize,
+    },
+    Blocked,
+    Merged,
+    /// The level was (re)loaded
+    Reset {
+        name: String,
+    },
+    Won,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogEntry {
+    /// Seconds since startup
+    pub time: f64,
+    pub level: LevelId,
+    pub event: GameplayEvent,
+}
+
+/// Ring buffer of the latest gameplay events, only filled while enabled.
+pub struct GameplayLog {
+    pub enabled: bool,
+    /// The number of entries to keep
+    pub capacity: usize,
+    /// Print the log to the console when flushing instead of storing it
+    pub to_console: bool,
+    entries: VecDeque<LogEntry>,
+}
+
+impl Default for GameplayLog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: 1000,
+            to_console: false,
+            entries: VecDeque::new(),
+        }
+    }
+}
+
+impl GameplayLog {
+    pub fn push(&mut self, entry: LogEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Writes all entries to the sink and empties the log.
+    pub fn flush(&mut self) {
+        let entries: Vec<LogEntry> = self.entries.drain(..).collect();
+        match serde_json::to_string(&entries) {
+            Ok(data) if self.to_console => info!("Gameplay log: {}", data),
+            Ok(data) => write_json(data, LOG_NAME),
+            Err(e) => warn!("Failed to serialize gameplay log: {:?}", e),
+        }
+    }
+}
+
+fn toggle_logging(keys: Res<Input<KeyCode>>, mut log: ResMut<GameplayLog>) {
+    if !keys.just_pressed(KeyCode::L) {
+        return;
+    }
+    log.enabled = !log.enabled;
+    info!("Gameplay logging: {}", log.enabled);
+    if !log.enabled {
+        log.flush();
+    }
+}
+
+/// Appends the events of this frame in the order resets, moves, blocks, merges and wins.
+fn log_gameplay(
+    mut loaded: EventReader<LevelLoaded>,
+    mut moved: EventReader<TileMoved>,
+    mut blocked: EventReader<MoveBlocked>,
+    mut merged: EventReader<MergeEvent>,
+    mut won: EventReader<LevelCompleted>,
+    level: Res<LevelInfo>,
+    time: Res<Time>,
+    mut log: ResMut<GameplayLog>,
+) {
+    let mut events: Vec<(LevelId, GameplayEvent)> = Vec::new();
+    events.extend(loaded.iter().map(|loaded| {
+        (
+            level.current.clone(),
+            GameplayEvent::Reset {
+                name: loaded.name.clone(),
+            },
+        )
+    }));
+    let triangles = moved.iter().count();
+    if triangles > 0 {
+        events.push((level.current.clone(), GameplayEvent::Moved { triangles }));
+    }
+    events.extend(
+        blocked
+            .iter()
+            .map(|_| (level.current.clone(), GameplayEvent::Blocked)),
+    );
+    events.extend(
+        merged
+            .iter()
+            .map(|_| (level.current.clone(), GameplayEvent::Merged)),
+    );
+    events.extend(
+        won.iter()
+            .map(|won| (won.level.clone(), GameplayEvent::Won)),
+    );
+
+    if !log.enabled {
+        return;
+    }
+    let now = time.seconds_since_startup();
+    for (level, event) in events {
+        log.push(LogEntry {
+            time: now,
+            level,
+            event,
+        });
+    }
+}
+
+#[test]
+fn test_gameplay_log() {
+    use crate::tilemap::{TriangleOrient, VertexCoord};
+
+    let mut world = World::new();
+    world.insert_resource(Events::<LevelLoaded>::default());
+    world.insert_resource(Events::<TileMoved>::default());
+    world.insert_resource(Events::<MoveBlocked>::default());
+    world.insert_resource(Events::<MergeEvent>::default());
+    world.insert_resource(Events::<LevelCompleted>::default());
+    world.insert_resource(LevelInfo::default());
+    world.insert_resource(Time::default());
+    world.insert_resource(GameplayLog {
+        enabled: true,
+        ..Default::default()
+    });
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(log_gameplay);
+
+    let tile = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    for i in 0..2 {
+        world.resource_mut::<Events<TileMoved>>().send(TileMoved {
+            entity: Entity::from_raw(i),
+            from: tile,
+            to: tile,
+        });
+    }
+    stage.run(&mut world);
+    world
+        .resource_mut::<Events<LevelCompleted>>()
+        .send(LevelCompleted {
+            level: LevelId::Builtin(1),
+            moves: 0,
+            par_reached: false,
+        });
+    stage.run(&mut world);
+
+    let events: Vec<GameplayEvent> = world
+        .resource::<GameplayLog>()
+        .entries
+        .iter()
+        .map(|entry| entry.event.clone())
+        .collect();
+    assert_eq!(
+        events,
+        vec![GameplayEvent::Moved { triangles: 2 }, GameplayEvent::Won]
+    );
+}
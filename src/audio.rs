@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use bevy_asset_loader::prelude::*;
+
+use crate::{
+    level::LevelCompleted,
+    rotation::{MergeEvent, MoveBlocked, TileMoved},
+    GameState,
+};
+
+/// Sound effects for rotations, merges and wins.
+/// Press `V` to mute or unmute them.
+pub struct MagnateAudioPlugin;
+
+impl Plugin for MagnateAudioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Volume>().add_system_set(
+            SystemSet::on_update(GameState::Next)
+                .with_system(toggle_mute)
+                .with_system(play_sounds.after(toggle_mute)),
+        );
+    }
+}
+
+#[derive(AssetCollection, Default)]
+pub struct AudioAssets {
+    #[asset(path = "audio/rotate.wav")]
+    rotate: Handle<AudioSource>,
+    #[asset(path = "audio/merge.wav")]
+    merge: Handle<AudioSource>,
+    #[asset(path = "audio/blocked.wav")]
+    blocked: Handle<AudioSource>,
+    #[asset(path = "audio/win.wav")]
+    win: Handle<AudioSource>,
+}
+
+/// Resource controlling how loud the sound effects are played.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Volume {
+    /// Between `0` for silence and `1` for the full volume of the sounds
+    pub level: f32,
+    pub muted: bool,
+}
+
+impl Default for Volume {
+    fn default() -> Self {
+        Self {
+            level: 0.5,
+            muted: false,
+        }
+    }
+}
+
+impl Volume {
+    /// The volume to play sounds with, or `None` if nothing should be played.
+    pub fn effective(&self) -> Option<f32> {
+        let level = self.level.clamp(0., 1.);
+        if self.muted || level <= 0. {
+            None
+        } else {
+            Some(level)
+        }
+    }
+}
+
+fn toggle_mute(keys: Res<Input<KeyCode>>, mut volume: ResMut<Volume>) {
+    // `Left Control` + `V` pastes a level
+    if keys.just_pressed(KeyCode::V) && !keys.pressed(KeyCode::LControl) {
+        volume.muted = !volume.muted;
+        info!("Sound muted: {}", volume.muted);
+    }
+}
+
+/// Plays every sound at most once per frame, e.g. a single click for all triangles of a rotation.
+fn play_sounds(
+    audio: Res<Audio>,
+    sounds: Res<AudioAssets>,
+    volume: Res<Volume>,
+    mut moved: EventReader<TileMoved>,
+    mut blocked: EventReader<MoveBlocked>,
+    mut merged: EventReader<MergeEvent>,
+    mut completed: EventReader<LevelCompleted>,
+) {
+    // Consume the events in any case
+    let cues = [
+        (moved.iter().count() > 0, &sounds.rotate),
+        (blocked.iter().count() > 0, &sounds.blocked),
+        (merged.iter().count() > 0, &sounds.merge),
+        (completed.iter().count() > 0, &sounds.win),
+    ];
+    let level = match volume.effective() {
+        Some(level) => level,
+        None => return,
+    };
+    for (_, sound) in cues.into_iter().filter(|(triggered, _)| *triggered) {
+        audio.play_with_settings(sound.clone(), PlaybackSettings::ONCE.with_volume(level));
+    }
+}
+
+#[test]
+fn test_volume() {
+    assert_eq!(Volume::default().effective(), Some(0.5));
+    let muted = Volume {
+        muted: true,
+        ..Default::default()
+    };
+    assert_eq!(muted.effective(), None);
+    let silent = Volume {
+        level: 0.,
+        muted: false,
+    };
+    assert_eq!(silent.effective(), None);
+    let loud = Volume {
+        level: 3.,
+        muted: false,
+    };
+    assert_eq!(loud.effective(), Some(1.));
+}
+
+#[test]
+fn test_toggle_mute() {
+    let mut world = World::new();
+    world.insert_resource(Input::<KeyCode>::default());
+    world.insert_resource(Volume::default());
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(toggle_mute);
+    world.resource_mut::<Input<KeyCode>>().press(KeyCode::V);
+    stage.run(&mut world);
+    assert!(world.resource::<Volume>().muted);
+
+    let mut keys = world.resource_mut::<Input<KeyCode>>();
+    keys.release(KeyCode::V);
+    keys.clear();
+    keys.press(KeyCode::V);
+    stage.run(&mut world);
+    assert!(!world.resource::<Volume>().muted);
+}
@@ -0,0 +1,237 @@
+//! The triangle grid behind the level. It's drawn as lines covering the camera view, so it stays
+//! crisp at any zoom and window size. Press `G` to switch back to the static background sprite.
+
+use bevy::{
+    prelude::*,
+    render::{mesh::Indices, render_resource::PrimitiveTopology},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+use bevy_point_selection::SelectionSource;
+
+use crate::{
+    level::build_clump_mesh,
+    settings::Settings,
+    tilemap::{FromWorldPosition, GridConfig, TileCoord, VertexCoord},
+    GameState,
+};
+
+const GRID_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+/// Behind everything, like the background sprite
+const GRID_Z: f32 = 100.;
+
+pub struct MagnateBackgroundPlugin;
+
+impl Plugin for MagnateBackgroundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(GameState::Next).with_system(spawn_grid))
+            .add_system_set(
+                SystemSet::on_update(GameState::Next)
+                    .with_system(update_grid)
+                    .with_system(update_border)
+                    .with_system(switch_background),
+            );
+    }
+}
+
+/// The procedural grid lines
+#[derive(Component, Default, Debug, Clone)]
+pub struct BackgroundGrid;
+
+/// The immovable triangles around the playfield, baked into one mesh
+#[derive(Component, Default, Debug, Clone)]
+pub struct BorderMesh {
+    pub tiles: Vec<TileCoord>,
+}
+
+/// The static 1280x720 background image, only shown if [`Settings::procedural_grid`] is off
+#[derive(Component, Default, Debug, Clone)]
+pub struct BackgroundSprite;
+
+/// The grid lines between the vertices from `min` to `max`. The lines point along the three
+/// directions of the grid, i.e. along the x and y axis of the vertex coordinates and diagonally.
+fn grid_lines(min: VertexCoord, max: VertexCoord) -> Vec<(VertexCoord, VertexCoord)> {
+    let mut lines = Vec::new();
+    for y in min.y..=max.y {
+        lines.push((VertexCoord::new(min.x, y), VertexCoord::new(max.x, y)));
+    }
+    for x in min.x..=max.x {
+        lines.push((VertexCoord::new(x, min.y), VertexCoord::new(x, max.y)));
+    }
+    // Lines with a constant x + y
+    for sum in (min.x + min.y + 1)..(max.x + max.y) {
+        let start_x = min.x.max(sum - max.y);
+        let end_x = max.x.min(sum - min.y);
+        lines.push((
+            VertexCoord::new(start_x, sum - start_x),
+            VertexCoord::new(end_x, sum - end_x),
+        ));
+    }
+    lines
+}
+
+fn build_grid_mesh(min: VertexCoord, max: VertexCoord, grid: &GridConfig) -> Mesh {
+    let positions: Vec<[f32; 3]> = grid_lines(min, max)
+        .into_iter()
+        .flat_map(|(start, end)| [start, end])
+        .map(|v| grid.vertex_pos(v).extend(0.).into())
+        .collect();
+    let indices = (0..positions.len() as u32).collect();
+
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 0., 1.]; positions.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.]; positions.len()]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh
+}
+
+/// The vertices spanning a grid which covers the view from `min` to `max` in world space.
+fn grid_extent(min: Vec2, max: Vec2, grid: &GridConfig) -> (VertexCoord, VertexCoord) {
+    let corners = [min, Vec2::new(min.x, max.y), Vec2::new(max.x, min.y), max]
+        .map(|corner| TileCoord::from_world_pos_in(corner, grid).0);
+    let lower = corners.into_iter().reduce(VertexCoord::min).unwrap();
+    let upper = corners.into_iter().reduce(VertexCoord::max).unwrap();
+    // The tiles are found by their lower left corner, so the upper tiles need one more vertex
+    (lower - IVec2::ONE, upper + IVec2::splat(2))
+}
+
+fn spawn_grid(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    grid: Res<GridConfig>,
+) {
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes
+                .add(build_grid_mesh(VertexCoord::ZERO, VertexCoord::ZERO, &grid))
+                .into(),
+            material: materials.add(ColorMaterial::from(GRID_COLOR)),
+            transform: Transform::from_xyz(0., 0., GRID_Z),
+            ..Default::default()
+        })
+        .insert(BackgroundGrid)
+        .insert(Name::new("Background Grid"));
+}
+
+/// Regenerates the grid whenever the view moves onto vertices it doesn't cover yet.
+fn update_grid(
+    camera: Query<(&Transform, &OrthographicProjection), With<SelectionSource>>,
+    grids: Query<&Mesh2dHandle, With<BackgroundGrid>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    grid: Res<GridConfig>,
+    mut covered: Local<Option<(VertexCoord, VertexCoord)>>,
+) {
+    let (transf, projection) = match camera.get_single() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let center = transf.translation.truncate();
+    let min = center + Vec2::new(projection.left, projection.bottom) * projection.scale;
+    let max = center + Vec2::new(projection.right, projection.top) * projection.scale;
+    let extent = grid_extent(min, max, &grid);
+    if *covered == Some(extent) && !grid.is_changed() {
+        return;
+    }
+    *covered = Some(extent);
+
+    for handle in grids.iter() {
+        if let Some(mesh) = meshes.get_mut(&handle.0) {
+            *mesh = build_grid_mesh(extent.0, extent.1, &grid);
+        }
+    }
+}
+
+/// Rebakes the border when a level moves or scales the grid.
+fn update_border(
+    borders: Query<(&BorderMesh, &Mesh2dHandle)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    grid: Res<GridConfig>,
+) {
+    if !grid.is_changed() {
+        return;
+    }
+    for (border, handle) in borders.iter() {
+        if let Some(mesh) = meshes.get_mut(&handle.0) {
+            *mesh = build_clump_mesh(&border.tiles, &grid);
+        }
+    }
+}
+
+/// Shows either the grid or the background sprite, see [`Settings::procedural_grid`].
+fn switch_background(
+    settings: Res<Settings>,
+    mut grids: Query<&mut Visibility, (With<BackgroundGrid>, Without<BackgroundSprite>)>,
+    mut sprites: Query<&mut Visibility, With<BackgroundSprite>>,
+) {
+    for mut visibility in grids.iter_mut() {
+        if visibility.is_visible != settings.procedural_grid {
+            visibility.is_visible = settings.procedural_grid;
+        }
+    }
+    for mut visibility in sprites.iter_mut() {
+        if visibility.is_visible == settings.procedural_grid {
+            visibility.is_visible = !settings.procedural_grid;
+        }
+    }
+}
+
+#[test]
+fn test_grid_lines() {
+    let lines = grid_lines(VertexCoord::ZERO, VertexCoord::new(2, 1));
+    // 2 horizontal, 3 along y and the diagonals with x + y = 1 and 2
+    assert_eq!(lines.len(), 2 + 3 + 2);
+    assert!(lines.contains(&(VertexCoord::new(0, 1), VertexCoord::new(2, 1))));
+    assert!(lines.contains(&(VertexCoord::new(0, 1), VertexCoord::new(1, 0))));
+    assert!(lines.contains(&(VertexCoord::new(1, 1), VertexCoord::new(2, 0))));
+    for (start, end) in lines {
+        assert!(start.cmpge(VertexCoord::ZERO).all() && end.cmple(VertexCoord::new(2, 1)).all());
+    }
+
+    // The grid covers the whole view
+    let grid = GridConfig::default();
+    let (min, max) = grid_extent(Vec2::new(-640., -360.), Vec2::new(640., 360.), &grid);
+    for corner in [Vec2::new(-640., -360.), Vec2::new(640., 360.)] {
+        let tile = TileCoord::from_world_pos(corner).0;
+        assert!(tile.cmpgt(min).all() && (tile + IVec2::ONE).cmplt(max).all());
+    }
+}
+
+#[test]
+fn test_update_border() {
+    use crate::tilemap::{TileVertices, TriangleOrient};
+
+    let tile = (VertexCoord::new(2, 1), TriangleOrient::PointingUp);
+    let grid = GridConfig {
+        origin: Vec2::new(50., 20.),
+        side: 40.,
+    };
+    let mut world = World::new();
+    world.insert_resource(grid);
+    let mut meshes = Assets::<Mesh>::default();
+    let handle = meshes.add(build_clump_mesh(&[tile], &GridConfig::default()));
+    world.insert_resource(meshes);
+    world
+        .spawn()
+        .insert(BorderMesh { tiles: vec![tile] })
+        .insert(Mesh2dHandle(handle.clone()));
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(update_border);
+    stage.run(&mut world);
+
+    let meshes = world.resource::<Assets<Mesh>>();
+    let positions = match meshes
+        .get(&handle)
+        .unwrap()
+        .attribute(Mesh::ATTRIBUTE_POSITION)
+    {
+        Some(bevy::render::mesh::VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+        _ => panic!("The border mesh has no positions"),
+    };
+    // The left vertex of the triangle moved with the grid
+    let corner = tile.world_vertices_in(&grid)[0];
+    assert!(positions
+        .iter()
+        .any(|p| Vec2::new(p[0], p[1]).distance(corner) < 1e-3));
+}
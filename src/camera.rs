@@ -0,0 +1,588 @@
+use bevy::{
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+use bevy_point_selection::SelectionSource;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::RotationHint,
+    settings::Settings,
+    tilemap::{GridConfig, Immovable, RuneTile, TileVertices, TriangleTile},
+    GameState, InputSuppressed,
+};
+
+/// Limits of the projection scale when zooming, smaller is closer
+const MIN_ZOOM: f32 = 0.5;
+const MAX_ZOOM: f32 = 3.;
+/// Zoom change per line scrolled
+const ZOOM_STEP: f32 = 0.1;
+/// Pixels of precise scrolling, e.g. on touchpads, which count as one line
+const PIXELS_PER_LINE: f32 = 50.;
+/// Space between the level and the edge of the view in world units
+const FIT_MARGIN: f32 = 60.;
+
+/// Camera animations. Press `H` to take a closer look at the rotation hint.
+/// Drag with the middle mouse button to pan, scroll to zoom and press `Home` to reset the view.
+/// Other plugins shake the camera with [`CameraShake`] events.
+pub struct MagnateCameraPlugin;
+
+impl Plugin for MagnateCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CameraFocus>()
+            .add_event::<CameraSweep>()
+            .add_event::<CameraShake>()
+            .init_resource::<LevelIntro>()
+            .init_resource::<ShakeState>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Next)
+                    .with_system(focus_hint)
+                    .with_system(remove_shake_offset.before(camera_focus))
+                    .with_system(pan_zoom.after(remove_shake_offset).before(camera_focus))
+                    .with_system(camera_focus)
+                    .with_system(camera_shake.after(camera_focus)),
+            );
+    }
+}
+
+/// Request to move the camera to `target` and back again, optionally zooming to the projection
+/// scale `zoom` on the way. Each way takes `duration` seconds.
+#[derive(Debug, Clone)]
+pub struct CameraFocus {
+    pub target: Vec2,
+    pub zoom: Option<f32>,
+    pub duration: f32,
+}
+
+/// Intro of a level: the camera pans from `from` back to its resting view, optionally starting
+/// at the projection scale `zoom`. Player input is suppressed until the sweep ends or any key is
+/// pressed. Sent when a level defining an intro is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraSweep {
+    pub from: Vec2,
+    #[serde(default)]
+    pub zoom: Option<f32>,
+    pub duration: f32,
+}
+
+/// The intro of the current level, kept to save it again.
+#[derive(Debug, Clone, Default)]
+pub struct LevelIntro(pub Option<CameraSweep>);
+
+/// Request to shake the camera by up to `intensity` world units, decaying linearly to rest
+/// over `duration` seconds. Concurrent shakes add up.
+#[derive(Debug, Clone)]
+pub struct CameraShake {
+    pub intensity: f32,
+    pub duration: f32,
+}
+
+/// The running shakes with their elapsed time and the offset they applied to the camera.
+#[derive(Default)]
+struct ShakeState {
+    shakes: Vec<(CameraShake, f32)>,
+    offset: Vec2,
+}
+
+struct FocusAnimation {
+    focus: CameraFocus,
+    start: Vec2,
+    start_zoom: f32,
+    elapsed: f32,
+    /// Only the way back of an intro sweep, which can be skipped
+    intro: bool,
+}
+
+impl FocusAnimation {
+    /// Position and zoom of the camera at the current time.
+    fn sample(&self) -> (Vec2, f32) {
+        let t = self.elapsed / self.focus.duration.max(f32::EPSILON);
+        // There in the first half, back in the second half
+        let s = if t <= 1. { ease(t) } else { ease(2. - t) };
+
+        let zoom = self.focus.zoom.unwrap_or(self.start_zoom);
+        (
+            self.start.lerp(self.focus.target, s),
+            self.start_zoom + (zoom - self.start_zoom) * s,
+        )
+    }
+
+    fn is_finished(&self) -> bool {
+        self.elapsed >= 2. * self.focus.duration
+    }
+}
+
+/// Smoothstep easing from 0 to 1.
+pub fn ease(t: f32) -> f32 {
+    let t = t.clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+fn camera_focus(
+    mut requests: EventReader<CameraFocus>,
+    mut sweeps: EventReader<CameraSweep>,
+    mut animation: Local<Option<FocusAnimation>>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
+    mut suppressed: ResMut<InputSuppressed>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+) {
+    let (mut transf, mut projection) = match camera.get_single_mut() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+
+    // Start from the resting position if interrupted
+    let (start, start_zoom) = match &*animation {
+        Some(anim) => (anim.start, anim.start_zoom),
+        None => (transf.translation.truncate(), projection.scale),
+    };
+    if let Some(focus) = requests.iter().last() {
+        *animation = Some(FocusAnimation {
+            focus: focus.clone(),
+            start,
+            start_zoom,
+            elapsed: 0.,
+            intro: false,
+        });
+    }
+    if let Some(sweep) = sweeps.iter().last() {
+        // The second half of a focus on the sweep start
+        *animation = Some(FocusAnimation {
+            focus: CameraFocus {
+                target: sweep.from,
+                zoom: sweep.zoom,
+                duration: sweep.duration,
+            },
+            start,
+            start_zoom,
+            elapsed: sweep.duration,
+            intro: true,
+        });
+    }
+
+    if let Some(anim) = animation.as_mut() {
+        anim.elapsed += time.delta_seconds();
+        if anim.intro && keys.get_just_pressed().next().is_some() {
+            anim.elapsed = 2. * anim.focus.duration;
+        }
+        if anim.is_finished() {
+            anim.elapsed = 2. * anim.focus.duration;
+        }
+        let (pos, zoom) = anim.sample();
+        transf.translation = pos.extend(transf.translation.z);
+        projection.scale = zoom;
+
+        suppressed.intro = anim.intro && !anim.is_finished();
+        if anim.is_finished() {
+            *animation = None;
+        }
+    }
+}
+
+/// How far the camera moves when the cursor is dragged by `motion` pixels, such that the world
+/// stays under the cursor. Screen motion points down, the world up.
+fn pan_delta(motion: Vec2, projection: &OrthographicProjection, window_height: f32) -> Vec2 {
+    let units_per_pixel =
+        (projection.top - projection.bottom) * projection.scale / window_height.max(1.);
+    Vec2::new(-motion.x, motion.y) * units_per_pixel
+}
+
+/// The projection scale after scrolling `lines` up, which zooms in.
+fn zoomed(scale: f32, lines: f32) -> f32 {
+    (scale * (1. - ZOOM_STEP).powf(lines)).clamp(MIN_ZOOM, MAX_ZOOM)
+}
+
+/// Pans the camera while dragging with the middle mouse button and zooms with the scroll wheel.
+/// The selection works in world space, so it follows along.
+fn pan_zoom(
+    mouse_btn: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    suppressed: Res<InputSuppressed>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<SelectionSource>>,
+) {
+    let motion: Vec2 = motion.iter().map(|motion| motion.delta).sum();
+    let lines: f32 = wheel
+        .iter()
+        .map(|wheel| match wheel.unit {
+            MouseScrollUnit::Line => wheel.y,
+            MouseScrollUnit::Pixel => wheel.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    if suppressed.any() {
+        return;
+    }
+    let (mut transf, mut projection) = match camera.get_single_mut() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+
+    if keys.just_pressed(KeyCode::Home) {
+        transf.translation = Vec3::new(0., 0., transf.translation.z);
+        projection.scale = 1.;
+        return;
+    }
+    if mouse_btn.pressed(MouseButton::Middle) && motion != Vec2::ZERO {
+        let height = windows.get_primary().map_or(720., |window| window.height());
+        transf.translation += pan_delta(motion, &projection, height).extend(0.);
+    }
+    if lines != 0. {
+        projection.scale = zoomed(projection.scale, lines);
+    }
+}
+
+/// Center and projection scale of a view of `view_size` world units at scale `1` which shows
+/// everything between `min` and `max`. If that fits into the default view at the origin, it's
+/// kept, so the labels next to the playfield stay in place. The view is never zoomed in.
+fn fit_view(min: Vec2, max: Vec2, view_size: Vec2) -> (Vec2, f32) {
+    let (min, max) = (min - Vec2::splat(FIT_MARGIN), max + Vec2::splat(FIT_MARGIN));
+    let half_view = view_size / 2.;
+    if min.cmpge(-half_view).all() && max.cmple(half_view).all() {
+        return (Vec2::ZERO, 1.);
+    }
+    let scale = ((max - min) / view_size.max(Vec2::ONE)).max_element();
+    ((min + max) / 2., scale.clamp(1., MAX_ZOOM))
+}
+
+/// Moves and zooms the camera so that all tiles of the loaded level are visible, see
+/// [`fit_view`]. Does nothing for an empty level like level 0.
+pub fn fit_camera_to_level(world: &mut World) {
+    // Without the border, which are triangles without clump
+    let mut triangles =
+        world.query_filtered::<&TriangleTile, Or<(With<Parent>, With<Immovable>)>>();
+    let mut runes = world.query::<&RuneTile>();
+    let tiles: Vec<_> = triangles
+        .iter(world)
+        .map(|tile| tile.position)
+        .chain(runes.iter(world).map(|rune| rune.position))
+        .collect();
+    let grid = world
+        .get_resource::<GridConfig>()
+        .copied()
+        .unwrap_or_default();
+    let corners = tiles.iter().flat_map(|tile| tile.world_vertices_in(&grid));
+    let (min, max) = match corners.fold(None, |bounds: Option<(Vec2, Vec2)>, corner| {
+        Some(bounds.map_or((corner, corner), |(min, max)| {
+            (min.min(corner), max.max(corner))
+        }))
+    }) {
+        Some(bounds) => bounds,
+        None => return,
+    };
+
+    let mut camera = world
+        .query_filtered::<(&mut Transform, &mut OrthographicProjection), With<SelectionSource>>();
+    let (mut transf, mut projection) = match camera.get_single_mut(world) {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    let view_size = Vec2::new(
+        projection.right - projection.left,
+        projection.top - projection.bottom,
+    );
+    let (center, scale) = fit_view(min, max, view_size);
+    transf.translation = center.extend(transf.translation.z);
+    projection.scale = scale;
+}
+
+/// Takes back last frame's shake, so that other systems see the camera at rest.
+fn remove_shake_offset(
+    mut camera: Query<&mut Transform, With<Camera2d>>,
+    mut state: ResMut<ShakeState>,
+) {
+    if state.offset == Vec2::ZERO {
+        return;
+    }
+    if let Ok(mut transf) = camera.get_single_mut() {
+        transf.translation -= state.offset.extend(0.);
+    }
+    state.offset = Vec2::ZERO;
+}
+
+/// Adds a random offset to the camera for all running shakes.
+fn camera_shake(
+    mut requests: EventReader<CameraShake>,
+    mut camera: Query<&mut Transform, With<Camera2d>>,
+    mut state: ResMut<ShakeState>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+) {
+    if settings.reduced_motion {
+        requests.clear();
+        state.shakes.clear();
+        return;
+    }
+    state
+        .shakes
+        .extend(requests.iter().map(|shake| (shake.clone(), 0.)));
+    if state.shakes.is_empty() {
+        return;
+    }
+
+    let delta = time.delta_seconds();
+    let mut rng = rand::thread_rng();
+    let mut offset = Vec2::ZERO;
+    state.shakes.retain_mut(|(shake, elapsed)| {
+        *elapsed += delta;
+        if *elapsed >= shake.duration {
+            return false;
+        }
+        let strength = shake.intensity * (1. - *elapsed / shake.duration);
+        let angle = rng.gen_range(0. ..std::f32::consts::TAU);
+        offset += Vec2::new(angle.cos(), angle.sin()) * strength;
+        true
+    });
+
+    if let Ok(mut transf) = camera.get_single_mut() {
+        transf.translation += offset.extend(0.);
+        state.offset = offset;
+    }
+}
+
+fn focus_hint(
+    keys: Res<Input<KeyCode>>,
+    hint: Query<&GlobalTransform, With<RotationHint>>,
+    mut focus: EventWriter<CameraFocus>,
+) {
+    if !keys.just_pressed(KeyCode::H) {
+        return;
+    }
+    if let Ok(transf) = hint.get_single() {
+        focus.send(CameraFocus {
+            target: transf.translation().truncate(),
+            zoom: Some(0.6),
+            duration: 0.8,
+        });
+    }
+}
+
+#[test]
+fn test_focus_animation() {
+    let mut anim = FocusAnimation {
+        focus: CameraFocus {
+            target: Vec2::new(100., 50.),
+            zoom: Some(0.5),
+            duration: 1.,
+        },
+        start: Vec2::ZERO,
+        start_zoom: 1.,
+        elapsed: 0.,
+        intro: false,
+    };
+    assert_eq!(anim.sample(), (Vec2::ZERO, 1.));
+
+    anim.elapsed = 0.5;
+    let (halfway, _) = anim.sample();
+    assert!(halfway.x > 0. && halfway.x < 100.);
+
+    anim.elapsed = 1.;
+    assert_eq!(anim.sample(), (Vec2::new(100., 50.), 0.5));
+
+    anim.elapsed = 2.;
+    assert!(anim.is_finished());
+    assert_eq!(anim.sample(), (Vec2::ZERO, 1.));
+}
+
+#[test]
+fn test_intro_sweep() {
+    use std::time::{Duration, Instant};
+
+    let mut world = World::new();
+    world.insert_resource(Events::<CameraFocus>::default());
+    world.insert_resource(Events::<CameraSweep>::default());
+    world.insert_resource(InputSuppressed::default());
+    world.insert_resource(Input::<KeyCode>::default());
+    let start = Instant::now();
+    let mut time = Time::default();
+    time.update_with_instant(start);
+    world.insert_resource(time);
+
+    let rest = Vec2::new(20., 10.);
+    let camera = world
+        .spawn()
+        .insert(Transform::from_translation(rest.extend(999.)))
+        .insert(OrthographicProjection::default())
+        .insert(Camera2d::default())
+        .id();
+    world
+        .resource_mut::<Events<CameraSweep>>()
+        .send(CameraSweep {
+            from: Vec2::new(-300., 200.),
+            zoom: Some(1.5),
+            duration: 1.,
+        });
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(camera_focus);
+    let mut run_at = |world: &mut World, secs: f32| {
+        world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs_f32(secs));
+        stage.run(world);
+    };
+
+    run_at(&mut world, 0.3);
+    assert!(world.resource::<InputSuppressed>().any());
+    let transf = world.get::<Transform>(camera).unwrap();
+    assert!(transf.translation.truncate().distance(rest) > 1.);
+
+    run_at(&mut world, 1.5);
+    assert!(!world.resource::<InputSuppressed>().any());
+    let transf = world.get::<Transform>(camera).unwrap();
+    assert_eq!(transf.translation, rest.extend(999.));
+    assert_eq!(
+        world.get::<OrthographicProjection>(camera).unwrap().scale,
+        1.
+    );
+}
+
+#[test]
+fn test_camera_shake() {
+    use std::time::{Duration, Instant};
+
+    let mut world = World::new();
+    world.insert_resource(Events::<CameraShake>::default());
+    world.insert_resource(ShakeState::default());
+    world.insert_resource(Settings::default());
+    let start = Instant::now();
+    let mut time = Time::default();
+    time.update_with_instant(start);
+    world.insert_resource(time);
+
+    let rest = Vec3::new(20., 10., 999.);
+    let camera = world
+        .spawn()
+        .insert(Transform::from_translation(rest))
+        .insert(Camera2d::default())
+        .id();
+    world
+        .resource_mut::<Events<CameraShake>>()
+        .send(CameraShake {
+            intensity: 10.,
+            duration: 0.5,
+        });
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(remove_shake_offset.before(camera_shake))
+        .add_system(camera_shake);
+    let mut run_at = |world: &mut World, secs: f32| {
+        world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs_f32(secs));
+        stage.run(world);
+    };
+
+    run_at(&mut world, 0.1);
+    let offset = world.get::<Transform>(camera).unwrap().translation - rest;
+    assert!(offset.length() > 1. && offset.length() <= 10.);
+    assert_eq!(offset.z, 0.);
+
+    run_at(&mut world, 0.7);
+    let transf = world.get::<Transform>(camera).unwrap();
+    assert!(transf.translation.distance(rest) < 1e-3);
+
+    // Reduced motion ignores shakes
+    world.resource_mut::<Settings>().reduced_motion = true;
+    world
+        .resource_mut::<Events<CameraShake>>()
+        .send(CameraShake {
+            intensity: 10.,
+            duration: 0.5,
+        });
+    run_at(&mut world, 0.8);
+    let transf = world.get::<Transform>(camera).unwrap();
+    assert!(transf.translation.distance(rest) < 1e-3);
+}
+
+#[test]
+fn test_zoom_limits() {
+    assert!(zoomed(1., 1.) < 1.);
+    assert!(zoomed(1., -1.) > 1.);
+    assert_eq!(zoomed(1., 100.), MIN_ZOOM);
+    assert_eq!(zoomed(1., -100.), MAX_ZOOM);
+    assert!((zoomed(zoomed(1., 2.), -2.) - 1.).abs() < 1e-5);
+}
+
+#[test]
+fn test_pan_keeps_world_under_cursor() {
+    use bevy::render::camera::{CameraProjection, ScalingMode};
+
+    let screen = Vec2::new(1280., 720.);
+    let mut projection = OrthographicProjection {
+        scaling_mode: ScalingMode::FixedVertical(720.),
+        ..Default::default()
+    };
+    projection.scale = zoomed(1., 3.);
+    projection.update(screen.x, screen.y);
+    let inverse = projection.get_projection_matrix().inverse();
+
+    // The same mapping as the selection, from pixels from the bottom left corner to the world
+    let world_at = |transf: &Transform, pixel: Vec2| {
+        let ndc = pixel / screen * 2. - Vec2::ONE;
+        (transf.compute_matrix() * inverse * ndc.extend(0.).extend(1.))
+            .truncate()
+            .truncate()
+    };
+
+    let before = Transform::from_xyz(40., -25., 999.);
+    let pixel = Vec2::new(300., 200.);
+    // The screen motion points down, the pixels up
+    let motion = Vec2::new(60., 35.);
+    let mut after = before;
+    after.translation += pan_delta(motion, &projection, screen.y).extend(0.);
+
+    let grabbed = world_at(&before, pixel);
+    let dragged = world_at(&after, pixel + Vec2::new(motion.x, -motion.y));
+    assert!(grabbed.distance(dragged) < 1e-3);
+}
+
+#[test]
+fn test_fit_camera() {
+    use crate::tilemap::{TransformInWorld, TriangleOrient, VertexCoord};
+
+    let view = Vec2::new(1280., 720.);
+    let mut world = World::new();
+    let camera = world
+        .spawn()
+        .insert(Transform::from_xyz(100., 50., 999.))
+        .insert(OrthographicProjection {
+            left: -view.x / 2.,
+            right: view.x / 2.,
+            bottom: -view.y / 2.,
+            top: view.y / 2.,
+            scale: 2.,
+            ..Default::default()
+        })
+        .insert(SelectionSource)
+        .id();
+    let view_of = |world: &World| {
+        let transf = world.get::<Transform>(camera).unwrap();
+        let projection = world.get::<OrthographicProjection>(camera).unwrap();
+        (transf.translation.truncate(), projection.scale)
+    };
+
+    // An empty level keeps the view
+    fit_camera_to_level(&mut world);
+    assert_eq!(view_of(&world), (Vec2::new(100., 50.), 2.));
+
+    // The border isn't part of the level
+    let tile = |x, y| TriangleTile {
+        position: (VertexCoord::new(x, y), TriangleOrient::PointingUp),
+    };
+    world.spawn().insert(tile(40, 0));
+    world.spawn().insert(tile(0, 0)).insert(Immovable);
+    fit_camera_to_level(&mut world);
+    assert_eq!(view_of(&world), (Vec2::ZERO, 1.));
+
+    world.spawn().insert(tile(20, 0)).insert(Immovable);
+    fit_camera_to_level(&mut world);
+    let (center, scale) = view_of(&world);
+    assert!(center.x > 0. && scale > 1.);
+    let right = VertexCoord::new(21, 0).to_world_pos().translation.x;
+    assert!(center.x + view.x / 2. * scale >= right + FIT_MARGIN - 1e-3);
+}
@@ -0,0 +1,198 @@
+//! Runs the game logic without a window, e.g. to validate levels in batch.
+//! Start the game with `--headless <level>` to print a report of the level and exit.
+
+use std::fmt;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    level::LevelInfo,
+    moves::{completed_hexagons, legal_moves, solve, ColoredClump, Move},
+    rotation::{ContactRule, PlayfieldBounds},
+    savegame::{collect_save, spawn_level},
+    tilemap::{Immovable, RuneTile, TileColor, TileCoord, TriangleTile},
+    AssetHandles, SpriteAssets, BORDER_COORDS,
+};
+
+/// Solutions longer than this aren't searched by [`run`], the search grows exponentially.
+const SOLVER_MAX_MOVES: usize = 6;
+
+/// Summary of a loaded level.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LevelReport {
+    pub triangles: usize,
+    pub clumps: usize,
+    pub immovables: usize,
+    pub runes: usize,
+    pub lit_runes: usize,
+    /// Legal moves that stay within the playfield border
+    pub legal_moves: usize,
+    /// Hexagons of six triangles around a vertex
+    pub hexagons: usize,
+}
+
+impl LevelReport {
+    pub fn from_world(world: &mut World) -> Self {
+        let mut tris_query = world.query_filtered::<(&TriangleTile, &Parent), Without<Immovable>>();
+        let mut occupied: HashSet<TileCoord> = HashSet::new();
+        let mut clumps: HashMap<Entity, Vec<TileCoord>> = HashMap::new();
+        for (tile, parent) in tris_query.iter(world) {
+            occupied.insert(tile.position);
+            clumps.entry(parent.get()).or_default().push(tile.position);
+        }
+
+        let mut immov_query = world.query_filtered::<&TriangleTile, With<Immovable>>();
+        let immovables: HashSet<TileCoord> = immov_query.iter(world).map(|t| t.position).collect();
+
+        let mut runes_query = world.query::<&RuneTile>();
+        let runes: Vec<TileCoord> = runes_query.iter(world).map(|r| r.position).collect();
+        let lit_runes = runes_query
+            .iter(world)
+            .filter(|r| {
+                // Immovables light runes as well
+                r.required_cells()
+                    .iter()
+                    .all(|cell| occupied.contains(cell) || immovables.contains(cell))
+            })
+            .count();
+
+        let bounds = *world.resource::<PlayfieldBounds>();
+        let clumps: Vec<Vec<TileCoord>> = clumps.into_values().collect();
+        let legal_moves =
+            legal_moves(&clumps, &occupied, &immovables, (bounds.min, bounds.max)).len();
+
+        Self {
+            triangles: occupied.len(),
+            clumps: clumps.len(),
+            immovables: immovables.len(),
+            runes: runes.len(),
+            lit_runes,
+            legal_moves,
+            hexagons: completed_hexagons(&occupied).len(),
+        }
+    }
+}
+
+impl fmt::Display for LevelReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "triangles:  {}", self.triangles)?;
+        writeln!(f, "clumps:     {}", self.clumps)?;
+        writeln!(f, "immovables: {}", self.immovables)?;
+        writeln!(f, "runes:      {} ({} lit)", self.runes, self.lit_runes)?;
+        writeln!(f, "hexagons:   {}", self.hexagons)?;
+        write!(f, "moves:      {}", self.legal_moves)
+    }
+}
+
+/// The fewest moves solving the loaded level, searching at most `max_moves` deep.
+/// See [`solve`] for how the moves index the clumps.
+pub fn solve_level(world: &mut World, max_moves: usize) -> Option<Vec<Move>> {
+    let mut tris_query =
+        world.query_filtered::<(&TriangleTile, Option<&TileColor>, &Parent), Without<Immovable>>();
+    let mut clumps: HashMap<Entity, ColoredClump> = HashMap::new();
+    for (tile, color, parent) in tris_query.iter(world) {
+        let color = color.copied().unwrap_or_default();
+        clumps
+            .entry(parent.get())
+            .or_default()
+            .push((tile.position, color));
+    }
+    let clumps: Vec<ColoredClump> = clumps.into_values().collect();
+
+    let mut immov_query = world.query_filtered::<&TriangleTile, With<Immovable>>();
+    let immovables: HashSet<TileCoord> = immov_query.iter(world).map(|t| t.position).collect();
+
+    let mut runes_query = world.query::<(&RuneTile, Option<&TileColor>)>();
+    let runes: Vec<(Vec<TileCoord>, TileColor)> = runes_query
+        .iter(world)
+        .map(|(rune, color)| {
+            (
+                rune.required_cells().to_vec(),
+                color.copied().unwrap_or_default(),
+            )
+        })
+        .collect();
+
+    let bounds = *world.resource::<PlayfieldBounds>();
+    let contact_rule = *world.resource::<ContactRule>();
+    solve(
+        &clumps,
+        &immovables,
+        &runes,
+        (bounds.min, bounds.max),
+        |tile| contact_rule.contacts(tile),
+        max_moves,
+    )
+}
+
+/// Build an app without window, rendering and input. Input dependent systems are not added.
+/// Moves are bounded by the border of the windowed game.
+pub fn build_app() -> App {
+    let border: Vec<TileCoord> =
+        serde_json::from_str(BORDER_COORDS).expect("Border json should be formatted correctly!");
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(AssetHandles::default())
+        .insert_resource(SpriteAssets::default())
+        .insert_resource(PlayfieldBounds::from_tiles(&border))
+        .init_resource::<LevelInfo>();
+    app
+}
+
+/// Load the level `name`, run a frame and print the [`LevelReport`] and the shortest solution.
+/// Exits with an error code if the level can't be loaded.
+pub fn run(name: &str) {
+    let mut app = build_app();
+    if let Err(e) = spawn_level(&mut app.world, name) {
+        eprintln!("Failed to load level: {}", e);
+        std::process::exit(1);
+    }
+    app.update();
+
+    println!("Level {}", name);
+    println!("{}", LevelReport::from_world(&mut app.world));
+    let center = collect_save(&mut app.world).centroid_world();
+    println!("center:     ({:.0}, {:.0})", center.x, center.y);
+    match solve_level(&mut app.world, SOLVER_MAX_MOVES) {
+        Some(solution) => println!("solution:   {} moves", solution.len()),
+        None => println!("solution:   none within {} moves", SOLVER_MAX_MOVES),
+    }
+}
+
+#[test]
+fn test_headless_level() {
+    use crate::savegame::end_level_index;
+
+    let mut app = build_app();
+    spawn_level(&mut app.world, "1").unwrap();
+    app.update();
+
+    let report = LevelReport::from_world(&mut app.world);
+    assert_eq!(report.triangles, 1);
+    assert_eq!(report.runes, 1);
+    assert_eq!(report.lit_runes, 0);
+    assert_eq!(report.hexagons, 0);
+
+    // No built-in level starts solved
+    for level in 1..end_level_index() {
+        spawn_level(&mut app.world, &level.to_string()).unwrap();
+        let report = LevelReport::from_world(&mut app.world);
+        assert!(report.lit_runes < report.runes, "level {}", level);
+    }
+}
+
+#[test]
+fn test_solve_level() {
+    let mut app = build_app();
+    spawn_level(&mut app.world, "1").unwrap();
+    app.update();
+
+    // The tutorial is solved exactly at its par
+    let par = app.world.resource::<LevelInfo>().par.unwrap();
+    let solution = solve_level(&mut app.world, SOLVER_MAX_MOVES).unwrap();
+    assert_eq!(solution.len(), par as usize);
+    assert_eq!(solve_level(&mut app.world, solution.len() - 1), None);
+}
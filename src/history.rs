@@ -0,0 +1,338 @@
+//! Undo for rotations and the merges they caused. Press `Z` to undo the last rotation and `Y`
+//! to redo it.
+
+use bevy::prelude::*;
+
+use crate::{
+    level::LevelInfo,
+    level_editor::spawn_clump,
+    rotation::{MergeEvent, MergeQueue, RotationAnimation, TileMoved},
+    tilemap::{GridConfig, TransformInWorld, TriangleTile},
+    GameState,
+};
+
+pub struct MagnateHistoryPlugin;
+
+impl Plugin for MagnateHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MoveHistory>()
+            .add_system_set(
+                SystemSet::on_update(GameState::Next)
+                    .with_system(undo_system)
+                    .with_system(redo_system),
+            )
+            // After all rotations and merges of the frame
+            .add_system_to_stage(CoreStage::PostUpdate, record_history);
+    }
+}
+
+/// A rotation and the merges it caused.
+#[derive(Debug, Clone, Default)]
+pub struct HistoryEntry {
+    /// The [`LevelInfo::move_count`] after the rotation, which tags the merges it caused
+    pub rotation: u32,
+    pub moves: Vec<TileMoved>,
+    /// In the order they were applied, which can take several frames
+    pub merges: Vec<MergeEvent>,
+}
+
+/// The rotations of the current level, one entry per rotation. Reset whenever a level is loaded.
+pub struct MoveHistory {
+    pub enabled: bool,
+    /// The number of rotations to keep, `None` keeps all of them
+    pub limit: Option<usize>,
+    entries: Vec<HistoryEntry>,
+    /// Undone rotations, the latest last. Cleared by a new rotation.
+    undone: Vec<HistoryEntry>,
+}
+
+impl Default for MoveHistory {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            limit: None,
+            entries: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+}
+
+impl MoveHistory {
+    /// Records a new rotation, which can't be followed by a redo.
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.undone.clear();
+        self.restore(entry);
+    }
+
+    fn restore(&mut self, entry: HistoryEntry) {
+        self.entries.push(entry);
+        if let Some(limit) = self.limit {
+            let excess = self.entries.len().saturating_sub(limit);
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Takes the latest rotation and keeps its moves for [`MoveHistory::redo`].
+    pub fn undo(&mut self) -> Option<HistoryEntry> {
+        let entry = self.entries.pop()?;
+        // The merges are found again when the rotation is redone
+        self.undone.push(HistoryEntry {
+            merges: Vec::new(),
+            ..entry.clone()
+        });
+        Some(entry)
+    }
+
+    /// Takes the latest undone rotation and records it again.
+    pub fn redo(&mut self) -> Option<HistoryEntry> {
+        let entry = self.undone.pop()?;
+        self.restore(entry.clone());
+        Some(entry)
+    }
+
+    /// Adds a merge to the rotation which caused it, even if later rotations happened since.
+    pub fn push_merge(&mut self, merge: MergeEvent) {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .rev()
+            .find(|entry| entry.rotation == merge.rotation)
+        {
+            entry.merges.push(merge);
+        }
+    }
+}
+
+fn record_history(
+    mut moved: EventReader<TileMoved>,
+    mut merged: EventReader<MergeEvent>,
+    mut history: ResMut<MoveHistory>,
+    level: Res<LevelInfo>,
+) {
+    let moves: Vec<TileMoved> = moved.iter().cloned().collect();
+    if history.enabled && !moves.is_empty() {
+        history.push(HistoryEntry {
+            rotation: level.move_count,
+            moves,
+            merges: Vec::new(),
+        });
+    }
+    for merge in merged.iter() {
+        if history.enabled {
+            history.push_merge(merge.clone());
+        }
+    }
+}
+
+fn undo_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut history: ResMut<MoveHistory>,
+    mut queue: ResMut<MergeQueue>,
+    mut level: ResMut<LevelInfo>,
+    mut triangles: Query<(&mut TriangleTile, &mut Transform)>,
+    grid: Res<GridConfig>,
+) {
+    if !history.enabled || !keys.just_pressed(KeyCode::Z) {
+        return;
+    }
+    let entry = match history.undo() {
+        Some(entry) => entry,
+        None => return,
+    };
+    level.move_count = level.move_count.saturating_sub(1);
+    // Merges which are still deferred would undo the undo
+    queue.forget(entry.rotation);
+
+    // Give the absorbed triangles a parent of their own again, starting with the latest merge,
+    // since it might contain the triangles of earlier ones
+    for merge in entry.merges.iter().rev() {
+        let alive: Vec<Entity> = merge
+            .triangles
+            .iter()
+            .copied()
+            .filter(|&id| triangles.contains(id))
+            .collect();
+        if !alive.is_empty() {
+            spawn_clump(&mut commands, &alive);
+        }
+    }
+    for moved in entry.moves {
+        // The triangle might be gone after a reload
+        if let Ok((mut tile, mut transf)) = triangles.get_mut(moved.entity) {
+            tile.position = moved.from;
+            *transf = tile.to_world_pos_in(&grid);
+            commands.entity(moved.entity).remove::<RotationAnimation>();
+        }
+    }
+}
+
+/// Moves the triangles of the latest undone rotation again, the merge system then finds the
+/// merges it caused.
+fn redo_system(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut history: ResMut<MoveHistory>,
+    mut level: ResMut<LevelInfo>,
+    mut triangles: Query<(&mut TriangleTile, &mut Transform)>,
+    grid: Res<GridConfig>,
+) {
+    if !history.enabled || !keys.just_pressed(KeyCode::Y) {
+        return;
+    }
+    let entry = match history.redo() {
+        Some(entry) => entry,
+        None => return,
+    };
+    level.move_count = entry.rotation;
+    for moved in entry.moves {
+        if let Ok((mut tile, mut transf)) = triangles.get_mut(moved.entity) {
+            tile.position = moved.to;
+            *transf = tile.to_world_pos_in(&grid);
+            commands.entity(moved.entity).remove::<RotationAnimation>();
+        }
+    }
+}
+
+#[test]
+fn test_history_limit() {
+    use crate::tilemap::{TriangleOrient, VertexCoord};
+
+    let tile = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let entry = |i| HistoryEntry {
+        rotation: i,
+        moves: vec![TileMoved {
+            entity: Entity::from_raw(i),
+            from: tile,
+            to: tile,
+        }],
+        merges: Vec::new(),
+    };
+    let mut history = MoveHistory {
+        limit: Some(2),
+        ..Default::default()
+    };
+    for i in 0..3 {
+        history.push(entry(i));
+    }
+    assert_eq!(history.undo().unwrap().moves[0].entity, Entity::from_raw(2));
+    assert_eq!(history.undo().unwrap().moves[0].entity, Entity::from_raw(1));
+    assert!(history.undo().is_none());
+
+    assert_eq!(history.redo().unwrap().rotation, 1);
+    assert_eq!(history.redo().unwrap().rotation, 2);
+    assert!(history.redo().is_none());
+    // A new rotation drops the undone ones
+    history.undo();
+    history.push(entry(3));
+    assert!(history.redo().is_none());
+}
+
+#[test]
+fn test_deferred_merge() {
+    use crate::tilemap::{TriangleOrient, VertexCoord};
+
+    let tile = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let mut history = MoveHistory::default();
+    for rotation in 1..=2 {
+        history.push(HistoryEntry {
+            rotation,
+            moves: vec![TileMoved {
+                entity: Entity::from_raw(rotation),
+                from: tile,
+                to: tile,
+            }],
+            merges: Vec::new(),
+        });
+    }
+    // Applied after the second rotation, but caused by the first one
+    history.push_merge(MergeEvent {
+        survivor: Entity::from_raw(10),
+        absorbed: Entity::from_raw(11),
+        triangles: Vec::new(),
+        tile_count: 2,
+        rotation: 1,
+    });
+    assert!(history.undo().unwrap().merges.is_empty());
+    assert_eq!(history.undo().unwrap().merges.len(), 1);
+}
+
+#[test]
+fn test_undo_merge() {
+    use crate::tilemap::{ClumpRoot, TriangleOrient, VertexCoord};
+
+    let mut world = World::new();
+    let from = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let to = (VertexCoord::new(1, 0), TriangleOrient::PointingUp);
+    let moved = world
+        .spawn()
+        .insert(TriangleTile { position: to })
+        .insert(Transform::default())
+        .id();
+    let absorbed = world
+        .spawn()
+        .insert(TriangleTile {
+            position: (VertexCoord::new(2, 0), TriangleOrient::PointingDown),
+        })
+        .insert(Transform::default())
+        .id();
+    // The state after the rotation and the merge
+    let survivor = world
+        .spawn()
+        .insert(ClumpRoot)
+        .push_children(&[moved, absorbed])
+        .id();
+
+    let mut history = MoveHistory::default();
+    history.push(HistoryEntry {
+        rotation: 1,
+        moves: vec![TileMoved {
+            entity: moved,
+            from,
+            to,
+        }],
+        merges: Vec::new(),
+    });
+    history.push_merge(MergeEvent {
+        survivor,
+        absorbed: Entity::from_raw(99),
+        triangles: vec![absorbed],
+        tile_count: 2,
+        rotation: 1,
+    });
+    world.insert_resource(history);
+    world.insert_resource(MergeQueue::default());
+    world.insert_resource(LevelInfo {
+        move_count: 1,
+        ..Default::default()
+    });
+    let mut keys = Input::<KeyCode>::default();
+    keys.press(KeyCode::Z);
+    world.insert_resource(keys);
+    world.insert_resource(GridConfig::default());
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(undo_system);
+    stage.run(&mut world);
+
+    assert_eq!(world.get::<TriangleTile>(moved).unwrap().position, from);
+    assert_eq!(world.get::<Parent>(moved).unwrap().get(), survivor);
+    let parent = world.get::<Parent>(absorbed).unwrap().get();
+    assert_ne!(parent, survivor);
+    assert!(world.get::<ClumpRoot>(parent).is_some());
+    assert_eq!(world.get::<Children>(survivor).unwrap().len(), 1);
+    assert_eq!(world.resource::<LevelInfo>().move_count, 0);
+
+    let mut keys = Input::<KeyCode>::default();
+    keys.press(KeyCode::Y);
+    world.insert_resource(keys);
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(redo_system);
+    stage.run(&mut world);
+
+    // The merge is left to the merge system
+    assert_eq!(world.get::<TriangleTile>(moved).unwrap().position, to);
+    assert_eq!(world.resource::<LevelInfo>().move_count, 1);
+    let mut history = world.resource_mut::<MoveHistory>();
+    assert_eq!(history.undo().unwrap().moves[0].entity, moved);
+}
@@ -3,15 +3,45 @@ use std::time::Duration;
 use bevy::{
     prelude::*,
     render::{mesh::Indices, render_resource::PrimitiveTopology},
-    utils::HashSet,
+    transform::TransformSystem,
+    utils::{HashMap, HashSet},
 };
 
+use bevy_point_selection::Selectable;
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    savegame::spawn_level,
-    tilemap::{RuneTile, TileCoord, TransformInWorld, TriangleTile, SQRT3_HALF, TRIANGLE_SIDE},
-    AssetHandles, GameState, SpriteAssets,
+    camera::{ease, CameraShake},
+    level_editor::RUNE_FRAMES,
+    moves::RotationDir,
+    savegame::{end_level_index, load_level},
+    settings::Settings,
+    tilemap::{
+        GridConfig, IterNeighbors, RuneTile, TileColor, TileCoord, TransformInWorld, TriangleTile,
+        VertexCoord, SQRT3_HALF, TRIANGLE_SIDE,
+    },
+    AssetHandles, GameState, SpriteAssets, LEVEL_LABEL_COLOR,
 };
 
+/// Color of the move counter after solving a level within par
+const PAR_COLOR: Color = Color::rgb_u8(212, 175, 55);
+
+/// Tint of each [`TileColor`], applied to runes and the grey triangle sprite. Color `0` keeps the
+/// ruby sprite untinted.
+const TILE_TINTS: &[Color] = &[
+    Color::WHITE,
+    Color::rgb(0.3, 0.5, 1.0),
+    Color::rgb(0.3, 0.9, 0.4),
+    Color::rgb(1.0, 0.8, 0.2),
+];
+
+fn tile_tint(color: TileColor) -> Color {
+    TILE_TINTS
+        .get(color.0 as usize)
+        .copied()
+        .unwrap_or(Color::WHITE)
+}
+
 pub struct MagnateLevelPlugin;
 
 impl Plugin for MagnateLevelPlugin {
@@ -19,15 +49,29 @@ impl Plugin for MagnateLevelPlugin {
         app.add_system_set(
             SystemSet::on_update(GameState::Next)
                 .with_system(rune_system)
+                .with_system(advance_level.after(rune_system))
                 .with_system(soft_despawn)
-                .with_system(scale_animation),
+                .with_system(scale_animation)
+                .with_system(rotate_hint_sprites)
+                .with_system(continue_prompt)
+                .with_system(update_move_counter)
+                .with_system(freeze_selection),
         )
         .add_system_set(
-            SystemSet::on_enter(GameState::Next)
-                .with_system(initial_load.exclusive_system())
-                .with_system(spawn_tutorial),
+            SystemSet::on_enter(GameState::Next).with_system(initial_load.exclusive_system()),
         )
-        .init_resource::<LevelInfo>();
+        .init_resource::<LevelInfo>()
+        .init_resource::<Playlist>()
+        .init_resource::<RotateHintStyle>()
+        .init_resource::<RuneFeedback>()
+        .init_resource::<WinAnimationConfig>()
+        .add_event::<LevelCompleted>()
+        .add_event::<BoardDirty>()
+        // Runs after every spawn of the frame, so new pieces never show up at full size
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            spawn_animation.before(TransformSystem::TransformPropagate),
+        );
     }
 }
 
@@ -37,6 +81,36 @@ pub struct ScaleAnimation {
     pub amplitude: f32,
 }
 
+/// Seconds for a piece to grow to its full size after it was spawned
+const SPAWN_DURATION: f32 = 0.3;
+/// Delay in seconds between two pieces of a level starting to grow
+pub const SPAWN_STAGGER: f32 = 0.02;
+
+/// Grows a piece from nothing with a slight overshoot, removed once it has its full size.
+#[derive(Component, Default, Debug, Clone)]
+pub struct SpawnAnimation {
+    pub elapsed: f32,
+    /// Seconds before the piece starts to grow
+    pub delay: f32,
+}
+
+impl SpawnAnimation {
+    pub fn with_delay(delay: f32) -> Self {
+        Self { elapsed: 0., delay }
+    }
+
+    /// The scale relative to the full size, which overshoots before settling at `1`.
+    pub fn scale(&self) -> f32 {
+        const OVERSHOOT: f32 = 1.70158;
+        let t = ((self.elapsed - self.delay) / SPAWN_DURATION).clamp(0., 1.) - 1.;
+        1. + (OVERSHOOT + 1.) * t * t * t + OVERSHOOT * t * t
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.delay + SPAWN_DURATION
+    }
+}
+
 #[derive(Component, Default, Debug, Clone)]
 pub struct SoftDespawned {
     pub death_time: Duration,
@@ -48,68 +122,471 @@ pub struct RotationHint;
 #[derive(Component, Default, Debug, Clone)]
 pub struct ReloadHint;
 
+/// Shown while a won level waits for `Enter`, see [`Settings::auto_advance`].
+#[derive(Component, Default, Debug, Clone)]
+pub struct ContinuePrompt;
+
+/// A tutorial hint as stored in the level data. Also a component on the spawned hint.
+#[derive(Component, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TutorialHint {
+    pub sprite: HintSprite,
+    pub position: HintPosition,
+    pub trigger: HintTrigger,
+    /// The rotation a rotate hint asks for
+    #[serde(default)]
+    pub direction: RotationDir,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HintSprite {
+    /// Removed when the player rotates for the first time
+    Rotate,
+    /// Removed when the player reloads the level manually
+    Reload,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HintPosition {
+    /// Centered on a grid vertex, e.g. the anchor to rotate around
+    Vertex(VertexCoord),
+    /// Fixed world position, e.g. next to the board
+    World([f32; 2]),
+}
+
+/// When a hint becomes visible
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HintTrigger {
+    OnLoad,
+    OnFirstMerge,
+    OnFirstRotate,
+}
+
+/// How rotate hints show their direction, to fit the sprites of a theme.
+#[derive(Debug, Clone, Default)]
+pub struct RotateHintStyle {
+    /// Mirror the clockwise sprite instead of using the counter clockwise one
+    pub mirror: bool,
+    /// Swap the sprites of both directions
+    pub swap: bool,
+}
+
+/// The text showing the name of the current level
+#[derive(Component, Default, Debug, Clone)]
+pub struct LevelLabel;
+
+/// The text showing the moves of the current level and its par, if it has one
+#[derive(Component, Default, Debug, Clone)]
+pub struct MoveCounter;
+
+/// Send this after changing the board in a way that doesn't change any [`TriangleTile`], e.g.
+/// reparenting triangles, so the runes are checked again.
+#[derive(Debug, Clone, Default)]
+pub struct BoardDirty;
+
+/// How the runes grow over the duration of the win animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WinEasing {
+    #[default]
+    Linear,
+    /// Slow start and end, see [`ease`]
+    Smooth,
+    /// Fast start, slow end
+    EaseOut,
+}
+
+impl WinEasing {
+    /// Maps the linear progress `t` in `0..=1` to the eased progress.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0., 1.);
+        match self {
+            WinEasing::Linear => t,
+            WinEasing::Smooth => ease(t),
+            WinEasing::EaseOut => 1. - (1. - t) * (1. - t),
+        }
+    }
+}
+
+/// The runes grow one after another once a level is won. A `duration` and `stagger` of zero skip
+/// the animation, e.g. for speedruns.
+pub struct WinAnimationConfig {
+    /// Scale of the runes at the end, relative to their normal size
+    pub final_scale: f32,
+    /// Delay in seconds between two runes starting to grow
+    pub stagger: f32,
+    /// Seconds for a single rune to grow
+    pub duration: f32,
+    pub easing: WinEasing,
+}
+
+impl Default for WinAnimationConfig {
+    fn default() -> Self {
+        Self {
+            final_scale: 4.,
+            stagger: 0.,
+            duration: 0.6,
+            easing: WinEasing::Linear,
+        }
+    }
+}
+
+impl WinAnimationConfig {
+    /// The relative scale of the `index`th rune `elapsed` seconds into the animation.
+    pub fn scale_at(&self, index: usize, elapsed: f32) -> f32 {
+        let t = (elapsed - self.stagger * index as f32) / self.duration.max(f32::EPSILON);
+        1. + (self.final_scale - 1.) * self.easing.apply(t)
+    }
+
+    /// Seconds until all of `runes` runes are fully grown.
+    pub fn total_duration(&self, runes: usize) -> f32 {
+        self.duration + self.stagger * runes.saturating_sub(1) as f32
+    }
+}
+
+/// Runes glow faintly if a triangle is close to them.
+pub struct RuneFeedback {
+    /// How many edge neighbors a triangle may be away, `0` disables the glow
+    pub almost_distance: usize,
+    /// Tint of the lit sprite for runes that are almost lit
+    pub almost_color: Color,
+}
+
+impl Default for RuneFeedback {
+    fn default() -> Self {
+        Self {
+            almost_distance: 1,
+            almost_color: Color::rgba(1., 1., 1., 0.4),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuneState {
+    Unlit,
+    /// A triangle is within [`RuneFeedback::almost_distance`]
+    Almost,
+    /// Some, but not all of the [`RuneTile::required`] cells are covered
+    Partial {
+        filled: usize,
+        total: usize,
+    },
+    Lit,
+}
+
+impl RuneState {
+    /// The frame of the glyph in the sprite sheet, see [`RUNE_FRAMES`]. Partially lit runes
+    /// advance through the frames between the unlit and the lit one, so with only those two
+    /// they stay unlit and just glow brighter.
+    pub fn frame(self) -> usize {
+        match self {
+            RuneState::Unlit => 0,
+            RuneState::Partial { filled, total } => filled * (RUNE_FRAMES - 1) / total,
+            RuneState::Almost | RuneState::Lit => RUNE_FRAMES - 1,
+        }
+    }
+}
+
+/// Identifies a level: either built-in by its index, or saved by its name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LevelId {
+    Builtin(usize),
+    Named(String),
+}
+
+impl LevelId {
+    /// The built-in level if `name` is the number of one, otherwise the saved level `name`.
+    pub fn from_name(name: &str) -> Self {
+        match name.parse() {
+            Ok(i) if i <= end_level_index() => LevelId::Builtin(i),
+            _ => LevelId::Named(name.to_string()),
+        }
+    }
+
+    /// The level shown after all levels are completed.
+    pub fn end() -> Self {
+        LevelId::Builtin(end_level_index())
+    }
+}
+
+impl std::fmt::Display for LevelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelId::Builtin(i) => write!(f, "{}", i),
+            LevelId::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Resource with the levels in the order they are played, winning a level continues with the
+/// next one. After the last one the end level is shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playlist(pub Vec<LevelId>);
+
+impl Default for Playlist {
+    /// All built-in levels except the empty and the end level.
+    fn default() -> Self {
+        Self((1..end_level_index()).map(LevelId::Builtin).collect())
+    }
+}
+
+impl Playlist {
+    /// The level after `current`. Levels which aren't part of the playlist continue with the end
+    /// level.
+    pub fn next(&self, current: &LevelId) -> LevelId {
+        self.0
+            .iter()
+            .position(|level| level == current)
+            .and_then(|i| self.0.get(i + 1))
+            .cloned()
+            .unwrap_or_else(LevelId::end)
+    }
+
+    pub fn is_first(&self, level: &LevelId) -> bool {
+        self.0.first() == Some(level)
+    }
+
+    pub fn is_last(&self, level: &LevelId) -> bool {
+        self.0.last() == Some(level)
+    }
+}
+
+/// Sent by the rune system as soon as all runes of a level are lit.
+pub struct LevelCompleted {
+    pub level: LevelId,
+    /// Rotations it took to solve the level
+    pub moves: u32,
+    /// The level was solved in at most its par of moves
+    pub par_reached: bool,
+}
+
 pub struct LevelInfo {
-    pub current: usize,
+    pub current: LevelId,
     pub win_animation_progress: Option<f32>,
     pub should_reload: bool,
+    /// The win animation has ended, but the next level is only loaded after confirmation
+    pub awaiting_confirm: bool,
+    /// Rotations since the level was loaded
+    pub move_count: u32,
+    /// The number of moves the level can be solved in, from the level data
+    pub par: Option<u32>,
+}
+
+impl LevelInfo {
+    pub fn par_reached(&self) -> bool {
+        self.par.map_or(false, |par| self.move_count <= par)
+    }
 }
 
 impl Default for LevelInfo {
     fn default() -> Self {
         Self {
-            current: 1,
+            current: LevelId::Builtin(1),
             win_animation_progress: None,
             should_reload: false,
+            awaiting_confirm: false,
+            move_count: 0,
+            par: None,
         }
     }
 }
 
 fn rune_system(
-    mut runes: Query<(&RuneTile, &mut TextureAtlasSprite, &mut Transform)>,
+    mut runes: Query<(
+        &RuneTile,
+        Option<&TileColor>,
+        &mut TextureAtlasSprite,
+        &mut Transform,
+    )>,
     added_runes: Query<Entity, Added<RuneTile>>,
     changed_triangles: Query<Entity, Changed<TriangleTile>>,
-    all_triangles: Query<&TriangleTile>,
+    all_triangles: Query<(&TriangleTile, Option<&TileColor>)>,
+    mut dirty: EventReader<BoardDirty>,
+    feedback: Res<RuneFeedback>,
+    win_anim: Res<WinAnimationConfig>,
     mut level: ResMut<LevelInfo>,
     time: Res<Time>,
+    mut completed: EventWriter<LevelCompleted>,
+    mut shake: EventWriter<CameraShake>,
+    grid: Res<GridConfig>,
 ) {
     if let Some(progress) = level.win_animation_progress {
-        if progress >= 0.6 {
-            level.current += 1;
-            level.should_reload = true;
-            level.win_animation_progress = None;
-        } else {
-            for (_, _, mut transf) in runes.iter_mut() {
-                transf.scale *= 1. + progress;
+        if progress < win_anim.total_duration(runes.iter().len()) {
+            // Pop from left to right
+            let mut sorted: Vec<_> = runes.iter_mut().collect();
+            sorted.sort_by_key(|(rune, _, _, _)| (rune.position.0.x, rune.position.0.y));
+            for (i, (rune, _, _, mut transf)) in sorted.into_iter().enumerate() {
+                transf.scale = rune.to_world_pos_in(&grid).scale * win_anim.scale_at(i, progress);
             }
             level.win_animation_progress = Some(progress + time.delta_seconds());
         }
         return;
     }
 
-    if changed_triangles.is_empty() && added_runes.is_empty() {
+    // Consume the events in any case
+    let is_dirty = dirty.iter().count() > 0;
+    if changed_triangles.is_empty() && added_runes.is_empty() && !is_dirty && !feedback.is_changed()
+    {
         return;
     }
-    let all_triangles: HashSet<TileCoord> = all_triangles.iter().map(|tri| tri.position).collect();
+    let mut by_color: HashMap<TileColor, HashSet<TileCoord>> = HashMap::new();
+    for (tri, color) in all_triangles.iter() {
+        by_color
+            .entry(color.copied().unwrap_or_default())
+            .or_default()
+            .insert(tri.position);
+    }
+    let no_triangles = HashSet::new();
 
     let mut total_runes = 0;
     let mut fulfilled_runes = 0;
-    for (rune, mut sprite, _) in runes.iter_mut() {
-        if all_triangles.contains(&rune.position) {
+    for (rune, color, mut sprite, _) in runes.iter_mut() {
+        let color = color.copied().unwrap_or_default();
+        let occupied = by_color.get(&color).unwrap_or(&no_triangles);
+        let state = rune_state(rune.required_cells(), occupied, feedback.almost_distance);
+        if state == RuneState::Lit {
             fulfilled_runes += 1;
-            // round to odd
-            sprite.index = (sprite.index / 2) * 2 + 1;
-        } else {
-            // round to even
-            sprite.index = (sprite.index / 2) * 2;
         }
+        // Keep the glyph
+        sprite.index = (sprite.index / RUNE_FRAMES) * RUNE_FRAMES + state.frame();
+        sprite.color = match state {
+            RuneState::Almost => feedback.almost_color,
+            // Glow brighter the more cells are covered
+            RuneState::Partial { filled, total } => {
+                let faint = feedback.almost_color.a();
+                let mut glow = feedback.almost_color;
+                glow.set_a(faint + (1. - faint) * filled as f32 / total as f32);
+                glow
+            }
+            _ => tile_tint(color),
+        };
         total_runes += 1;
     }
 
     if total_runes > 0 && total_runes == fulfilled_runes {
+        completed.send(LevelCompleted {
+            level: level.current.clone(),
+            moves: level.move_count,
+            par_reached: level.par_reached(),
+        });
+        if level.par_reached() {
+            info!("Solved in {} moves, within par!", level.move_count);
+        }
+        shake.send(CameraShake {
+            intensity: 10.,
+            duration: 0.5,
+        });
+    }
+}
+
+/// Starts the win animation once a level is completed and loads the next level after it, either
+/// right away or once confirmed with `Enter`.
+fn advance_level(
+    mut completed: EventReader<LevelCompleted>,
+    runes: Query<(), With<RuneTile>>,
+    win_anim: Res<WinAnimationConfig>,
+    settings: Res<Settings>,
+    keys: Res<Input<KeyCode>>,
+    playlist: Res<Playlist>,
+    mut level: ResMut<LevelInfo>,
+) {
+    if completed.iter().count() > 0 {
         level.win_animation_progress = Some(0.);
+        level.awaiting_confirm = false;
+        return;
+    }
+    let progress = match level.win_animation_progress {
+        Some(progress) => progress,
+        None => return,
+    };
+    if progress < win_anim.total_duration(runes.iter().len()) {
+        return;
+    }
+    if settings.auto_advance || keys.just_pressed(KeyCode::Return) {
+        level.current = playlist.next(&level.current);
+        level.should_reload = true;
+        level.win_animation_progress = None;
+        level.awaiting_confirm = false;
+    } else if !level.awaiting_confirm {
+        level.awaiting_confirm = true;
+    }
+}
+
+/// Triangles can't be selected while the runes scale up after a win.
+fn freeze_selection(level: Res<LevelInfo>, mut selectables: Query<&mut Selectable>) {
+    if !level.is_changed() {
+        return;
+    }
+    let enabled = level.win_animation_progress.is_none();
+    for mut selectable in selectables.iter_mut() {
+        if selectable.enabled != enabled {
+            selectable.set_enabled(enabled);
+        }
     }
 }
 
+/// Shows "moves / par", which turns gold once the level is won within par.
+fn update_move_counter(level: Res<LevelInfo>, mut counters: Query<&mut Text, With<MoveCounter>>) {
+    if !level.is_changed() {
+        return;
+    }
+    for mut text in counters.iter_mut() {
+        if let Some(section) = text.sections.first_mut() {
+            section.value = match level.par {
+                Some(par) => format!("{} / {}", level.move_count, par),
+                None => level.move_count.to_string(),
+            };
+            section.style.color = if level.win_animation_progress.is_some() && level.par_reached() {
+                PAR_COLOR
+            } else {
+                LEVEL_LABEL_COLOR
+            };
+        }
+    }
+}
+
+fn continue_prompt(
+    level: Res<LevelInfo>,
+    mut prompts: Query<&mut Visibility, With<ContinuePrompt>>,
+) {
+    if !level.is_changed() {
+        return;
+    }
+    for mut visibility in prompts.iter_mut() {
+        visibility.is_visible = level.awaiting_confirm;
+    }
+}
+
+/// The state of a rune which needs all of the `cells` to be covered, which mustn't be empty.
+fn rune_state(
+    cells: &[TileCoord],
+    occupied: &HashSet<TileCoord>,
+    almost_distance: usize,
+) -> RuneState {
+    let filled = cells.iter().filter(|cell| occupied.contains(cell)).count();
+    if filled == cells.len() {
+        return RuneState::Lit;
+    }
+    if filled > 0 {
+        return RuneState::Partial {
+            filled,
+            total: cells.len(),
+        };
+    }
+    let mut seen: HashSet<TileCoord> = cells.iter().copied().collect();
+    let mut ring = cells.to_vec();
+    for _ in 0..almost_distance {
+        ring = ring
+            .iter()
+            .flat_map(|tile| tile.iter_neighbors())
+            .filter(|tile| seen.insert(*tile))
+            .collect();
+        if ring.iter().any(|tile| occupied.contains(tile)) {
+            return RuneState::Almost;
+        }
+    }
+    RuneState::Unlit
+}
+
 fn soft_despawn(
     mut commands: Commands,
     mut affected: Query<(Entity, &mut Transform, &SoftDespawned)>,
@@ -127,6 +604,35 @@ fn soft_despawn(
     }
 }
 
+/// The full size of tiles depends on their orientation, which can change while they grow.
+fn spawn_animation(
+    mut commands: Commands,
+    mut affected: Query<(
+        Entity,
+        &mut Transform,
+        &mut SpawnAnimation,
+        Option<&TriangleTile>,
+        Option<&RuneTile>,
+    )>,
+    time: Res<Time>,
+    grid: Res<GridConfig>,
+) {
+    for (id, mut transf, mut anim, triangle, rune) in affected.iter_mut() {
+        anim.elapsed += time.delta_seconds();
+        let full_scale = match (triangle, rune) {
+            (Some(triangle), _) => triangle.to_world_pos_in(&grid).scale,
+            (None, Some(rune)) => rune.to_world_pos_in(&grid).scale,
+            (None, None) => Vec3::ONE,
+        };
+        if anim.is_finished() {
+            transf.scale = full_scale;
+            commands.entity(id).remove::<SpawnAnimation>();
+        } else {
+            transf.scale = full_scale * anim.scale();
+        }
+    }
+}
+
 fn scale_animation(mut affected: Query<(&mut Transform, &ScaleAnimation)>, time: Res<Time>) {
     for (mut transf, anim) in affected.iter_mut() {
         let scale = 1.
@@ -140,43 +646,104 @@ fn scale_animation(mut affected: Query<(&mut Transform, &ScaleAnimation)>, time:
     }
 }
 
-fn spawn_tutorial(mut commands: Commands, sprites: Res<SpriteAssets>) {
-    commands
-        .spawn_bundle(SpriteBundle {
-            texture: sprites.reload_hint.clone(),
-            transform: Transform {
-                translation: Vec3::new(400., 300., 900.),
-                scale: Vec3::splat(0.5),
+/// Spawns a tutorial hint, which stays hidden until its trigger happens.
+pub fn spawn_hint(
+    commands: &mut Commands,
+    hint: &TutorialHint,
+    sprites: &SpriteAssets,
+    grid: &GridConfig,
+) -> Entity {
+    let mut translation = match hint.position {
+        HintPosition::Vertex(vertex) => vertex.to_world_pos_in(grid).translation,
+        HintPosition::World([x, y]) => Vec3::new(x, y, 0.),
+    };
+    let visibility = Visibility {
+        is_visible: hint.trigger == HintTrigger::OnLoad,
+    };
+
+    let mut entity = match hint.sprite {
+        HintSprite::Reload => {
+            translation.z = 900.;
+            let mut entity = commands.spawn_bundle(SpriteBundle {
+                texture: sprites.reload_hint.clone(),
+                transform: Transform {
+                    translation,
+                    scale: Vec3::splat(0.5),
+                    ..Default::default()
+                },
+                visibility,
                 ..Default::default()
-            },
-            // Will be made visible when first merging two tiles
-            visibility: Visibility { is_visible: false },
-            ..Default::default()
-        })
-        .insert(Name::new("Reload Hint"))
-        .insert(ReloadHint);
-
-    commands
-        .spawn_bundle(SpriteBundle {
-            texture: sprites.rotate_hint.clone(),
-            sprite: Sprite {
-                custom_size: Some(Vec2::splat(0.4 * TRIANGLE_SIDE)),
-                color: Color::rgba_u8(199, 172, 252, 230),
+            });
+            entity.insert(Name::new("Reload Hint")).insert(ReloadHint);
+            entity
+        }
+        HintSprite::Rotate => {
+            translation.z = 800.;
+            let mut entity = commands.spawn_bundle(SpriteBundle {
+                texture: sprites.rotate_hint.clone(),
+                sprite: Sprite {
+                    custom_size: Some(Vec2::splat(0.4 * TRIANGLE_SIDE)),
+                    color: Color::rgba_u8(199, 172, 252, 230),
+                    ..Default::default()
+                },
+                transform: Transform::from_translation(translation),
+                visibility,
                 ..Default::default()
-            },
-            transform: {
-                let mut transf = crate::tilemap::VertexCoord::new(0, 1).to_world_pos();
-                transf.translation.z = 800.;
-                transf
-            },
-            ..Default::default()
-        })
-        .insert(ScaleAnimation {
-            frequency: 0.2,
-            amplitude: 0.13,
-        })
-        .insert(Name::new("Rotation Hint"))
-        .insert(RotationHint);
+            });
+            entity
+                .insert(ScaleAnimation {
+                    frequency: 0.2,
+                    amplitude: 0.13,
+                })
+                .insert(Name::new("Rotation Hint"))
+                .insert(RotationHint);
+            entity
+        }
+    };
+    entity.insert(hint.clone()).id()
+}
+
+/// Makes all hints waiting for `trigger` visible.
+pub fn trigger_hints(hints: &mut Query<(&TutorialHint, &mut Visibility)>, trigger: HintTrigger) {
+    for (hint, mut visibility) in hints.iter_mut() {
+        if hint.trigger == trigger {
+            visibility.is_visible = true;
+        }
+    }
+}
+
+/// The texture and horizontal flip of a rotate hint asking for a rotation in `dir`.
+fn rotate_hint_sprite(
+    dir: RotationDir,
+    style: &RotateHintStyle,
+    sprites: &SpriteAssets,
+) -> (Handle<Image>, bool) {
+    let dir = if style.swap { dir.reversed() } else { dir };
+    match dir {
+        RotationDir::Clockwise => (sprites.rotate_hint.clone(), false),
+        RotationDir::CounterClockwise if style.mirror => (sprites.rotate_hint.clone(), true),
+        RotationDir::CounterClockwise => (sprites.rotate_hint_ccw.clone(), false),
+    }
+}
+
+fn rotate_hint_sprites(
+    style: Res<RotateHintStyle>,
+    sprites: Res<SpriteAssets>,
+    mut hints: Query<(
+        &TutorialHint,
+        ChangeTrackers<TutorialHint>,
+        &mut Handle<Image>,
+        &mut Sprite,
+    )>,
+) {
+    for (hint, tracker, mut texture, mut sprite) in hints.iter_mut() {
+        if hint.sprite != HintSprite::Rotate || !(style.is_changed() || tracker.is_changed()) {
+            continue;
+        }
+        let (new_texture, flip_x) = rotate_hint_sprite(hint.direction, &style, &sprites);
+        *texture = new_texture;
+        sprite.flip_x = flip_x;
+    }
 }
 
 /// Spawn the first level
@@ -192,32 +759,59 @@ fn initial_load(world: &mut World) {
         .resource_mut::<Assets<ColorMaterial>>()
         .add(ColorMaterial {
             color: Color::WHITE,
-            texture: Some(ruby_sprite),
+            texture: Some(ruby_sprite.clone()),
         });
     let grey_material = world
         .resource_mut::<Assets<ColorMaterial>>()
         .add(ColorMaterial {
             color: Color::WHITE,
-            texture: Some(grey_sprite),
+            texture: Some(grey_sprite.clone()),
+        });
+    let colored_materials = TILE_TINTS[1..]
+        .iter()
+        .map(|&tint| {
+            world
+                .resource_mut::<Assets<ColorMaterial>>()
+                .add(ColorMaterial {
+                    color: tint,
+                    texture: Some(grey_sprite.clone()),
+                })
+        })
+        .collect();
+    let ghost_material = world
+        .resource_mut::<Assets<ColorMaterial>>()
+        .add(ColorMaterial {
+            color: Color::rgba(1., 1., 1., 0.25),
+            texture: Some(ruby_sprite.clone()),
+        });
+    let blocked_ghost_material = world
+        .resource_mut::<Assets<ColorMaterial>>()
+        .add(ColorMaterial {
+            color: Color::rgba(1., 0.1, 0.1, 0.35),
+            texture: Some(ruby_sprite),
         });
     let assets = AssetHandles {
         triangle_mesh: meshes,
         triangle_material: ruby_material,
+        colored_materials,
         immovable_material: grey_material,
+        ghost_material,
+        blocked_ghost_material,
     };
-    // This needs to happen before spawn_level
+    // This needs to happen before load_level
     world.insert_resource(assets);
 
     // Get the default level from [`LevelInfo`]
-    let lvl = world.resource::<LevelInfo>().current;
-    spawn_level(world, lvl.to_string().as_str());
+    let lvl = world.resource::<LevelInfo>().current.to_string();
+    load_level(world, &lvl);
 }
 
-/// create a mesh for a flippable triangle. The two sides use UV 0..0.5 and 0.5..1.
-fn create_triangle_mesh(size: f32) -> Mesh {
+/// Position, normal and UV of the vertices of a flippable triangle. The two sides use UV 0..0.5
+/// and 0.5..1.
+fn triangle_vertices(size: f32) -> [([f32; 3], [f32; 3], [f32; 2]); 4] {
     // pos  , normal  , uv
     // x y z, nx ny nz, u v
-    let vertices = [
+    [
         ([0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 0.5]),
         ([size, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.5]),
         (
@@ -230,8 +824,15 @@ fn create_triangle_mesh(size: f32) -> Mesh {
             [0.0, 0.0, 1.0],
             [0.5, 1.0],
         ),
-    ];
-    let indices = Indices::U32(vec![0, 1, 2, 0, 3, 1]);
+    ]
+}
+
+const TRIANGLE_INDICES: [u32; 6] = [0, 1, 2, 0, 3, 1];
+
+/// create a mesh for a flippable triangle. The two sides use UV 0..0.5 and 0.5..1.
+fn create_triangle_mesh(size: f32) -> Mesh {
+    let vertices = triangle_vertices(size);
+    let indices = Indices::U32(TRIANGLE_INDICES.to_vec());
 
     let positions: Vec<_> = vertices.iter().map(|(p, _, _)| *p).collect();
     let normals: Vec<_> = vertices.iter().map(|(_, n, _)| *n).collect();
@@ -244,3 +845,413 @@ fn create_triangle_mesh(size: f32) -> Mesh {
     mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
     mesh
 }
+
+/// Bakes the triangles at `coords` into a single mesh in world space, which looks the same as
+/// spawning each of them with the triangle mesh. The vertices are at z = 0, so spawn it at
+/// [`crate::tilemap::TRIANGLE_Z`].
+pub fn build_clump_mesh(coords: &[TileCoord], grid: &GridConfig) -> Mesh {
+    let vertices = triangle_vertices(TRIANGLE_SIDE);
+
+    let mut positions: Vec<[f32; 3]> = Vec::with_capacity(coords.len() * vertices.len());
+    let mut normals: Vec<[f32; 3]> = Vec::with_capacity(coords.len() * vertices.len());
+    let mut uvs: Vec<[f32; 2]> = Vec::with_capacity(coords.len() * vertices.len());
+    let mut indices: Vec<u32> = Vec::with_capacity(coords.len() * TRIANGLE_INDICES.len());
+    for &position in coords {
+        // Down triangles are flipped, which also shows the other side of the texture
+        let mut transf = TriangleTile { position }.to_world_pos_in(grid);
+        // 2D meshes are sorted by the z of their entity, which has to carry the depth instead
+        transf.translation.z = 0.;
+        let offset = positions.len() as u32;
+        for (p, n, uv) in vertices {
+            positions.push(transf.mul_vec3(Vec3::from(p)).into());
+            normals.push(n);
+            uvs.push(uv);
+        }
+        indices.extend(TRIANGLE_INDICES.iter().map(|i| offset + i));
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh
+}
+
+#[test]
+fn test_rotate_hint_sprite() {
+    use bevy::asset::HandleId;
+
+    let sprites = SpriteAssets {
+        rotate_hint: Handle::weak(HandleId::random::<Image>()),
+        rotate_hint_ccw: Handle::weak(HandleId::random::<Image>()),
+        ..Default::default()
+    };
+    let cw = (sprites.rotate_hint.clone(), false);
+    let ccw = (sprites.rotate_hint_ccw.clone(), false);
+    let mirrored = (sprites.rotate_hint.clone(), true);
+
+    let style = RotateHintStyle::default();
+    assert_eq!(
+        rotate_hint_sprite(RotationDir::Clockwise, &style, &sprites),
+        cw
+    );
+    assert_eq!(
+        rotate_hint_sprite(RotationDir::CounterClockwise, &style, &sprites),
+        ccw
+    );
+
+    let style = RotateHintStyle {
+        mirror: true,
+        swap: false,
+    };
+    assert_eq!(
+        rotate_hint_sprite(RotationDir::Clockwise, &style, &sprites),
+        cw
+    );
+    assert_eq!(
+        rotate_hint_sprite(RotationDir::CounterClockwise, &style, &sprites),
+        mirrored
+    );
+
+    let style = RotateHintStyle {
+        mirror: false,
+        swap: true,
+    };
+    assert_eq!(
+        rotate_hint_sprite(RotationDir::Clockwise, &style, &sprites),
+        ccw
+    );
+    assert_eq!(
+        rotate_hint_sprite(RotationDir::CounterClockwise, &style, &sprites),
+        cw
+    );
+}
+
+/// A world with the resources the [`rune_system`] needs and a stage running it.
+#[cfg(test)]
+fn rune_test_world(level: LevelInfo, settings: Settings) -> (World, SystemStage) {
+    let mut world = World::new();
+    world.insert_resource(level);
+    world.insert_resource(Playlist::default());
+    world.insert_resource(RuneFeedback::default());
+    world.insert_resource(WinAnimationConfig::default());
+    world.insert_resource(settings);
+    world.insert_resource(Input::<KeyCode>::default());
+    world.insert_resource(Time::default());
+    world.insert_resource(Events::<BoardDirty>::default());
+    world.insert_resource(Events::<LevelCompleted>::default());
+    world.insert_resource(Events::<CameraShake>::default());
+    world.insert_resource(GridConfig::default());
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(rune_system)
+        .add_system(advance_level.after(rune_system));
+    (world, stage)
+}
+
+#[test]
+fn test_board_dirty() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, mut stage) = rune_test_world(LevelInfo::default(), Settings::default());
+
+    let position = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let rune = world
+        .spawn()
+        .insert(RuneTile {
+            position,
+            rune_index: 0,
+            ..Default::default()
+        })
+        .insert(TileColor(1))
+        .insert(TextureAtlasSprite::default())
+        .insert(Transform::default())
+        .id();
+    // A plain ruby on the rune, which doesn't light it yet
+    world.spawn().insert(TriangleTile { position });
+
+    stage.run(&mut world);
+    assert!(world
+        .resource::<LevelInfo>()
+        .win_animation_progress
+        .is_none());
+
+    // Recoloring the rune, e.g. in the editor, doesn't change any tile
+    world.entity_mut(rune).insert(TileColor(0));
+    stage.run(&mut world);
+    assert!(world
+        .resource::<LevelInfo>()
+        .win_animation_progress
+        .is_none());
+
+    world.resource_mut::<Events<BoardDirty>>().send(BoardDirty);
+    stage.run(&mut world);
+    assert!(world
+        .resource::<LevelInfo>()
+        .win_animation_progress
+        .is_some());
+
+    let events = world.resource::<Events<LevelCompleted>>();
+    let mut reader = events.get_reader();
+    let completed: Vec<(LevelId, u32)> = reader
+        .iter(events)
+        .map(|completed| (completed.level.clone(), completed.moves))
+        .collect();
+    assert_eq!(completed, vec![(LevelId::Builtin(1), 0)]);
+}
+
+#[test]
+fn test_spawn_animation() {
+    use crate::tilemap::TriangleOrient;
+    use std::time::{Duration, Instant};
+
+    let mut world = World::new();
+    let start = Instant::now();
+    let mut time = Time::default();
+    time.update_with_instant(start);
+    world.insert_resource(time);
+    world.insert_resource(GridConfig::default());
+
+    let tile = TriangleTile {
+        position: (VertexCoord::ZERO, TriangleOrient::PointingDown),
+    };
+    let full_scale = tile.to_world_pos().scale;
+    let triangle = world
+        .spawn()
+        .insert(tile)
+        .insert(Transform::default())
+        .insert(SpawnAnimation::with_delay(0.1))
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(spawn_animation);
+    let mut run_at = |world: &mut World, millis: u64| {
+        world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_millis(millis));
+        stage.run(world);
+        world.get::<Transform>(triangle).unwrap().scale
+    };
+
+    // Still waiting for its turn
+    assert_eq!(run_at(&mut world, 50), Vec3::ZERO);
+    // Overshoots near the end
+    let scale = run_at(&mut world, 350);
+    assert!(scale.x > full_scale.x && scale.y < full_scale.y);
+    assert_eq!(run_at(&mut world, 450), full_scale);
+    assert!(world.get::<SpawnAnimation>(triangle).is_none());
+}
+
+#[test]
+fn test_rune_colors() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, mut stage) = rune_test_world(LevelInfo::default(), Settings::default());
+
+    let position = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    world
+        .spawn()
+        .insert(RuneTile {
+            position,
+            rune_index: 0,
+            ..Default::default()
+        })
+        .insert(TileColor(1))
+        .insert(TextureAtlasSprite::default())
+        .insert(Transform::default());
+    // A plain ruby doesn't light a colored rune
+    let triangle = world
+        .spawn()
+        .insert(TriangleTile { position })
+        .insert(TileColor(0))
+        .id();
+    world.spawn().push_children(&[triangle]);
+
+    stage.run(&mut world);
+    assert!(world
+        .resource::<LevelInfo>()
+        .win_animation_progress
+        .is_none());
+
+    world.entity_mut(triangle).insert(TileColor(1));
+    world.resource_mut::<Events<BoardDirty>>().send(BoardDirty);
+    stage.run(&mut world);
+    assert!(world
+        .resource::<LevelInfo>()
+        .win_animation_progress
+        .is_some());
+}
+
+#[test]
+fn test_rune_state() {
+    use crate::tilemap::TriangleOrient;
+
+    let rune = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let adjacent = (VertexCoord::ZERO, TriangleOrient::PointingDown);
+    // Two edges away from the rune
+    let near = (VertexCoord::new(1, -1), TriangleOrient::PointingUp);
+
+    let occupied: HashSet<TileCoord> = [adjacent].into_iter().collect();
+    assert_eq!(rune_state(&[rune], &occupied, 1), RuneState::Almost);
+    assert_eq!(rune_state(&[rune], &occupied, 0), RuneState::Unlit);
+    assert_eq!(rune_state(&[adjacent], &occupied, 1), RuneState::Lit);
+
+    let occupied: HashSet<TileCoord> = [near].into_iter().collect();
+    assert_eq!(rune_state(&[rune], &occupied, 1), RuneState::Unlit);
+    assert_eq!(rune_state(&[rune], &occupied, 2), RuneState::Almost);
+
+    // Larger runes light up step by step
+    let cells = [rune, adjacent, near];
+    let partial = rune_state(&cells, &occupied, 1);
+    assert_eq!(
+        partial,
+        RuneState::Partial {
+            filled: 1,
+            total: 3
+        }
+    );
+    // There is no frame between unlit and lit yet
+    assert_eq!(partial.frame(), 0);
+    assert_eq!(
+        RuneState::Partial {
+            filled: 2,
+            total: 3
+        }
+        .frame(),
+        0
+    );
+    let occupied: HashSet<TileCoord> = cells.into_iter().collect();
+    assert_eq!(rune_state(&cells, &occupied, 1), RuneState::Lit);
+
+    // A single cell rune is only lit by its own cell
+    let single = RuneTile {
+        position: rune,
+        ..Default::default()
+    };
+    assert_eq!(single.required_cells(), &[rune]);
+    assert_eq!(RuneState::Unlit.frame(), 0);
+    assert_eq!(RuneState::Lit.frame(), RUNE_FRAMES - 1);
+}
+
+#[test]
+fn test_build_clump_mesh() {
+    use crate::tilemap::TriangleOrient;
+
+    let coords = [
+        (VertexCoord::ZERO, TriangleOrient::PointingUp),
+        (VertexCoord::ZERO, TriangleOrient::PointingDown),
+        (VertexCoord::new(2, -1), TriangleOrient::PointingUp),
+    ];
+    let mesh = build_clump_mesh(&coords, &GridConfig::default());
+    assert_eq!(mesh.count_vertices(), 3 * 4);
+    assert_eq!(mesh.indices().unwrap().len(), 3 * 6);
+    assert_eq!(mesh.indices().unwrap().iter().max(), Some(3 * 4 - 1));
+
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(bevy::render::mesh::VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+        _ => panic!("The mesh has no positions"),
+    };
+    assert!(positions.iter().all(|p| p[2] == 0.));
+
+    let empty = build_clump_mesh(&[], &GridConfig::default());
+    assert_eq!(empty.count_vertices(), 0);
+}
+
+#[test]
+fn test_win_anim_stagger() {
+    let config = WinAnimationConfig {
+        final_scale: 3.,
+        stagger: 0.2,
+        duration: 0.5,
+        easing: WinEasing::Linear,
+    };
+    let runes = 3;
+    assert_eq!(config.scale_at(0, 0.), 1.);
+
+    // The first rune is done while the last one hasn't even started
+    assert_eq!(config.scale_at(0, 0.5), 3.);
+    assert!(config.scale_at(1, 0.5) < 3.);
+    assert_eq!(config.scale_at(2, 0.3), 1.);
+
+    let total = config.total_duration(runes);
+    assert!((total - 0.9).abs() < 1e-6);
+    assert!(config.scale_at(runes - 1, total - 0.1) < 3.);
+    assert!((0..runes).all(|i| (config.scale_at(i, total) - 3.).abs() < 1e-4));
+}
+
+#[test]
+fn test_win_easing() {
+    for easing in [WinEasing::Linear, WinEasing::Smooth, WinEasing::EaseOut] {
+        assert_eq!(easing.apply(-1.), 0.);
+        assert_eq!(easing.apply(0.), 0.);
+        assert_eq!(easing.apply(1.), 1.);
+        assert_eq!(easing.apply(2.), 1.);
+    }
+    assert!(WinEasing::Smooth.apply(0.25) < WinEasing::Linear.apply(0.25));
+    assert!(WinEasing::EaseOut.apply(0.25) > WinEasing::Linear.apply(0.25));
+
+    // Without a duration the animation is over right away
+    let instant = WinAnimationConfig {
+        stagger: 0.,
+        duration: 0.,
+        ..Default::default()
+    };
+    assert_eq!(instant.total_duration(5), 0.);
+}
+
+#[test]
+fn test_wait_for_confirm() {
+    let level = LevelInfo {
+        win_animation_progress: Some(10.),
+        ..Default::default()
+    };
+    let settings = Settings {
+        auto_advance: false,
+        ..Default::default()
+    };
+    let (mut world, mut stage) = rune_test_world(level, settings);
+    stage.run(&mut world);
+    stage.run(&mut world);
+    let level = world.resource::<LevelInfo>();
+    assert_eq!(level.current, LevelId::Builtin(1));
+    assert!(level.awaiting_confirm);
+    assert!(!level.should_reload);
+
+    world
+        .resource_mut::<Input<KeyCode>>()
+        .press(KeyCode::Return);
+    stage.run(&mut world);
+    let level = world.resource::<LevelInfo>();
+    assert_eq!(level.current, LevelId::Builtin(2));
+    assert!(!level.awaiting_confirm);
+    assert!(level.should_reload);
+}
+
+#[test]
+fn test_playlist() {
+    assert_eq!(LevelId::from_name("2"), LevelId::Builtin(2));
+    assert_eq!(LevelId::from_name("99"), LevelId::Named(String::from("99")));
+    assert_eq!(
+        LevelId::from_name("castle"),
+        LevelId::Named(String::from("castle"))
+    );
+    assert_eq!(LevelId::Named(String::from("castle")).to_string(), "castle");
+
+    let castle = LevelId::Named(String::from("castle"));
+    let playlist = Playlist(vec![
+        LevelId::Builtin(1),
+        castle.clone(),
+        LevelId::Builtin(3),
+    ]);
+    assert_eq!(playlist.next(&LevelId::Builtin(1)), castle);
+    assert_eq!(playlist.next(&castle), LevelId::Builtin(3));
+    assert!(playlist.is_last(&LevelId::Builtin(3)));
+    assert_eq!(playlist.next(&LevelId::Builtin(3)), LevelId::end());
+    assert_eq!(playlist.next(&LevelId::Builtin(2)), LevelId::end());
+
+    let builtin = Playlist::default();
+    assert_eq!(builtin.next(&LevelId::Builtin(1)), LevelId::Builtin(2));
+    assert!(builtin.is_last(&LevelId::Builtin(end_level_index() - 1)));
+}
@@ -1,39 +1,128 @@
-use bevy::{prelude::*, render::camera::RenderTarget, sprite::MaterialMesh2dBundle};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    render::camera::RenderTarget,
+    sprite::{Anchor, MaterialMesh2dBundle},
+    utils::HashSet,
+};
 use bevy_point_selection::{viewport_to_world, Selectable};
-use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    level::{BoardDirty, SpawnAnimation},
+    rotation::{reparented_transform, MouseActions},
+    savegame::{read_json, write_json},
     tilemap::{
-        FromWorldPosition, Immovable, RuneTile, TileCoord, TransformInWorld, TriangleTile,
+        ClumpRoot, FromWorldPosition, GridConfig, Immovable, RuneTile, TileColor, TileCoord,
+        TileVertices, TransformInWorld, TranslateTile, TriangleOrient, TriangleTile, VertexCoord,
         TRIANGLE_SIDE, X_DIR, Y_DIR,
     },
-    AssetHandles, GameState, SpriteAssets,
+    AssetHandles, GameState, InputSuppressed, SpriteAssets,
 };
 
-const SELECTABLE_RADIUS: f32 = 0.25 * TRIANGLE_SIDE;
+pub const SELECTABLE_RADIUS: f32 = 0.25 * TRIANGLE_SIDE;
+/// Number of different rune glyphs in the rune sprite sheet
+pub const RUNE_GLYPHS: usize = 5;
+/// Frames of each glyph in the rune sprite sheet, from unlit to lit
+pub const RUNE_FRAMES: usize = 2;
+pub(crate) const PREFS_NAME: &str = "editor-prefs";
+/// Offsets of the vertex handles of a triangle relative to its left vertex
+const HANDLE_OFFSETS: [Vec2; 3] = [Vec2::ZERO, X_DIR, Y_DIR];
+const GHOST_RUNE_COLOR: Color = Color::rgba(1., 1., 1., 0.5);
+const BLOCKED_GHOST_RUNE_COLOR: Color = Color::rgba(1., 0.1, 0.1, 0.6);
+const REGION_COLOR: Color = Color::rgba(0.3, 0.5, 1.0, 0.2);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum BuilderState {
     Triangles,
     Immovables,
     Runes,
 }
 
+/// Whether tiles can be placed with the editor, which is only the case in the sandbox.
+#[derive(Default)]
+pub struct EditorEnabled(pub bool);
+
+/// Editor settings, which are persisted between runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorPrefs {
+    pub mode: BuilderState,
+    /// Glyph of newly placed runes
+    pub rune_index: usize,
+    /// Selection radius of the vertices of newly spawned triangles
+    pub handle_radius: f32,
+}
+
+impl Default for EditorPrefs {
+    fn default() -> Self {
+        Self {
+            mode: BuilderState::Triangles,
+            rune_index: 0,
+            handle_radius: SELECTABLE_RADIUS,
+        }
+    }
+}
+
+impl EditorPrefs {
+    /// Loads the saved preferences or the defaults if there are none.
+    pub fn load() -> Self {
+        Self::load_from(PREFS_NAME)
+    }
+
+    fn load_from(name: &str) -> Self {
+        match read_json(name) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                warn!("Failed to deserialize editor preferences: {:?}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(data) => write_json(data, PREFS_NAME),
+            Err(e) => warn!("Failed to serialize editor preferences: {:?}", e),
+        }
+    }
+}
+
 /// Dynamically add Triangles, Immovables and Runes with a mouseclick.
-/// Press `A` to select Triangles, `S` for Immovables and `D` for Runes.
-/// Then hold Left Control while clicking on a tile to place it.
+/// Press `A` to select Triangles, `S` for Immovables and `D` for Runes, `[` and `]` choose the
+/// glyph of the next rune. Press `F` to cycle whether new tiles point up, down or towards the
+/// cursor. Then hold Left Control while clicking on a tile to place it, or drag to place a tile on
+/// every tile the cursor passes. While it's held, a ghost shows where the tile would land, red if
+/// it collides with another tile. Colliding tiles aren't placed. Hold `Left Alt` and drag to copy
+/// all tiles in a rectangle, then `Left Alt` + right click pastes them at the cursor.
+/// `Left Control` + `T` toggles whether the closest rune requires the tile under the cursor.
 ///
 /// Use the [`crate::savegame::MagnateSaveGamePlugin`] to save the levels.
 pub struct MagnateLevelEditorPlugin;
 
 impl Plugin for MagnateLevelEditorPlugin {
     fn build(&self, app: &mut App) {
+        let prefs = EditorPrefs::load();
         app.add_system_set(
             SystemSet::on_update(GameState::Next)
                 .with_system(builder)
-                .with_system(update_builder_state),
+                .with_system(editor_ghost)
+                .with_system(update_builder_state)
+                .with_system(cycle_rune_glyph)
+                .with_system(cycle_orient_override)
+                .with_system(update_tool_label)
+                .with_system(region_copy_paste)
+                .with_system(split_triangle)
+                .with_system(toggle_required_cell)
+                .with_system(save_editor_prefs)
+                .with_system(debug_handles),
         )
-        .add_state(BuilderState::Triangles);
+        .add_state(prefs.mode)
+        .insert_resource(prefs)
+        .init_resource::<EditorEnabled>()
+        .init_resource::<PaintStroke>()
+        .init_resource::<OrientOverride>()
+        .init_resource::<RegionClipboard>();
     }
 }
 
@@ -49,18 +138,129 @@ fn update_builder_state(mut state: ResMut<State<BuilderState>>, keys: Res<Input<
     };
 }
 
+/// The tile placed last while dragging with the mouse button held, so every tile is only painted
+/// once when the cursor lingers.
+#[derive(Debug, Default, Clone)]
+pub struct PaintStroke {
+    pub last: Option<TileCoord>,
+}
+
+impl PaintStroke {
+    /// Whether a tile should be placed on `coord`, i.e. the cursor moved onto a new tile.
+    pub fn paint(&mut self, coord: TileCoord) -> bool {
+        if self.last == Some(coord) {
+            return false;
+        }
+        self.last = Some(coord);
+        true
+    }
+
+    pub fn end(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Orientation of the tiles placed by the [`builder`] instead of the one under the cursor. The
+/// tile keeps its vertex, so it's the other half of the cell.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OrientOverride(pub Option<TriangleOrient>);
+
+impl OrientOverride {
+    pub fn apply(&self, coord: TileCoord) -> TileCoord {
+        (coord.0, self.0.unwrap_or(coord.1))
+    }
+}
+
+/// Cycles the [`OrientOverride`] between the cursor, up and down with `F`.
+fn cycle_orient_override(keys: Res<Input<KeyCode>>, mut orient: ResMut<OrientOverride>) {
+    if !keys.just_pressed(KeyCode::F) {
+        return;
+    }
+    orient.0 = match orient.0 {
+        None => Some(TriangleOrient::PointingUp),
+        Some(TriangleOrient::PointingUp) => Some(TriangleOrient::PointingDown),
+        Some(TriangleOrient::PointingDown) => None,
+    };
+    info!("Editor orientation: {:?}", orient.0);
+}
+
+/// Text showing the selected [`BuilderState`], hidden until the editor is used.
+#[derive(Component, Debug, Default, Clone)]
+pub struct ToolLabel;
+
+/// Shows the selected tool once `Left Control` was held while the editor is enabled.
+fn update_tool_label(
+    keys: Res<Input<KeyCode>>,
+    enabled: Res<EditorEnabled>,
+    state: Res<State<BuilderState>>,
+    orient: Res<OrientOverride>,
+    mut used: Local<bool>,
+    mut labels: Query<(&mut Text, &mut Visibility), With<ToolLabel>>,
+) {
+    if keys.pressed(KeyCode::LControl) && enabled.0 {
+        *used = true;
+    }
+    let visible = *used && enabled.0;
+    let value = match orient.0 {
+        Some(orient) => format!("{:?}, {:?}", state.current(), orient),
+        None => format!("{:?}", state.current()),
+    };
+    for (mut text, mut visibility) in labels.iter_mut() {
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+        if let Some(section) = text.sections.first_mut() {
+            if section.value != value {
+                section.value = value.clone();
+            }
+        }
+    }
+}
+
+/// Picks the glyph of the next rune with `[` and `]`.
+fn cycle_rune_glyph(keys: Res<Input<KeyCode>>, mut prefs: ResMut<EditorPrefs>) {
+    let current = prefs.rune_index % RUNE_GLYPHS;
+    if keys.just_pressed(KeyCode::RBracket) {
+        prefs.rune_index = (current + 1) % RUNE_GLYPHS;
+    } else if keys.just_pressed(KeyCode::LBracket) {
+        prefs.rune_index = (current + RUNE_GLYPHS - 1) % RUNE_GLYPHS;
+    }
+}
+
+/// Saves the [`EditorPrefs`] whenever they change.
+fn save_editor_prefs(state: Res<State<BuilderState>>, mut prefs: ResMut<EditorPrefs>) {
+    if prefs.mode != *state.current() {
+        prefs.mode = *state.current();
+    }
+    if prefs.is_changed() && !prefs.is_added() {
+        prefs.save();
+    }
+}
+
 fn builder(
     commands: Commands,
     keys: Res<Input<KeyCode>>,
     mouse_btn: Res<Input<MouseButton>>,
+    actions: Res<MouseActions>,
+    suppressed: Res<InputSuppressed>,
+    enabled: Res<EditorEnabled>,
     state: Res<State<BuilderState>>,
+    prefs: Res<EditorPrefs>,
     sprites: Res<SpriteAssets>,
     assets: Res<AssetHandles>,
-    windows: Res<Windows>,
-    cam: Query<(&Camera, &GlobalTransform)>,
+    pointer: EditorPointer,
+    mut stroke: ResMut<PaintStroke>,
+    triangles: Query<(&TriangleTile, Option<&Immovable>)>,
+    runes: Query<&RuneTile>,
+    orient: Res<OrientOverride>,
 ) {
+    if suppressed.any() || !enabled.0 {
+        stroke.end();
+        return;
+    }
     builder_fallable(
-        commands, keys, mouse_btn, state, sprites, assets, windows, cam,
+        commands, keys, mouse_btn, actions, state, prefs, sprites, assets, pointer, stroke,
+        triangles, runes, orient,
     );
 }
 
@@ -68,124 +268,954 @@ fn builder_fallable(
     mut commands: Commands,
     keys: Res<Input<KeyCode>>,
     mouse_btn: Res<Input<MouseButton>>,
+    actions: Res<MouseActions>,
     state: Res<State<BuilderState>>,
+    prefs: Res<EditorPrefs>,
     sprites: Res<SpriteAssets>,
     assets: Res<AssetHandles>,
-    windows: Res<Windows>,
-    cam: Query<(&Camera, &GlobalTransform)>,
+    pointer: EditorPointer,
+    mut stroke: ResMut<PaintStroke>,
+    triangles: Query<(&TriangleTile, Option<&Immovable>)>,
+    runes: Query<&RuneTile>,
+    orient: Res<OrientOverride>,
 ) -> Option<()> {
-    if !keys.pressed(KeyCode::LControl) {
+    if !keys.pressed(KeyCode::LControl) || !mouse_btn.pressed(actions.select) {
+        stroke.end();
         return None;
     }
-    if !mouse_btn.any_just_pressed([MouseButton::Left, MouseButton::Right]) {
-        return None;
+    if mouse_btn.just_pressed(actions.select) {
+        stroke.end();
     }
 
-    let (camera, cam_transform) = cam.get_single().ok()?;
-    let window_id = match camera.target {
-        RenderTarget::Window(id) => id,
-        _ => return None,
-    };
-    let window = windows.get(window_id)?;
-    let cursor_position = viewport_to_world(camera, cam_transform, window)?;
-    let coord = FromWorldPosition::from_world_pos(cursor_position);
+    let cursor = pointer.world_pos()?;
+    let coord = orient.apply(FromWorldPosition::from_world_pos_in(cursor, &pointer.grid));
+    if !stroke.paint(coord) {
+        return None;
+    }
+    let mode = *state.current();
+    if OccupiedTiles::collect(&triangles, &runes).blocks(mode, coord) {
+        info!(
+            "Not placing {:?} on {:?}, the tile is occupied",
+            mode, coord
+        );
+        return None;
+    }
 
-    match state.current() {
+    match mode {
         BuilderState::Triangles => {
             let tri = spawn_solo_triangle(
                 &mut commands,
                 coord,
+                &pointer.grid,
                 assets.triangle_mesh.clone(),
                 assets.triangle_material.clone(),
+                prefs.handle_radius,
+                0.,
             );
-            commands
-                .spawn()
-                .insert_bundle(TransformBundle::default())
-                .insert_bundle(VisibilityBundle::default())
-                .add_child(tri);
+            spawn_clump(&mut commands, &[tri]);
         }
         BuilderState::Immovables => {
             spawn_immovable(
                 &mut commands,
                 coord,
+                &pointer.grid,
                 assets.triangle_mesh.clone(),
                 assets.immovable_material.clone(),
+                0.,
             );
         }
         BuilderState::Runes => {
-            spawn_rune(&mut commands, coord, sprites.runes.clone());
+            spawn_rune(
+                &mut commands,
+                RuneTile {
+                    position: coord,
+                    rune_index: prefs.rune_index % RUNE_GLYPHS,
+                    ..Default::default()
+                },
+                &pointer.grid,
+                sprites.runes.clone(),
+                0.,
+            );
         }
     };
 
     Some(())
 }
 
+/// The cursor and the grid it points at, shared by the systems placing tiles.
+#[derive(SystemParam)]
+pub struct EditorPointer<'w, 's> {
+    windows: Res<'w, Windows>,
+    cameras: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    grid: Res<'w, GridConfig>,
+}
+
+impl<'w, 's> EditorPointer<'w, 's> {
+    /// World position of the cursor, if it's within the window.
+    fn world_pos(&self) -> Option<Vec2> {
+        let (camera, cam_transform) = self.cameras.get_single().ok()?;
+        let window_id = match camera.target {
+            RenderTarget::Window(id) => id,
+            _ => return None,
+        };
+        let window = self.windows.get(window_id)?;
+        viewport_to_world(camera, cam_transform, window)
+    }
+}
+
+/// Translucent preview of the tile the [`builder`] would place under the cursor. There is one
+/// ghost for triangles and immovables and one with a sprite for runes.
+#[derive(Component, Debug, Clone, Default)]
+pub struct EditorGhost {
+    /// Placing the tile would collide with an existing one
+    pub blocked: bool,
+}
+
+/// Existing tiles the [`builder`] could collide with.
+#[derive(Default)]
+struct OccupiedTiles {
+    triangles: HashSet<TileCoord>,
+    immovables: HashSet<TileCoord>,
+    runes: HashSet<TileCoord>,
+}
+
+impl OccupiedTiles {
+    fn collect(
+        triangles: &Query<(&TriangleTile, Option<&Immovable>)>,
+        runes: &Query<&RuneTile>,
+    ) -> Self {
+        let mut occupied = OccupiedTiles::default();
+        for (tile, immovable) in triangles.iter() {
+            match immovable {
+                Some(_) => occupied.immovables.insert(tile.position),
+                None => occupied.triangles.insert(tile.position),
+            };
+        }
+        occupied
+            .runes
+            .extend(runes.iter().map(|rune| rune.position));
+        occupied
+    }
+
+    fn add(&mut self, tile: &CopiedTile) {
+        match *tile {
+            CopiedTile::Triangle(coord, _) => self.triangles.insert(coord),
+            CopiedTile::Immovable(coord) => self.immovables.insert(coord),
+            CopiedTile::Rune(coord, _, _) => self.runes.insert(coord),
+        };
+    }
+
+    /// Whether placing a tile of `mode` on `coord` collides, the same as the checks of
+    /// [`crate::savegame::validate_savegame`]. Triangles may cover runes, that's how they are lit.
+    fn blocks(&self, mode: BuilderState, coord: TileCoord) -> bool {
+        let solid = self.triangles.contains(&coord) || self.immovables.contains(&coord);
+        match mode {
+            BuilderState::Triangles => solid,
+            BuilderState::Immovables => solid || self.runes.contains(&coord),
+            BuilderState::Runes => self.runes.contains(&coord) || self.immovables.contains(&coord),
+        }
+    }
+}
+
+/// Moves the [`EditorGhost`] of the current [`BuilderState`] to the tile under the cursor while
+/// `Left Control` is held, and hides the ghosts otherwise.
+fn editor_ghost(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    suppressed: Res<InputSuppressed>,
+    enabled: Res<EditorEnabled>,
+    state: Res<State<BuilderState>>,
+    prefs: Res<EditorPrefs>,
+    sprites: Res<SpriteAssets>,
+    assets: Res<AssetHandles>,
+    pointer: EditorPointer,
+    triangles: Query<(&TriangleTile, Option<&Immovable>)>,
+    runes: Query<&RuneTile>,
+    orient: Res<OrientOverride>,
+    mut ghosts: Query<(
+        &mut EditorGhost,
+        &mut Transform,
+        &mut Visibility,
+        Option<&mut Handle<ColorMaterial>>,
+        Option<&mut TextureAtlasSprite>,
+    )>,
+) {
+    if ghosts.is_empty() {
+        spawn_editor_ghosts(&mut commands, &assets, &sprites);
+        return;
+    }
+
+    let active = keys.pressed(KeyCode::LControl) && !suppressed.any() && enabled.0;
+    let target: Option<TileCoord> = if active {
+        pointer
+            .world_pos()
+            .map(|pos| orient.apply(FromWorldPosition::from_world_pos_in(pos, &pointer.grid)))
+    } else {
+        None
+    };
+    let mode = *state.current();
+    let blocked = target.map_or(false, |coord| {
+        OccupiedTiles::collect(&triangles, &runes).blocks(mode, coord)
+    });
+
+    for (mut ghost, mut transf, mut visibility, material, sprite) in ghosts.iter_mut() {
+        let is_rune_ghost = sprite.is_some();
+        let coord = match target {
+            Some(coord) if is_rune_ghost == (mode == BuilderState::Runes) => coord,
+            _ => {
+                if visibility.is_visible {
+                    visibility.is_visible = false;
+                }
+                continue;
+            }
+        };
+        visibility.is_visible = true;
+        ghost.blocked = blocked;
+
+        *transf = if is_rune_ghost {
+            RuneTile {
+                position: coord,
+                rune_index: prefs.rune_index,
+                ..Default::default()
+            }
+            .to_world_pos_in(&pointer.grid)
+        } else {
+            TriangleTile { position: coord }.to_world_pos_in(&pointer.grid)
+        };
+        // Above the tile it previews
+        transf.translation.z += 1.;
+        if let Some(mut material) = material {
+            *material = if blocked {
+                assets.blocked_ghost_material.clone()
+            } else {
+                assets.ghost_material.clone()
+            };
+        }
+        if let Some(mut sprite) = sprite {
+            sprite.index = (prefs.rune_index % RUNE_GLYPHS) * RUNE_FRAMES;
+            sprite.color = if blocked {
+                BLOCKED_GHOST_RUNE_COLOR
+            } else {
+                GHOST_RUNE_COLOR
+            };
+        }
+    }
+}
+
+/// A tile copied by [`region_copy_paste`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopiedTile {
+    Triangle(TileCoord, TileColor),
+    Immovable(TileCoord),
+    /// With its glyph
+    Rune(TileCoord, usize, TileColor),
+}
+
+impl CopiedTile {
+    fn coord(&self) -> TileCoord {
+        match *self {
+            CopiedTile::Triangle(coord, _)
+            | CopiedTile::Immovable(coord)
+            | CopiedTile::Rune(coord, _, _) => coord,
+        }
+    }
+
+    fn mode(&self) -> BuilderState {
+        match self {
+            CopiedTile::Triangle(..) => BuilderState::Triangles,
+            CopiedTile::Immovable(..) => BuilderState::Immovables,
+            CopiedTile::Rune(..) => BuilderState::Runes,
+        }
+    }
+
+    fn translated(&self, delta: VertexCoord) -> Self {
+        match *self {
+            CopiedTile::Triangle(coord, color) => {
+                CopiedTile::Triangle(coord.translated(delta), color)
+            }
+            CopiedTile::Immovable(coord) => CopiedTile::Immovable(coord.translated(delta)),
+            CopiedTile::Rune(coord, glyph, color) => {
+                CopiedTile::Rune(coord.translated(delta), glyph, color)
+            }
+        }
+    }
+}
+
+/// Tiles copied with `Left Alt` + drag, relative to the vertex where the drag started.
+#[derive(Debug, Default, Clone)]
+pub struct RegionClipboard {
+    /// World position where the current drag started
+    drag_start: Option<Vec2>,
+    pub tiles: Vec<CopiedTile>,
+}
+
+/// The translucent rectangle shown while dragging a region.
+#[derive(Component, Debug, Default, Clone)]
+pub struct RegionMarker;
+
+/// The tiles whose center is within the rectangle of `corners`, relative to `anchor`.
+fn copy_region(
+    corners: (Vec2, Vec2),
+    anchor: VertexCoord,
+    tiles: impl IntoIterator<Item = CopiedTile>,
+    grid: &GridConfig,
+) -> Vec<CopiedTile> {
+    let (min, max) = (corners.0.min(corners.1), corners.0.max(corners.1));
+    tiles
+        .into_iter()
+        .filter(|tile| {
+            let center = tile.coord().world_center_in(grid);
+            center.cmpge(min).all() && center.cmple(max).all()
+        })
+        .map(|tile| tile.translated(-anchor))
+        .collect()
+}
+
+/// Copies the tiles in the rectangle dragged with `Left Alt` held and pastes them with
+/// `Left Alt` + right click. Pasted tiles which would collide are skipped.
+fn region_copy_paste(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mouse_btn: Res<Input<MouseButton>>,
+    actions: Res<MouseActions>,
+    suppressed: Res<InputSuppressed>,
+    enabled: Res<EditorEnabled>,
+    prefs: Res<EditorPrefs>,
+    sprites: Res<SpriteAssets>,
+    assets: Res<AssetHandles>,
+    pointer: EditorPointer,
+    mut clipboard: ResMut<RegionClipboard>,
+    triangles: Query<(&TriangleTile, Option<&Immovable>, Option<&TileColor>)>,
+    runes: Query<(&RuneTile, Option<&TileColor>)>,
+    mut markers: Query<(&mut Sprite, &mut Transform, &mut Visibility), With<RegionMarker>>,
+) {
+    if markers.is_empty() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: REGION_COLOR,
+                    ..Default::default()
+                },
+                visibility: Visibility { is_visible: false },
+                ..Default::default()
+            })
+            .insert(RegionMarker)
+            .insert(Name::new("Region Marker"));
+    }
+
+    let active = keys.pressed(KeyCode::LAlt) && !suppressed.any() && enabled.0;
+    let cursor = match pointer.world_pos() {
+        Some(cursor) if active => cursor,
+        _ => {
+            clipboard.drag_start = None;
+            for (_, _, mut visibility) in markers.iter_mut() {
+                if visibility.is_visible {
+                    visibility.is_visible = false;
+                }
+            }
+            return;
+        }
+    };
+    let all_tiles = || {
+        let triangles = triangles
+            .iter()
+            .map(|(tile, immovable, color)| match immovable {
+                Some(_) => CopiedTile::Immovable(tile.position),
+                None => CopiedTile::Triangle(tile.position, color.copied().unwrap_or_default()),
+            });
+        let runes = runes.iter().map(|(rune, color)| {
+            CopiedTile::Rune(
+                rune.position,
+                rune.rune_index,
+                color.copied().unwrap_or_default(),
+            )
+        });
+        triangles.chain(runes)
+    };
+
+    if mouse_btn.just_pressed(actions.select) {
+        clipboard.drag_start = Some(cursor);
+    }
+    if let Some(start) = clipboard.drag_start {
+        if mouse_btn.just_released(actions.select) {
+            let anchor = VertexCoord::from_world_pos_in(start, &pointer.grid);
+            clipboard.tiles = copy_region((start, cursor), anchor, all_tiles(), &pointer.grid);
+            clipboard.drag_start = None;
+            info!("Copied {} tiles", clipboard.tiles.len());
+        }
+    }
+    for (mut sprite, mut transf, mut visibility) in markers.iter_mut() {
+        let start = match clipboard.drag_start {
+            Some(start) => start,
+            None => {
+                visibility.is_visible = false;
+                continue;
+            }
+        };
+        visibility.is_visible = true;
+        sprite.custom_size = Some((cursor - start).abs());
+        transf.translation = ((start + cursor) / 2.).extend(900.);
+    }
+
+    if !mouse_btn.just_pressed(actions.rotate_clockwise) || clipboard.tiles.is_empty() {
+        return;
+    }
+    let mut occupied = OccupiedTiles::default();
+    for tile in all_tiles() {
+        occupied.add(&tile);
+    }
+    let offset = VertexCoord::from_world_pos_in(cursor, &pointer.grid);
+    let mut skipped = 0;
+    for tile in clipboard.tiles.iter().map(|tile| tile.translated(offset)) {
+        if occupied.blocks(tile.mode(), tile.coord()) {
+            skipped += 1;
+            continue;
+        }
+        occupied.add(&tile);
+        match tile {
+            CopiedTile::Triangle(coord, color) => {
+                let tri = spawn_solo_triangle(
+                    &mut commands,
+                    coord,
+                    &pointer.grid,
+                    assets.triangle_mesh.clone(),
+                    assets.material_for(color),
+                    prefs.handle_radius,
+                    0.,
+                );
+                commands.entity(tri).insert(color);
+                spawn_clump(&mut commands, &[tri]);
+            }
+            CopiedTile::Immovable(coord) => {
+                spawn_immovable(
+                    &mut commands,
+                    coord,
+                    &pointer.grid,
+                    assets.triangle_mesh.clone(),
+                    assets.immovable_material.clone(),
+                    0.,
+                );
+            }
+            CopiedTile::Rune(coord, glyph, color) => {
+                let rune = spawn_rune(
+                    &mut commands,
+                    RuneTile {
+                        position: coord,
+                        rune_index: glyph % RUNE_GLYPHS,
+                        ..Default::default()
+                    },
+                    &pointer.grid,
+                    sprites.runes.clone(),
+                    0.,
+                );
+                commands.entity(rune).insert(color);
+            }
+        }
+    }
+    if skipped > 0 {
+        info!("Skipped {} pasted tiles on occupied cells", skipped);
+    }
+}
+
+fn spawn_editor_ghosts(commands: &mut Commands, assets: &AssetHandles, sprites: &SpriteAssets) {
+    let hidden = Visibility { is_visible: false };
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: assets.triangle_mesh.clone().into(),
+            material: assets.ghost_material.clone(),
+            visibility: hidden.clone(),
+            ..default()
+        })
+        .insert(EditorGhost::default())
+        .insert(Name::new("Editor Ghost"));
+    commands
+        .spawn_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite {
+                color: GHOST_RUNE_COLOR,
+                ..Default::default()
+            },
+            texture_atlas: sprites.runes.clone(),
+            visibility: hidden,
+            ..Default::default()
+        })
+        .insert(EditorGhost::default())
+        .insert(Name::new("Editor Rune Ghost"));
+}
+
+/// Spawns the parent of a clump with the given triangles.
+pub fn spawn_clump(commands: &mut Commands, triangles: &[Entity]) -> Entity {
+    commands
+        .spawn()
+        .insert_bundle(TransformBundle::default())
+        .insert_bundle(VisibilityBundle::default())
+        .insert(ClumpRoot)
+        .push_children(triangles)
+        .id()
+}
+
+/// Moves some triangles of a clump into a clump of their own, the opposite of a merge. `parent`
+/// is the placement of their clump and `triangles` their local transforms, which are updated so
+/// they stay in place. Their vertex handles come along as their children.
+pub fn split_clump(
+    commands: &mut Commands,
+    parent: &GlobalTransform,
+    triangles: &[(Entity, Transform)],
+) -> Entity {
+    for (id, local) in triangles {
+        let local = reparented_transform(local, parent, &GlobalTransform::identity());
+        commands.entity(*id).insert(local);
+    }
+    let ids: Vec<Entity> = triangles.iter().map(|(id, _)| *id).collect();
+    spawn_clump(commands, &ids)
+}
+
+/// Splits the triangle under the cursor off its clump with `Left Control` + `X`.
+fn split_triangle(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    suppressed: Res<InputSuppressed>,
+    enabled: Res<EditorEnabled>,
+    pointer: EditorPointer,
+    triangles: Query<(Entity, &TriangleTile, &Transform, &Parent), Without<Immovable>>,
+    clumps: Query<(&Children, &GlobalTransform), With<ClumpRoot>>,
+) {
+    if suppressed.any()
+        || !enabled.0
+        || !keys.pressed(KeyCode::LControl)
+        || !keys.just_pressed(KeyCode::X)
+    {
+        return;
+    }
+    let cursor = match pointer.world_pos() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let coord = TileCoord::from_world_pos_in(cursor, &pointer.grid);
+    let (id, transf, parent) = match triangles
+        .iter()
+        .find(|(_, tile, _, _)| tile.position == coord)
+    {
+        Some((id, _, transf, parent)) => (id, *transf, parent.get()),
+        None => return,
+    };
+    match clumps.get(parent) {
+        Ok((children, parent_transf)) if children.len() > 1 => {
+            split_clump(&mut commands, parent_transf, &[(id, transf)]);
+            info!("Split {:?} off its clump", coord);
+        }
+        _ => info!("{:?} is already on its own", coord),
+    }
+}
+
+/// Adds the tile under the cursor to the cells the closest rune requires, or removes it again,
+/// with `Left Control` + `T`. See [`RuneTile::required`].
+fn toggle_required_cell(
+    keys: Res<Input<KeyCode>>,
+    suppressed: Res<InputSuppressed>,
+    enabled: Res<EditorEnabled>,
+    pointer: EditorPointer,
+    mut runes: Query<&mut RuneTile>,
+    mut dirty: EventWriter<BoardDirty>,
+) {
+    if suppressed.any()
+        || !enabled.0
+        || !keys.pressed(KeyCode::LControl)
+        || !keys.just_pressed(KeyCode::T)
+    {
+        return;
+    }
+    let cursor = match pointer.world_pos() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let coord = TileCoord::from_world_pos_in(cursor, &pointer.grid);
+    let distance = |rune: &RuneTile| {
+        rune.position
+            .world_center_in(&pointer.grid)
+            .distance(cursor)
+    };
+    let mut rune = match runes.iter_mut().min_by(|a, b| {
+        distance(a)
+            .partial_cmp(&distance(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }) {
+        Some(rune) => rune,
+        None => return,
+    };
+    rune.toggle_required(coord);
+    info!(
+        "Rune on {:?} requires {:?}",
+        rune.position,
+        rune.required_cells()
+    );
+    dirty.send(BoardDirty);
+}
+
 pub fn spawn_immovable(
     commands: &mut Commands,
     coord: TileCoord,
+    grid: &GridConfig,
     mesh: Handle<Mesh>,
     mat: Handle<ColorMaterial>,
+    spawn_delay: f32,
 ) -> Entity {
     let tile = TriangleTile { position: coord };
     commands
         .spawn_bundle(MaterialMesh2dBundle {
             mesh: mesh.into(),
-            transform: tile.to_world_pos(),
+            transform: tile.to_world_pos_in(grid),
             material: mat,
             ..default()
         })
         .insert(tile)
         .insert(Immovable)
+        .insert(SpawnAnimation::with_delay(spawn_delay))
         .id()
 }
 
 pub fn spawn_solo_triangle(
     commands: &mut Commands,
     coord: TileCoord,
+    grid: &GridConfig,
     mesh: Handle<Mesh>,
     mat: Handle<ColorMaterial>,
+    handle_radius: f32,
+    spawn_delay: f32,
 ) -> Entity {
     let tile = TriangleTile { position: coord };
     commands
         .spawn_bundle(MaterialMesh2dBundle {
             mesh: mesh.into(),
-            transform: tile.to_world_pos(),
+            transform: tile.to_world_pos_in(grid),
             material: mat,
             ..default()
         })
         .insert(tile)
+        .insert(SpawnAnimation::with_delay(spawn_delay))
         .with_children(|builder| {
-            builder
-                .spawn_bundle(TransformBundle::from_transform(Transform::default()))
-                .insert(Selectable::new(SELECTABLE_RADIUS));
-            builder
-                .spawn_bundle(TransformBundle::from_transform(
-                    Transform::from_translation(X_DIR.extend(0.)),
-                ))
-                .insert(Selectable::new(SELECTABLE_RADIUS));
-            builder
-                .spawn_bundle(TransformBundle::from_transform(
-                    Transform::from_translation(Y_DIR.extend(0.)),
-                ))
-                .insert(Selectable::new(SELECTABLE_RADIUS));
+            for offset in HANDLE_OFFSETS {
+                builder
+                    .spawn_bundle(TransformBundle::default())
+                    .insert(Selectable::new(handle_radius).with_offset(offset));
+            }
         })
         .id()
 }
 
+/// Debug view of the vertex handles, toggled with `F1` in debug builds. Handles are drawn as small
+/// dots, red if selected and blue otherwise.
+fn debug_handles(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut show: Local<bool>,
+    mut handles: Query<(
+        Entity,
+        &Selectable,
+        &mut Transform,
+        Option<&mut Sprite>,
+        Option<&mut Visibility>,
+    )>,
+) {
+    if cfg!(debug_assertions) && keys.just_pressed(KeyCode::F1) {
+        *show = !*show;
+    }
+
+    for (id, selectable, mut transf, sprite, visibility) in handles.iter_mut() {
+        let color = if selectable.is_selected {
+            Color::RED
+        } else {
+            Color::BLUE
+        };
+        match (sprite, visibility) {
+            (Some(mut sprite), Some(mut visibility)) => {
+                if sprite.color != color {
+                    sprite.color = color;
+                }
+                if visibility.is_visible != *show {
+                    visibility.is_visible = *show;
+                }
+            }
+            _ if *show => {
+                // Draw above the triangle
+                transf.translation.z = 1.;
+                // The handle is on its offset, not the origin of the entity
+                let size = 8.;
+                commands
+                    .entity(id)
+                    .insert(Sprite {
+                        color,
+                        custom_size: Some(Vec2::splat(size)),
+                        anchor: Anchor::Custom(-selectable.offset / size),
+                        ..Default::default()
+                    })
+                    .insert(Handle::<Image>::default())
+                    .insert_bundle(VisibilityBundle::default());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Spawns a rune with the given glyph, which must be less than [`RUNE_GLYPHS`]. It grows into
+/// place after `spawn_delay` seconds.
 pub fn spawn_rune(
     commands: &mut Commands,
-    coord: TileCoord,
+    tile: RuneTile,
+    grid: &GridConfig,
     atlas: Handle<TextureAtlas>,
+    spawn_delay: f32,
 ) -> Entity {
-    let tile = RuneTile { position: coord };
-
     commands
         .spawn_bundle(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(rand::thread_rng().gen_range(0..5) * 2),
+            // The sprite sheet holds the frames of one glyph after another
+            sprite: TextureAtlasSprite::new(tile.rune_index * RUNE_FRAMES),
             texture_atlas: atlas,
-            transform: tile.to_world_pos(),
+            transform: tile.to_world_pos_in(grid),
             ..Default::default()
         })
         .insert(tile)
+        .insert(SpawnAnimation::with_delay(spawn_delay))
         .id()
 }
+
+#[test]
+fn test_editor_prefs() {
+    let prefs = EditorPrefs {
+        mode: BuilderState::Runes,
+        rune_index: 3,
+        handle_radius: 12.,
+    };
+    let ser = serde_json::to_string(&prefs).unwrap();
+    assert_eq!(serde_json::from_str::<EditorPrefs>(&ser).unwrap(), prefs);
+
+    let missing = EditorPrefs::load_from("missing-editor-prefs");
+    assert_eq!(missing.mode, BuilderState::Triangles);
+    assert_eq!(missing.rune_index, 0);
+}
+
+#[test]
+fn test_handle_positions() {
+    use crate::tilemap::{TileVertices, TriangleOrient, VertexCoord};
+    use bevy::ecs::system::CommandQueue;
+
+    let grid = GridConfig::default();
+    for orient in [TriangleOrient::PointingUp, TriangleOrient::PointingDown] {
+        let coord = (VertexCoord::new(1, 0), orient);
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let triangle = spawn_solo_triangle(
+            &mut commands,
+            coord,
+            &grid,
+            Handle::default(),
+            Handle::default(),
+            SELECTABLE_RADIUS,
+            0.,
+        );
+        queue.apply(&mut world);
+
+        // The handles don't move relative to the triangle
+        let parent = GlobalTransform::from(*world.get::<Transform>(triangle).unwrap());
+        let handles: Vec<Vec2> = world
+            .get::<Children>(triangle)
+            .unwrap()
+            .iter()
+            .map(|&handle| {
+                let selectable = world.get::<Selectable>(handle).unwrap();
+                let local = world.get::<Transform>(handle).unwrap();
+                let transf = parent.mul_transform(*local);
+                selectable.placement(&transf).translation().truncate()
+            })
+            .collect();
+
+        // Every corner of the triangle has exactly one handle
+        assert_eq!(handles.len(), 3);
+        for vertex in coord.world_vertices_in(&grid) {
+            let on_vertex = handles.iter().filter(|h| h.distance(vertex) < 1e-3);
+            assert_eq!(on_vertex.count(), 1);
+        }
+    }
+}
+
+#[test]
+fn test_placement_blocked() {
+    use crate::tilemap::{TriangleOrient, VertexCoord};
+
+    let tile = |x| (VertexCoord::new(x, 0), TriangleOrient::PointingUp);
+    let occupied = OccupiedTiles {
+        triangles: [tile(0)].into_iter().collect(),
+        immovables: [tile(1)].into_iter().collect(),
+        runes: [tile(0), tile(2)].into_iter().collect(),
+    };
+    let modes = [
+        BuilderState::Triangles,
+        BuilderState::Immovables,
+        BuilderState::Runes,
+    ];
+    let blocked = |x| modes.map(|mode| occupied.blocks(mode, tile(x)));
+
+    assert_eq!(blocked(0), [true, true, true]);
+    assert_eq!(blocked(1), [true, true, true]);
+    // Triangles light up runes
+    assert_eq!(blocked(2), [false, true, true]);
+    assert_eq!(blocked(3), [false, false, false]);
+}
+
+#[test]
+fn test_cycle_rune_glyph() {
+    let mut world = World::new();
+    world.insert_resource(EditorPrefs::default());
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(cycle_rune_glyph);
+
+    let mut press = |world: &mut World, key: KeyCode| {
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(key);
+        world.insert_resource(keys);
+        stage.run(world);
+        world.resource::<EditorPrefs>().rune_index
+    };
+    assert_eq!(press(&mut world, KeyCode::RBracket), 1);
+    assert_eq!(press(&mut world, KeyCode::LBracket), 0);
+    assert_eq!(press(&mut world, KeyCode::LBracket), RUNE_GLYPHS - 1);
+    assert_eq!(press(&mut world, KeyCode::RBracket), 0);
+}
+
+#[test]
+fn test_paint_stroke() {
+    use crate::tilemap::{TriangleOrient, VertexCoord};
+
+    let up = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let down = (VertexCoord::ZERO, TriangleOrient::PointingDown);
+    let mut stroke = PaintStroke::default();
+    assert!(stroke.paint(up));
+    // Lingering on a tile only paints it once
+    assert!(!stroke.paint(up));
+    assert!(stroke.paint(down));
+    assert!(!stroke.paint(down));
+
+    // A new click paints again
+    stroke.end();
+    assert!(stroke.paint(down));
+}
+
+#[test]
+fn test_orient_override() {
+    use crate::tilemap::VertexCoord;
+
+    let coord = (VertexCoord::new(2, -1), TriangleOrient::PointingUp);
+    let mut world = World::new();
+    world.insert_resource(OrientOverride::default());
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(cycle_orient_override);
+
+    let mut press_f = |world: &mut World| {
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::F);
+        world.insert_resource(keys);
+        stage.run(world);
+        world.resource::<OrientOverride>().apply(coord)
+    };
+    assert_eq!(press_f(&mut world), coord);
+    assert_eq!(press_f(&mut world), (coord.0, TriangleOrient::PointingDown));
+    // Back to following the cursor
+    assert_eq!(press_f(&mut world), coord);
+    assert_eq!(OrientOverride::default().apply(coord), coord);
+}
+
+#[test]
+fn test_tool_label() {
+    let mut world = World::new();
+    world.insert_resource(Input::<KeyCode>::default());
+    world.insert_resource(EditorEnabled(true));
+    world.insert_resource(State::new(BuilderState::Runes));
+    world.insert_resource(OrientOverride::default());
+    let label = world
+        .spawn()
+        .insert(Text::from_section("", TextStyle::default()))
+        .insert(Visibility { is_visible: false })
+        .insert(ToolLabel)
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(update_tool_label);
+    stage.run(&mut world);
+    assert!(!world.get::<Visibility>(label).unwrap().is_visible);
+
+    world
+        .resource_mut::<Input<KeyCode>>()
+        .press(KeyCode::LControl);
+    stage.run(&mut world);
+    assert!(world.get::<Visibility>(label).unwrap().is_visible);
+    assert_eq!(world.get::<Text>(label).unwrap().sections[0].value, "Runes");
+
+    // The sandbox disables the editor
+    world.resource_mut::<EditorEnabled>().0 = false;
+    stage.run(&mut world);
+    assert!(!world.get::<Visibility>(label).unwrap().is_visible);
+}
+
+#[test]
+fn test_copy_region() {
+    let up = |x, y| (VertexCoord::new(x, y), TriangleOrient::PointingUp);
+    let tiles = [
+        CopiedTile::Triangle(up(0, 0), TileColor(1)),
+        CopiedTile::Immovable(up(1, 0)),
+        CopiedTile::Rune(up(5, 0), 3, TileColor::default()),
+    ];
+    let anchor = VertexCoord::new(1, 0);
+    let corners = (
+        anchor.to_world_pos().translation.truncate() + Vec2::new(-2., -1.) * TRIANGLE_SIDE,
+        anchor.to_world_pos().translation.truncate() + Vec2::new(2., 1.) * TRIANGLE_SIDE,
+    );
+    let copied = copy_region(corners, anchor, tiles, &GridConfig::default());
+    assert_eq!(
+        copied,
+        vec![
+            CopiedTile::Triangle(up(-1, 0), TileColor(1)),
+            CopiedTile::Immovable(up(0, 0)),
+        ]
+    );
+
+    // Pasting at the anchor restores the tiles
+    let pasted: Vec<CopiedTile> = copied.iter().map(|tile| tile.translated(anchor)).collect();
+    assert_eq!(pasted, tiles[..2]);
+    assert_eq!(pasted[1].mode(), BuilderState::Immovables);
+}
+
+#[test]
+fn test_split_clump() {
+    use bevy::ecs::system::CommandQueue;
+
+    let mut world = World::new();
+    let handle = world
+        .spawn()
+        .insert(Selectable::new(SELECTABLE_RADIUS))
+        .id();
+    let local = Transform::from_xyz(10., 20., 0.);
+    let split = world.spawn().insert(local).push_children(&[handle]).id();
+    let stays = world.spawn().insert(Transform::default()).id();
+    let parent = GlobalTransform::from_xyz(100., 0., 0.);
+    let clump = world
+        .spawn()
+        .insert(parent)
+        .insert(ClumpRoot)
+        .push_children(&[split, stays])
+        .id();
+
+    let mut queue = CommandQueue::default();
+    let mut commands = Commands::new(&mut queue, &world);
+    let new_clump = split_clump(&mut commands, &parent, &[(split, local)]);
+    queue.apply(&mut world);
+
+    assert!(world.get::<ClumpRoot>(new_clump).is_some());
+    assert_eq!(world.get::<Parent>(split).unwrap().get(), new_clump);
+    assert_eq!(world.get::<Children>(clump).unwrap().to_vec(), vec![stays]);
+    // Same world position and the handle is still attached
+    assert_eq!(
+        world.get::<Transform>(split).unwrap().translation,
+        Vec3::new(110., 20., 0.)
+    );
+    assert_eq!(world.get::<Parent>(handle).unwrap().get(), split);
+}
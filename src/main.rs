@@ -4,50 +4,68 @@
 //! Rotate triangles to light up the runes, but beware that they're inseperarable once touching.
 //!
 //! ## TODO:
-//! - Allow more levels
-//! - Rotation Ghost ?
 //! - Merge indication
-//! - Block indication
-//!     Jiggle cursor
-//!
-//! - Undo??
-//!
-//! - Particles?
-//! - Audio?
 //! - Animations?
-//! - Different Colors?
 
-use bevy::{prelude::*, render::camera::ScalingMode};
+use analytics::MagnateAnalyticsPlugin;
+use audio::{AudioAssets, MagnateAudioPlugin};
+use background::{BackgroundSprite, BorderMesh, MagnateBackgroundPlugin};
+use bevy::{prelude::*, render::camera::ScalingMode, sprite::MaterialMesh2dBundle};
 use bevy_asset_loader::prelude::*;
-use bevy_point_selection::{PointSelectionPlugin, SelectionSource};
-use level::MagnateLevelPlugin;
-use level_editor::MagnateLevelEditorPlugin;
-use rotation::MagnateRotationPlugin;
+use bevy_point_selection::{PointSelectionPlugin, PointerBlocked, SelectionSource};
+use camera::MagnateCameraPlugin;
+use history::MagnateHistoryPlugin;
+use level::{build_clump_mesh, ContinuePrompt, LevelLabel, MagnateLevelPlugin, MoveCounter};
+use level_editor::{MagnateLevelEditorPlugin, ToolLabel};
+use menu::MagnateMenuPlugin;
+use particles::MagnateParticlesPlugin;
+use rotation::{MagnateRotationPlugin, PlayfieldBounds};
 use savegame::MagnateSaveGamePlugin;
-use tilemap::{TileCoord, TriangleTile};
+use settings::MagnateSettingsPlugin;
+use summary::MagnateSummaryPlugin;
+use tilemap::{GridConfig, TileColor, TileCoord, TriangleTile, TRIANGLE_Z};
 
 pub const BG_COLOR: Color = Color::rgb(0.7, 0.7, 0.7);
+pub const LEVEL_LABEL_COLOR: Color = Color::rgb_u8(148, 141, 126);
 
+mod analytics;
+mod audio;
+mod background;
+mod camera;
+mod headless;
+mod history;
 mod level;
 mod level_editor;
+mod menu;
+mod moves;
+mod particles;
 mod rotation;
+mod sandbox;
 mod savegame;
+mod settings;
+mod summary;
 mod tilemap;
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash)]
 pub enum GameState {
     AssetLoading,
     Next,
+    /// Pushed on top of `Next` after the last level was completed
+    Summary,
+    /// Pushed on top of `Next` while the level select menu is open
+    LevelSelect,
 }
 
 const BORDER_COORDS: &'static str = include_str!("../assets/border.json");
 
-#[derive(AssetCollection)]
+#[derive(AssetCollection, Default)]
 struct SpriteAssets {
     #[asset(path = "indicator.png")]
     indicator: Handle<Image>,
     #[asset(path = "rotate_hint.png")]
     rotate_hint: Handle<Image>,
+    #[asset(path = "rotate_hint_ccw.png")]
+    rotate_hint_ccw: Handle<Image>,
     #[asset(path = "reload_hint.png")]
     reload_hint: Handle<Image>,
     #[asset(path = "background.png")]
@@ -71,13 +89,92 @@ struct SpriteAssets {
     font: Handle<Font>,
 }
 
+/// Set while player input should be ignored, e.g. during the intro sweep of a level.
+#[derive(Default, Debug)]
+pub struct InputSuppressed {
+    pub intro: bool,
+    /// The cursor is over a UI node, so clicks belong to the UI
+    pub ui: bool,
+}
+
+impl InputSuppressed {
+    pub fn any(&self) -> bool {
+        self.intro || self.ui
+    }
+}
+
+#[derive(Default)]
 struct AssetHandles {
     triangle_mesh: Handle<Mesh>,
     triangle_material: Handle<ColorMaterial>,
+    /// Materials of the triangles with a [`TileColor`] other than `0`, starting with color `1`
+    colored_materials: Vec<Handle<ColorMaterial>>,
     immovable_material: Handle<ColorMaterial>,
+    /// Translucent triangles previewing a rotation
+    ghost_material: Handle<ColorMaterial>,
+    /// Like the ghost material, but for rotations which are blocked
+    blocked_ghost_material: Handle<ColorMaterial>,
+}
+
+impl AssetHandles {
+    /// The triangle material of `color`. Unknown colors fall back to the plain ruby.
+    fn material_for(&self, color: TileColor) -> Handle<ColorMaterial> {
+        match color.0 {
+            0 => self.triangle_material.clone(),
+            c => self
+                .colored_materials
+                .get(c as usize - 1)
+                .unwrap_or(&self.triangle_material)
+                .clone(),
+        }
+    }
+}
+
+/// Renames a saved level on the web, where there is no `--rename-level`. Call it from the browser
+/// console as `rename_level("9", "my-level")`.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+pub fn rename_level(old: &str, new: &str) -> Result<(), String> {
+    savegame::rename_level(old, new).map_err(|e| e.to_string())
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--headless") {
+        headless::run(args.get(i + 1).map_or("1", String::as_str));
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.iter().any(|arg| arg == "--export-levels") {
+        let force = args.iter().any(|arg| arg == "--force");
+        let dir = std::path::Path::new(savegame::LEVELS_DIR);
+        match savegame::export_levels(dir, force) {
+            Ok(written) => println!("Exported {} levels to {}", written, dir.display()),
+            Err(e) => eprintln!("Failed to export levels: {}", e),
+        }
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(i) = args.iter().position(|arg| arg == "--delete-level") {
+        match args.get(i + 1).map(|name| savegame::delete_level(name)) {
+            Some(Ok(())) => println!("Deleted level {}", args[i + 1]),
+            Some(Err(e)) => eprintln!("Failed to delete level: {}", e),
+            None => eprintln!("Usage: --delete-level <name>"),
+        }
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(i) = args.iter().position(|arg| arg == "--rename-level") {
+        match (args.get(i + 1), args.get(i + 2)) {
+            (Some(old), Some(new)) => match savegame::rename_level(old, new) {
+                Ok(()) => println!("Renamed level {} to {}", old, new),
+                Err(e) => eprintln!("Failed to rename level: {}", e),
+            },
+            _ => eprintln!("Usage: --rename-level <old> <new>"),
+        }
+        return;
+    }
+
     App::new()
         .insert_resource(ClearColor(BG_COLOR))
         .insert_resource(WindowDescriptor {
@@ -91,23 +188,57 @@ fn main() {
         .add_loading_state(
             LoadingState::new(GameState::AssetLoading)
                 .continue_to_state(GameState::Next)
-                .with_collection::<SpriteAssets>(),
+                .with_collection::<SpriteAssets>()
+                .with_collection::<AudioAssets>(),
         )
         .add_state(GameState::AssetLoading)
+        .init_resource::<InputSuppressed>()
+        .init_resource::<GridConfig>()
         .add_plugins(DefaultPlugins)
-        .add_plugin(PointSelectionPlugin)
+        .add_plugin(PointSelectionPlugin::default())
         .add_plugin(MagnateRotationPlugin)
         .add_plugin(MagnateSaveGamePlugin)
         .add_plugin(MagnateLevelPlugin)
         .add_plugin(MagnateLevelEditorPlugin)
+        .add_plugin(MagnateSummaryPlugin)
+        .add_plugin(MagnateCameraPlugin)
+        .add_plugin(MagnateHistoryPlugin)
+        .add_plugin(MagnateSettingsPlugin)
+        .add_plugin(MagnateAnalyticsPlugin)
+        .add_plugin(MagnateMenuPlugin)
+        .add_plugin(MagnateParticlesPlugin)
+        .add_plugin(MagnateAudioPlugin)
+        .add_plugin(MagnateBackgroundPlugin)
         .add_system_set(
             SystemSet::on_enter(GameState::Next)
                 .with_system(spawn_camera)
                 .with_system(spawn_background),
         )
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            detect_ui_focus.after(bevy::ui::UiSystem::Focus),
+        )
         .run();
 }
 
+/// Suppresses world input and the selection while the cursor hovers or clicks an interactive UI
+/// node.
+fn detect_ui_focus(
+    interactions: Query<&Interaction>,
+    mut suppressed: ResMut<InputSuppressed>,
+    mut blocked: ResMut<PointerBlocked>,
+) {
+    let over_ui = interactions
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if suppressed.ui != over_ui {
+        suppressed.ui = over_ui;
+    }
+    if blocked.0 != over_ui {
+        blocked.0 = over_ui;
+    }
+}
+
 /// Spawn a 2d camera with a fix heigth  in triangle units, and auto width
 fn spawn_camera(mut commands: Commands) {
     commands
@@ -121,32 +252,110 @@ fn spawn_camera(mut commands: Commands) {
         .insert(SelectionSource);
 }
 
-/// Spawn the 1280x720 background sprite with the triangle grid
-fn spawn_background(mut commands: Commands, assets: Res<SpriteAssets>) {
+/// Spawn the 1280x720 background sprite with the triangle grid, which is hidden while the grid is
+/// drawn procedurally
+fn spawn_background(
+    mut commands: Commands,
+    assets: Res<SpriteAssets>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    grid: Res<GridConfig>,
+) {
     commands
         .spawn_bundle(SpriteBundle {
             texture: assets.background.clone(),
             transform: Transform::from_xyz(0.0, 0.0, 100.0),
             ..Default::default()
         })
+        .insert(BackgroundSprite)
         .insert(Name::new("Background"));
 
-    commands.spawn_bundle(Text2dBundle {
-        text: Text::from_section(
-            "1",
-            TextStyle {
-                font: assets.font.clone(),
-                font_size: 50.,
-                color: Color::rgb_u8(148, 141, 126),
-            },
-        ),
-        transform: Transform::from_xyz(460., 0., 550.),
-        ..Default::default()
-    });
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "1",
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 50.,
+                    color: LEVEL_LABEL_COLOR,
+                },
+            ),
+            transform: Transform::from_xyz(460., 0., 550.),
+            ..Default::default()
+        })
+        .insert(LevelLabel);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "0",
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 30.,
+                    color: LEVEL_LABEL_COLOR,
+                },
+            ),
+            transform: Transform::from_xyz(460., -50., 550.),
+            ..Default::default()
+        })
+        .insert(MoveCounter);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "",
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 24.,
+                    color: LEVEL_LABEL_COLOR,
+                },
+            ),
+            transform: Transform::from_xyz(460., -100., 550.),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(ToolLabel);
+
+    commands
+        .spawn_bundle(Text2dBundle {
+            text: Text::from_section(
+                "Press Enter to continue",
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 40.,
+                    color: LEVEL_LABEL_COLOR,
+                },
+            )
+            .with_alignment(TextAlignment::CENTER),
+            transform: Transform::from_xyz(0., -300., 550.),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(ContinuePrompt);
 
     // Spawn border immovables
     let immovables: Vec<TileCoord> =
         serde_json::from_str(BORDER_COORDS).expect("Border json should be formatted correctly!");
+    if !moves::is_boundary_closed(&immovables) {
+        warn!("The border has a gap, triangles might escape the playfield");
+    }
+    commands.insert_resource(PlayfieldBounds::from_tiles(&immovables));
+
+    // A single mesh for the whole border instead of a sprite per triangle
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: meshes.add(build_clump_mesh(&immovables, &grid)).into(),
+            material: materials.add(ColorMaterial {
+                color: Color::WHITE,
+                texture: Some(assets.grey_triangle.clone()),
+            }),
+            transform: Transform::from_xyz(0., 0., TRIANGLE_Z),
+            ..Default::default()
+        })
+        .insert(BorderMesh {
+            tiles: immovables.clone(),
+        })
+        .insert(Name::new("Border"));
 
     for coord in immovables {
         let tile = TriangleTile { position: coord };
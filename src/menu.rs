@@ -0,0 +1,339 @@
+use bevy::{prelude::*, render::camera::RenderTarget};
+use bevy_point_selection::viewport_to_world;
+
+use crate::{
+    level::{LevelId, LevelInfo},
+    savegame::end_level_index,
+    summary::Progress,
+    GameState, SpriteAssets, LEVEL_LABEL_COLOR,
+};
+
+/// Number of levels per row in the level select menu
+const COLUMNS: usize = 4;
+const CELL_SIZE: f32 = 120.;
+/// Color of the levels which were already completed once
+const COMPLETED_COLOR: Color = Color::rgb_u8(212, 175, 55);
+
+/// A level select menu, opened with `M` or `Escape`. Move the focus with the arrow keys, the
+/// d-pad or the mouse and load the focused level with `Enter`, the south button or a click.
+/// `Escape` closes the menu. Completed levels are shown in gold.
+pub struct MagnateMenuPlugin;
+
+impl Plugin for MagnateMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MenuFocus>()
+            .init_resource::<EscapeClaimed>()
+            .add_system_set(SystemSet::on_update(GameState::Next).with_system(open_menu))
+            .add_system_set(SystemSet::on_enter(GameState::LevelSelect).with_system(spawn_menu))
+            .add_system_set(
+                SystemSet::on_update(GameState::LevelSelect)
+                    .with_system(menu_mouse.before(menu_navigation))
+                    .with_system(menu_navigation.before(highlight_focus))
+                    .with_system(highlight_focus),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::LevelSelect).with_system(despawn_menu));
+    }
+}
+
+#[derive(Component, Default, Debug, Clone)]
+pub struct LevelSelectMenu;
+
+/// The cell of the level with this index in the menu.
+#[derive(Component, Debug, Clone)]
+pub struct LevelCell(pub usize);
+
+/// The index of the focused cell, the first level has index `0`.
+#[derive(Default, Debug)]
+pub struct MenuFocus {
+    pub index: usize,
+}
+
+/// Set while `Escape` does something else than opening the menu, e.g. releasing pinned clumps.
+/// It is set for the previous frame, so the systems handling `Escape` can run in any order.
+#[derive(Default, Debug)]
+pub struct EscapeClaimed(pub bool);
+
+/// The number of selectable levels, all built-in levels without the empty and the end level.
+fn level_count() -> usize {
+    end_level_index() - 1
+}
+
+/// The cell next to `index` in direction `step`, where positive y is downwards. Stays on
+/// `index` at the edges of the grid.
+fn move_focus(index: usize, step: IVec2, count: usize) -> usize {
+    let column = (index % COLUMNS) as i32 + step.x;
+    let row = (index / COLUMNS) as i32 + step.y;
+    if column < 0 || column >= COLUMNS as i32 || row < 0 {
+        return index;
+    }
+    let next = row as usize * COLUMNS + column as usize;
+    if next < count {
+        next
+    } else {
+        index
+    }
+}
+
+/// The position of the cell of the level with `index` relative to the center of the menu.
+fn cell_position(index: usize, count: usize) -> Vec2 {
+    let rows = (count + COLUMNS - 1) / COLUMNS;
+    let top_left = Vec2::new(
+        -0.5 * (COLUMNS - 1) as f32 * CELL_SIZE,
+        0.5 * rows.saturating_sub(1) as f32 * CELL_SIZE,
+    );
+    let offset = Vec2::new(
+        (index % COLUMNS) as f32 * CELL_SIZE,
+        -((index / COLUMNS) as f32) * CELL_SIZE,
+    );
+    top_left + offset
+}
+
+/// The index of the cell containing `point`, relative to the center of the menu.
+fn cell_at(point: Vec2, count: usize) -> Option<usize> {
+    (0..count)
+        .find(|&index| (point - cell_position(index, count)).abs().max_element() < 0.5 * CELL_SIZE)
+}
+
+fn open_menu(
+    mut keys: ResMut<Input<KeyCode>>,
+    claimed: Res<EscapeClaimed>,
+    level: Res<LevelInfo>,
+    mut focus: ResMut<MenuFocus>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let key = match [KeyCode::M, KeyCode::Escape]
+        .into_iter()
+        .filter(|&key| key != KeyCode::Escape || !claimed.0)
+        .find(|&key| keys.just_pressed(key))
+    {
+        Some(key) => key,
+        None => return,
+    };
+    keys.clear_just_pressed(key);
+    focus.index = match level.current {
+        LevelId::Builtin(current) => current.clamp(1, level_count()) - 1,
+        // Saved levels aren't listed
+        LevelId::Named(_) => 0,
+    };
+    let _ = state.push(GameState::LevelSelect);
+}
+
+fn menu_navigation(
+    mut keys: ResMut<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut focus: ResMut<MenuFocus>,
+    mut level: ResMut<LevelInfo>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let button_pressed = |button_type| {
+        buttons
+            .get_just_pressed()
+            .any(|button| button.button_type == button_type)
+    };
+
+    let steps = [
+        (KeyCode::Left, GamepadButtonType::DPadLeft, IVec2::NEG_X),
+        (KeyCode::Right, GamepadButtonType::DPadRight, IVec2::X),
+        (KeyCode::Up, GamepadButtonType::DPadUp, IVec2::NEG_Y),
+        (KeyCode::Down, GamepadButtonType::DPadDown, IVec2::Y),
+    ];
+    for (key, button, step) in steps {
+        if keys.just_pressed(key) || button_pressed(button) {
+            focus.index = move_focus(focus.index, step, level_count());
+        }
+    }
+
+    if keys.just_pressed(KeyCode::Return) || button_pressed(GamepadButtonType::South) {
+        // Don't confirm anything else with the same press
+        keys.clear_just_pressed(KeyCode::Return);
+        select_level(focus.index, &mut level, &mut state);
+    } else if keys.just_pressed(KeyCode::Escape) || button_pressed(GamepadButtonType::East) {
+        // Otherwise the menu would open again right away
+        keys.clear_just_pressed(KeyCode::Escape);
+        let _ = state.pop();
+    }
+}
+
+/// Focuses the hovered cell and loads its level on click.
+fn menu_mouse(
+    mut mouse: ResMut<Input<MouseButton>>,
+    windows: Res<Windows>,
+    cam: Query<(&Camera, &GlobalTransform)>,
+    mut focus: ResMut<MenuFocus>,
+    mut level: ResMut<LevelInfo>,
+    mut state: ResMut<State<GameState>>,
+) {
+    let cursor = cam.get_single().ok().and_then(|(camera, cam_transform)| {
+        let window = match camera.target {
+            RenderTarget::Window(id) => windows.get(id)?,
+            _ => return None,
+        };
+        viewport_to_world(camera, cam_transform, window)
+    });
+    let index = match cursor.and_then(|cursor| cell_at(cursor, level_count())) {
+        Some(index) => index,
+        None => return,
+    };
+    if focus.index != index {
+        focus.index = index;
+    }
+    if mouse.just_pressed(MouseButton::Left) {
+        // The click must not rotate any triangles once the level is loaded
+        mouse.clear_just_pressed(MouseButton::Left);
+        select_level(index, &mut level, &mut state);
+    }
+}
+
+/// Loads the level of the cell `index` and closes the menu.
+fn select_level(index: usize, level: &mut LevelInfo, state: &mut State<GameState>) {
+    level.current = LevelId::Builtin(index + 1);
+    level.should_reload = true;
+    level.win_animation_progress = None;
+    level.awaiting_confirm = false;
+    let _ = state.pop();
+}
+
+fn highlight_focus(
+    focus: Res<MenuFocus>,
+    progress: Res<Progress>,
+    mut cells: Query<(&LevelCell, &mut Text, &mut Transform)>,
+) {
+    for (cell, mut text, mut transf) in cells.iter_mut() {
+        let focused = cell.0 == focus.index;
+        text.sections[0].style.color = if focused {
+            Color::WHITE
+        } else if progress.levels.contains_key(&(cell.0 + 1)) {
+            COMPLETED_COLOR
+        } else {
+            LEVEL_LABEL_COLOR
+        };
+        transf.scale = Vec3::splat(if focused { 1.3 } else { 1. });
+    }
+}
+
+fn spawn_menu(mut commands: Commands, assets: Res<SpriteAssets>) {
+    let count = level_count();
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(1280., 720.)),
+                color: Color::rgba(0., 0., 0., 0.6),
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(0., 0., 950.),
+            ..Default::default()
+        })
+        .insert(LevelSelectMenu)
+        .insert(Name::new("Level Select"))
+        .with_children(|builder| {
+            for index in 0..count {
+                builder
+                    .spawn_bundle(Text2dBundle {
+                        text: Text::from_section(
+                            (index + 1).to_string(),
+                            TextStyle {
+                                font: assets.font.clone(),
+                                font_size: 60.,
+                                color: LEVEL_LABEL_COLOR,
+                            },
+                        )
+                        .with_alignment(TextAlignment::CENTER),
+                        transform: Transform::from_translation(
+                            cell_position(index, count).extend(1.),
+                        ),
+                        ..Default::default()
+                    })
+                    .insert(LevelCell(index));
+            }
+        });
+}
+
+fn despawn_menu(mut commands: Commands, menus: Query<Entity, With<LevelSelectMenu>>) {
+    for id in menus.iter() {
+        commands.entity(id).despawn_recursive();
+    }
+}
+
+#[test]
+fn test_menu_navigation() {
+    let mut world = World::new();
+    world.insert_resource(Input::<KeyCode>::default());
+    world.insert_resource(Input::<GamepadButton>::default());
+    world.insert_resource(MenuFocus::default());
+    world.insert_resource(LevelInfo::default());
+    world.insert_resource(State::new(GameState::LevelSelect));
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(menu_navigation);
+    let mut press = |world: &mut World, key: KeyCode| {
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(key);
+        world.insert_resource(keys);
+        stage.run(world);
+    };
+
+    press(&mut world, KeyCode::Right);
+    assert_eq!(world.resource::<MenuFocus>().index, 1);
+    press(&mut world, KeyCode::Down);
+    assert_eq!(world.resource::<MenuFocus>().index, 1 + COLUMNS);
+    // There is no row above the first one
+    press(&mut world, KeyCode::Up);
+    press(&mut world, KeyCode::Up);
+    assert_eq!(world.resource::<MenuFocus>().index, 1);
+    assert!(!world.resource::<LevelInfo>().should_reload);
+
+    press(&mut world, KeyCode::Return);
+    let level = world.resource::<LevelInfo>();
+    assert_eq!(level.current, LevelId::Builtin(2));
+    assert!(level.should_reload);
+}
+
+#[test]
+fn test_cell_at() {
+    let count = 6;
+    for index in 0..count {
+        let center = cell_position(index, count);
+        assert_eq!(cell_at(center, count), Some(index));
+        assert_eq!(
+            cell_at(center + Vec2::splat(0.4 * CELL_SIZE), count),
+            Some(index)
+        );
+    }
+    // The second row isn't full
+    let missing = cell_position(count - 1, count) + Vec2::new(CELL_SIZE, 0.);
+    assert_eq!(cell_at(missing, count), None);
+    assert_eq!(cell_at(Vec2::new(0., 10. * CELL_SIZE), count), None);
+}
+
+#[test]
+fn test_escape_toggles_menu() {
+    let mut world = World::new();
+    world.insert_resource(Input::<GamepadButton>::default());
+    world.insert_resource(MenuFocus::default());
+    world.insert_resource(EscapeClaimed::default());
+    world.insert_resource(LevelInfo::default());
+    world.insert_resource(State::new(GameState::Next));
+
+    // State transitions are applied within the same frame, so a single press must not close
+    // the menu right after opening it or the other way around
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system_set(State::<GameState>::get_driver())
+        .add_system_set(SystemSet::on_update(GameState::Next).with_system(open_menu))
+        .add_system_set(SystemSet::on_update(GameState::LevelSelect).with_system(menu_navigation));
+    let mut press_escape = |world: &mut World| {
+        let mut keys = Input::<KeyCode>::default();
+        keys.press(KeyCode::Escape);
+        world.insert_resource(keys);
+        stage.run(world);
+        world.resource::<State<GameState>>().current().clone()
+    };
+
+    assert_eq!(press_escape(&mut world), GameState::LevelSelect);
+    assert_eq!(press_escape(&mut world), GameState::Next);
+
+    // Claimed by another system
+    world.insert_resource(EscapeClaimed(true));
+    assert_eq!(press_escape(&mut world), GameState::Next);
+}
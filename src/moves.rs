@@ -0,0 +1,380 @@
+//! Move generation on plain board data, independent of the ECS. Used by the rotation system
+//! and the headless report, and meant as the core for solvers and hints.
+
+use bevy::{prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::tilemap::{
+    IterNeighbors, RotateAroundVertex, TileColor, TileCoord, TileVertices, TriangleOrient,
+    TrianglesAroundVertex, VertexCoord,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum RotationDir {
+    #[default]
+    Clockwise,
+    CounterClockwise,
+}
+
+impl RotationDir {
+    pub const ALL: [RotationDir; 2] = [RotationDir::Clockwise, RotationDir::CounterClockwise];
+
+    pub fn reversed(self) -> Self {
+        match self {
+            RotationDir::Clockwise => RotationDir::CounterClockwise,
+            RotationDir::CounterClockwise => RotationDir::Clockwise,
+        }
+    }
+
+    /// The angle of a sixth turn in this direction in radians, counter clockwise is positive.
+    pub fn angle(self) -> f32 {
+        match self {
+            RotationDir::Clockwise => -std::f32::consts::FRAC_PI_3,
+            RotationDir::CounterClockwise => std::f32::consts::FRAC_PI_3,
+        }
+    }
+
+    pub fn rotate(self, tile: TileCoord, anchor: VertexCoord) -> TileCoord {
+        match self {
+            RotationDir::Clockwise => tile.rotated_clockwise(anchor),
+            RotationDir::CounterClockwise => tile.rotated_counter_clockwise(anchor),
+        }
+    }
+}
+
+/// Rotating the clump with index `clump` by 60° around `anchor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move {
+    pub clump: usize,
+    pub anchor: VertexCoord,
+    pub dir: RotationDir,
+}
+
+/// The minimum and maximum vertex of all `tiles`. Without tiles both are zero.
+pub fn bounds(tiles: impl Iterator<Item = TileCoord>) -> (VertexCoord, VertexCoord) {
+    tiles
+        .flat_map(|tile| tile.vertices())
+        .fold(None, |bounds, v| match bounds {
+            None => Some((v, v)),
+            Some((min, max)) => Some((v.min(min), v.max(max))),
+        })
+        .unwrap_or((VertexCoord::ZERO, VertexCoord::ZERO))
+}
+
+/// Whether all vertices of `tile` lie within the `bounds` from [`bounds`].
+pub fn within_bounds(tile: TileCoord, bounds: (VertexCoord, VertexCoord)) -> bool {
+    let (min, max) = bounds;
+    tile.vertices()
+        .iter()
+        .all(|v| v.cmpge(min).all() && v.cmple(max).all())
+}
+
+/// Whether the `coords` enclose the area around their center without gaps, so that no
+/// triangle inside can be rotated out. Triangles move between edge neighbors, thus the free
+/// tiles reachable from the center must all lie within the bounds of the border.
+pub fn is_boundary_closed(coords: &[TileCoord]) -> bool {
+    if coords.is_empty() {
+        return false;
+    }
+    let border: HashSet<TileCoord> = coords.iter().cloned().collect();
+    let extent = bounds(coords.iter().cloned());
+
+    // The tile containing the centroid of all border vertices
+    let vertices: Vec<Vec2> = coords
+        .iter()
+        .flat_map(|tile| tile.vertices())
+        .map(|v| v.as_vec2())
+        .collect();
+    let center = vertices.iter().sum::<Vec2>() / vertices.len() as f32;
+    let base = center.floor();
+    let frac = center - base;
+    let start = if frac.x + frac.y <= 1. {
+        (base.as_ivec2(), TriangleOrient::PointingUp)
+    } else {
+        (base.as_ivec2() + IVec2::Y, TriangleOrient::PointingDown)
+    };
+    if border.contains(&start) {
+        return false;
+    }
+
+    let mut visited: HashSet<TileCoord> = [start].into_iter().collect();
+    let mut stack = vec![start];
+    while let Some(tile) = stack.pop() {
+        if !within_bounds(tile, extent) {
+            return false;
+        }
+        for neighbor in tile.iter_neighbors() {
+            if !border.contains(&neighbor) && visited.insert(neighbor) {
+                stack.push(neighbor);
+            }
+        }
+    }
+    true
+}
+
+/// Whether all six triangles around `center` are `occupied`, forming a hexagon.
+pub fn is_hexagon(center: VertexCoord, occupied: &HashSet<TileCoord>) -> bool {
+    center
+        .triangles_around()
+        .iter()
+        .all(|tile| occupied.contains(tile))
+}
+
+/// The centers of all hexagons formed by the `occupied` triangles, sorted by x and then y.
+pub fn completed_hexagons(occupied: &HashSet<TileCoord>) -> Vec<VertexCoord> {
+    let mut centers: Vec<VertexCoord> = occupied
+        .iter()
+        .flat_map(|tile| tile.vertices())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .filter(|&center| is_hexagon(center, occupied))
+        .collect();
+    centers.sort_by_key(|v| (v.x, v.y));
+    centers
+}
+
+/// The new positions of `tiles` after the rotation, or `None` if a rotated tile would land on a
+/// blocked position. The `tiles` themselves never block.
+pub fn try_rotate(
+    tiles: &[TileCoord],
+    anchor: VertexCoord,
+    dir: RotationDir,
+    is_blocked: impl Fn(TileCoord) -> bool,
+) -> Option<Vec<TileCoord>> {
+    tiles
+        .iter()
+        .map(|&tile| dir.rotate(tile, anchor))
+        .map(|new| (tiles.contains(&new) || !is_blocked(new)).then_some(new))
+        .collect()
+}
+
+/// All moves that don't collide with other triangles or immovables and keep every vertex inside
+/// `bounds`. The anchors are the vertices of the clump's triangles.
+pub fn legal_moves(
+    clumps: &[Vec<TileCoord>],
+    all_triangles: &HashSet<TileCoord>,
+    immovables: &HashSet<TileCoord>,
+    bounds: (VertexCoord, VertexCoord),
+) -> Vec<Move> {
+    let is_blocked = |tile: TileCoord| {
+        all_triangles.contains(&tile) || immovables.contains(&tile) || !within_bounds(tile, bounds)
+    };
+
+    let mut moves = Vec::new();
+    for (clump, tiles) in clumps.iter().enumerate() {
+        let mut anchors: Vec<VertexCoord> = tiles
+            .iter()
+            .flat_map(|tile| tile.vertices())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        anchors.sort_by_key(|v| (v.x, v.y));
+
+        for anchor in anchors {
+            for dir in RotationDir::ALL {
+                if try_rotate(tiles, anchor, dir, is_blocked).is_some() {
+                    moves.push(Move { clump, anchor, dir });
+                }
+            }
+        }
+    }
+    moves
+}
+
+/// The triangles of a clump with their colors, as the [`solve`]r sees them.
+pub type ColoredClump = Vec<(TileCoord, TileColor)>;
+
+/// The clumps after `m`. The moved clump merges with every clump it touches with a triangle of
+/// the same color, `contacts` are the tiles touching a tile as in the game's contact rule.
+fn apply_move(
+    clumps: &[ColoredClump],
+    m: Move,
+    contacts: &impl Fn(TileCoord) -> Vec<TileCoord>,
+) -> Vec<ColoredClump> {
+    let mut merged: ColoredClump = clumps[m.clump]
+        .iter()
+        .map(|&(tile, color)| (m.dir.rotate(tile, m.anchor), color))
+        .collect();
+    let touched: HashSet<(TileCoord, TileColor)> = merged
+        .iter()
+        .flat_map(|&(tile, color)| contacts(tile).into_iter().map(move |c| (c, color)))
+        .collect();
+
+    let mut after = Vec::new();
+    for (i, clump) in clumps.iter().enumerate() {
+        if i == m.clump {
+            continue;
+        }
+        if clump.iter().any(|triangle| touched.contains(triangle)) {
+            merged.extend(clump.iter().copied());
+        } else {
+            after.push(clump.clone());
+        }
+    }
+    after.push(merged);
+
+    // The same board always has the same clump order
+    let key = |&(tile, color): &(TileCoord, TileColor)| {
+        (
+            tile.0.x,
+            tile.0.y,
+            tile.1 == TriangleOrient::PointingDown,
+            color.0,
+        )
+    };
+    for clump in after.iter_mut() {
+        clump.sort_by_key(key);
+    }
+    after.sort_by_key(|clump| clump.iter().map(key).collect::<Vec<_>>());
+    after
+}
+
+/// The fewest moves lighting all `runes`, given by their required cells and color, or `None` if
+/// it takes more than `max_moves`. Moves are limited like in [`legal_moves`] and clumps merge
+/// as in the game, see [`apply_move`].
+///
+/// The clump of a move is the index into the clumps after the previous move, which are sorted by
+/// their tiles.
+pub fn solve(
+    clumps: &[ColoredClump],
+    immovables: &HashSet<TileCoord>,
+    runes: &[(Vec<TileCoord>, TileColor)],
+    bounds: (VertexCoord, VertexCoord),
+    contacts: impl Fn(TileCoord) -> Vec<TileCoord>,
+    max_moves: usize,
+) -> Option<Vec<Move>> {
+    // A level without runes is never completed
+    if runes.is_empty() {
+        return None;
+    }
+    let is_lit = |clumps: &[ColoredClump]| {
+        let occupied: HashSet<(TileCoord, TileColor)> = clumps.iter().flatten().copied().collect();
+        runes
+            .iter()
+            .all(|(cells, color)| cells.iter().all(|&cell| occupied.contains(&(cell, *color))))
+    };
+
+    // Breadth first, so the first lit board is reached with the fewest moves
+    let mut visited: HashSet<Vec<ColoredClump>> = HashSet::new();
+    let mut frontier = vec![(clumps.to_vec(), Vec::new())];
+    for depth in 0..=max_moves {
+        let mut next = Vec::new();
+        for (board, path) in frontier {
+            if is_lit(&board) {
+                return Some(path);
+            }
+            if depth == max_moves {
+                continue;
+            }
+            let tiles: Vec<Vec<TileCoord>> = board
+                .iter()
+                .map(|clump| clump.iter().map(|&(tile, _)| tile).collect())
+                .collect();
+            let occupied: HashSet<TileCoord> = tiles.iter().flatten().copied().collect();
+            for m in legal_moves(&tiles, &occupied, immovables, bounds) {
+                let after = apply_move(&board, m, &contacts);
+                if visited.insert(after.clone()) {
+                    let mut path = path.clone();
+                    path.push(m);
+                    next.push((after, path));
+                }
+            }
+        }
+        frontier = next;
+    }
+    None
+}
+
+#[test]
+fn test_legal_moves() {
+    use crate::tilemap::TriangleOrient::*;
+
+    let single = vec![(VertexCoord::ZERO, PointingUp)];
+    let mut all_triangles: HashSet<TileCoord> = single.iter().cloned().collect();
+    let immovables: HashSet<TileCoord> = [(VertexCoord::ZERO, PointingDown)].into_iter().collect();
+    // Excludes everything left of the y axis
+    let bounds = (VertexCoord::new(0, -2), VertexCoord::new(2, 2));
+
+    let moves = legal_moves(&[single.clone()], &all_triangles, &immovables, bounds);
+    assert_eq!(
+        moves,
+        vec![
+            Move {
+                clump: 0,
+                anchor: VertexCoord::new(0, 1),
+                dir: RotationDir::CounterClockwise,
+            },
+            Move {
+                clump: 0,
+                anchor: VertexCoord::new(1, 0),
+                dir: RotationDir::Clockwise,
+            },
+        ]
+    );
+
+    // Both moves now end on the other clump
+    let other = vec![(VertexCoord::new(0, 1), PointingDown)];
+    all_triangles.extend(other.iter().cloned());
+    let moves = legal_moves(&[single, other], &all_triangles, &immovables, bounds);
+    assert!(moves.iter().all(|m| m.clump == 1));
+}
+
+#[test]
+fn test_is_boundary_closed() {
+    use crate::tilemap::TriangleOrient::*;
+
+    // A hexagonal ring of radius 3 around the origin, two triangles thick
+    let in_hexagon = |tile: &TileCoord, r: i32| {
+        tile.vertices()
+            .iter()
+            .all(|v| v.x.abs() <= r && v.y.abs() <= r && (v.x + v.y).abs() <= r)
+    };
+    let ring: Vec<TileCoord> = (-4..=4)
+        .flat_map(|x| (-4..=4).map(move |y| VertexCoord::new(x, y)))
+        .flat_map(|v| [(v, PointingUp), (v, PointingDown)])
+        .filter(|tile| in_hexagon(tile, 3) && !in_hexagon(tile, 2))
+        .collect();
+    assert_eq!(ring.len(), 30);
+    assert!(is_boundary_closed(&ring));
+
+    // A gap one tile wide through the wall on the left
+    let gap = [
+        (VertexCoord::new(-3, 1), PointingUp),
+        (VertexCoord::new(-3, 1), PointingDown),
+    ];
+    let open: Vec<TileCoord> = ring.into_iter().filter(|t| !gap.contains(t)).collect();
+    assert!(!is_boundary_closed(&open));
+
+    assert!(!is_boundary_closed(&[]));
+
+    let border: Vec<TileCoord> = serde_json::from_str(crate::BORDER_COORDS).unwrap();
+    assert!(is_boundary_closed(&border));
+}
+
+#[test]
+fn test_hexagons() {
+    let center = VertexCoord::new(2, -1);
+    let hexagon: Vec<TileCoord> = center.triangles_around().into_iter().collect();
+    // The hexagon is every sixth turn of one triangle around its center
+    let up = (center, TriangleOrient::PointingUp);
+    for steps in 0..6 {
+        assert!(hexagon.contains(&up.rotated_n(center, steps)));
+    }
+    // Each triangle touches its two neighbors in the hexagon
+    for tile in hexagon.iter() {
+        let inner = tile
+            .iter_neighbors()
+            .filter(|neighbor| hexagon.contains(neighbor))
+            .count();
+        assert_eq!(inner, 2);
+    }
+
+    let mut occupied: HashSet<TileCoord> = hexagon.iter().cloned().collect();
+    assert!(is_hexagon(center, &occupied));
+    assert_eq!(completed_hexagons(&occupied), vec![center]);
+
+    occupied.remove(&hexagon[3]);
+    occupied.insert((VertexCoord::new(5, 5), TriangleOrient::PointingDown));
+    assert!(!is_hexagon(center, &occupied));
+    assert!(completed_hexagons(&occupied).is_empty());
+}
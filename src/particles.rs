@@ -0,0 +1,234 @@
+//! Short-lived sprites flying around, e.g. the confetti when a level is won.
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{
+    level::{LevelCompleted, LevelId},
+    settings::Settings,
+    tilemap::RuneTile,
+    GameState,
+};
+
+const CONFETTI_COUNT: usize = 80;
+/// Sparks flying off every rune when a level is won
+const SPARKS_PER_RUNE: usize = 50;
+const SPARK_COLOR: Color = Color::rgb(1.0, 0.9, 0.6);
+/// Downwards acceleration of all particles in world units per second squared
+const GRAVITY: f32 = 600.;
+const CONFETTI_COLORS: [Color; 4] = [
+    Color::rgb(0.8, 0.1, 0.2),
+    Color::rgb(0.95, 0.8, 0.2),
+    Color::rgb(0.3, 0.6, 0.9),
+    Color::WHITE,
+];
+
+pub struct MagnateParticlesPlugin;
+
+impl Plugin for MagnateParticlesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(GameState::Next)
+                .with_system(confetti_on_win)
+                .with_system(sparks_on_win)
+                .with_system(update_particles)
+                .with_system(fade_particles.after(update_particles)),
+        );
+    }
+}
+
+#[derive(Component, Default, Debug, Clone)]
+pub struct Particle {
+    pub velocity: Vec2,
+    /// Seconds until the particle is despawned
+    pub lifetime: f32,
+    pub age: f32,
+}
+
+/// Particles with this fade out over their lifetime.
+#[derive(Component, Default, Debug, Clone)]
+pub struct FadeOut;
+
+/// Bursts confetti from the top of the screen whenever a level is won.
+fn confetti_on_win(
+    mut commands: Commands,
+    mut won: EventReader<LevelCompleted>,
+    camera: Query<&Transform, With<Camera2d>>,
+    settings: Res<Settings>,
+) {
+    if won.iter().count() == 0 || settings.reduced_motion {
+        return;
+    }
+    let center = camera
+        .get_single()
+        .map_or(Vec2::ZERO, |transf| transf.translation.truncate());
+
+    let mut rng = rand::thread_rng();
+    for i in 0..CONFETTI_COUNT {
+        let position = center + Vec2::new(rng.gen_range(-640. ..640.), 380.);
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: CONFETTI_COLORS[i % CONFETTI_COLORS.len()],
+                    custom_size: Some(Vec2::new(12., 7.)),
+                    ..Default::default()
+                },
+                transform: Transform::from_translation(position.extend(900.)).with_rotation(
+                    Quat::from_rotation_z(rng.gen_range(0. ..std::f32::consts::TAU)),
+                ),
+                ..Default::default()
+            })
+            .insert(Particle {
+                velocity: Vec2::new(rng.gen_range(-150. ..150.), rng.gen_range(-100. ..150.)),
+                lifetime: rng.gen_range(1.5..2.5),
+                age: 0.,
+            });
+    }
+}
+
+/// Bursts small sparks from every rune whenever a level is won.
+fn sparks_on_win(
+    mut commands: Commands,
+    mut won: EventReader<LevelCompleted>,
+    runes: Query<&GlobalTransform, With<RuneTile>>,
+    settings: Res<Settings>,
+) {
+    if won.iter().count() == 0 || settings.reduced_motion {
+        return;
+    }
+
+    let mut rng = rand::thread_rng();
+    for rune in runes.iter() {
+        let center = rune.translation().truncate();
+        for _ in 0..SPARKS_PER_RUNE {
+            let angle = rng.gen_range(0. ..std::f32::consts::TAU);
+            let speed = rng.gen_range(80. ..320.);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: SPARK_COLOR,
+                        custom_size: Some(Vec2::splat(5.)),
+                        ..Default::default()
+                    },
+                    transform: Transform::from_translation(center.extend(900.)),
+                    ..Default::default()
+                })
+                .insert(Particle {
+                    velocity: Vec2::new(angle.cos(), angle.sin()) * speed,
+                    lifetime: rng.gen_range(0.8..1.2),
+                    age: 0.,
+                })
+                .insert(FadeOut);
+        }
+    }
+}
+
+fn fade_particles(mut particles: Query<(&Particle, &mut Sprite), With<FadeOut>>) {
+    for (particle, mut sprite) in particles.iter_mut() {
+        let alpha = 1. - particle.age / particle.lifetime.max(f32::EPSILON);
+        sprite.color.set_a(alpha.clamp(0., 1.));
+    }
+}
+
+fn update_particles(
+    mut commands: Commands,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform)>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    for (id, mut particle, mut transf) in particles.iter_mut() {
+        particle.age += delta;
+        if particle.age >= particle.lifetime {
+            commands.entity(id).despawn();
+            continue;
+        }
+        particle.velocity.y -= GRAVITY * delta;
+        transf.translation += (particle.velocity * delta).extend(0.);
+        transf.rotate_z(3. * delta);
+    }
+}
+
+#[test]
+fn test_confetti() {
+    use std::time::{Duration, Instant};
+
+    let mut world = World::new();
+    world.insert_resource(Events::<LevelCompleted>::default());
+    world.insert_resource(Settings::default());
+    let start = Instant::now();
+    let mut time = Time::default();
+    time.update_with_instant(start);
+    world.insert_resource(time);
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(confetti_on_win)
+        .add_system(update_particles.after(confetti_on_win));
+
+    world
+        .resource_mut::<Events<LevelCompleted>>()
+        .send(LevelCompleted {
+            level: LevelId::Builtin(1),
+            moves: 0,
+            par_reached: false,
+        });
+    stage.run(&mut world);
+    let mut particles = world.query::<&Particle>();
+    assert_eq!(particles.iter(&world).count(), CONFETTI_COUNT);
+
+    world
+        .resource_mut::<Time>()
+        .update_with_instant(start + Duration::from_secs(3));
+    stage.run(&mut world);
+    assert_eq!(particles.iter(&world).count(), 0);
+}
+
+#[test]
+fn test_rune_sparks() {
+    use std::time::{Duration, Instant};
+
+    let mut world = World::new();
+    world.insert_resource(Events::<LevelCompleted>::default());
+    world.insert_resource(Settings::default());
+    let start = Instant::now();
+    let mut time = Time::default();
+    time.update_with_instant(start);
+    world.insert_resource(time);
+    for x in [0., 100.] {
+        world
+            .spawn()
+            .insert(RuneTile::default())
+            .insert(GlobalTransform::from_xyz(x, 0., 0.));
+    }
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(sparks_on_win)
+        .add_system(update_particles.after(sparks_on_win))
+        .add_system(fade_particles.after(update_particles));
+
+    world
+        .resource_mut::<Events<LevelCompleted>>()
+        .send(LevelCompleted {
+            level: LevelId::Builtin(1),
+            moves: 0,
+            par_reached: false,
+        });
+    stage.run(&mut world);
+    let mut particles = world.query_filtered::<&Sprite, With<FadeOut>>();
+    assert_eq!(particles.iter(&world).count(), 2 * SPARKS_PER_RUNE);
+
+    world
+        .resource_mut::<Time>()
+        .update_with_instant(start + Duration::from_millis(500));
+    stage.run(&mut world);
+    assert!(particles
+        .iter(&world)
+        .all(|sprite| sprite.color.a() > 0. && sprite.color.a() < 1.));
+
+    world
+        .resource_mut::<Time>()
+        .update_with_instant(start + Duration::from_secs(2));
+    stage.run(&mut world);
+    assert_eq!(particles.iter(&world).count(), 0);
+}
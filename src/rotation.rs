@@ -1,40 +1,220 @@
+use std::{collections::VecDeque, marker::PhantomData};
+
 use bevy::{
+    ecs::system::SystemParam,
     prelude::*,
+    sprite::MaterialMesh2dBundle,
+    transform::TransformSystem,
     utils::{HashMap, HashSet},
 };
-use bevy_point_selection::SelectionIndicator;
+use bevy_point_selection::{Selectable, SelectionIndicator, SelectionSet};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    level::{ReloadHint, RotationHint, SoftDespawned},
+    camera::CameraShake,
+    level::{
+        trigger_hints, BoardDirty, HintTrigger, LevelInfo, RotationHint, SoftDespawned,
+        TutorialHint,
+    },
+    level_editor::EditorEnabled,
+    menu::EscapeClaimed,
+    moves::{self, try_rotate, RotationDir},
+    settings::Settings,
     tilemap::{
-        FromWorldPosition, IterNeighbors, RotateAroundVertex, TileCoord, TransformInWorld,
-        TriangleTile, VertexCoord, TRIANGLE_SIDE,
+        ClumpRoot, FromWorldPosition, GridConfig, IterNeighbors, TileColor, TileCoord,
+        TileVertices, TransformInWorld, TriangleTile, TrianglesAroundVertex, VertexCoord,
+        TRIANGLE_SIDE,
     },
-    GameState, SpriteAssets,
+    AssetHandles, GameState, InputSuppressed, SpriteAssets,
 };
 
 #[derive(Component, Default)]
 pub struct SelectedTrianglesState {
-    /// The entity ids of all currently selected [`TriangleTile`]
+    /// The entity ids of all currently selected [`TriangleTile`], possibly of several clumps
     pub selected_set: HashSet<Entity>,
     /// The coordinate of the rotation point if it exists, otherwise undefined
     pub anchor: VertexCoord,
+    /// Clumps pinned with `Shift` + click or hovered while `Shift` is held, which are rotated
+    /// together with the hovered one until `Escape` releases them
+    pub pinned: HashSet<Entity>,
+    /// Set while the selection comes from a [`ForceSelection`], which follows its clump through
+    /// rotations and merges until the cursor moves
+    pub forced: bool,
+}
+
+impl SelectedTrianglesState {
+    /// The distinct clumps of the selected triangles.
+    pub fn selected_clumps(&self, parents: &Query<&Parent>) -> HashSet<Entity> {
+        parents
+            .iter_many(self.selected_set.iter())
+            .map(Parent::get)
+            .collect()
+    }
+}
+
+/// Send this event to select a clump programmatically, e.g. to guide the player in a tutorial.
+/// The forced selection is kept until the cursor moves. `Tab` sends it for the next corner.
+pub struct ForceSelection {
+    pub clump: Entity,
+    /// The vertex to rotate around, the left vertex of the first triangle of the clump if `None`
+    pub anchor: Option<VertexCoord>,
+}
+
+/// Resource deciding when two triangles touch, so that their clumps are merged.
+/// It is stored per level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ContactRule {
+    /// Triangles sharing an edge touch
+    #[default]
+    SharedEdge,
+    /// Triangles sharing only a vertex touch
+    SharedVertex,
+    /// Triangles sharing either an edge or a vertex touch
+    Both,
+}
+
+impl ContactRule {
+    /// All the tiles touching `coord` according to this rule.
+    pub fn contacts(&self, coord: TileCoord) -> Vec<TileCoord> {
+        let edge_neighbors: Vec<TileCoord> = coord.iter_neighbors().collect();
+        // Every triangle that shares only a vertex is around exactly one of the corners
+        let vertex_neighbors: Vec<TileCoord> = coord
+            .vertices()
+            .into_iter()
+            .flat_map(|v| v.triangles_around())
+            .filter(|other| *other != coord && !edge_neighbors.contains(other))
+            .collect();
+
+        match self {
+            ContactRule::SharedEdge => edge_neighbors,
+            ContactRule::SharedVertex => vertex_neighbors,
+            ContactRule::Both => [edge_neighbors, vertex_neighbors].concat(),
+        }
+    }
+}
+
+/// Sent by the [`rotation_system`] for every triangle that was moved to a new tile.
+#[derive(Debug, Clone)]
+pub struct TileMoved {
+    pub entity: Entity,
+    pub from: TileCoord,
+    pub to: TileCoord,
+}
+
+/// Sent by the [`rotation_system`] when a rotation around `anchor` was blocked.
+#[derive(Debug, Clone)]
+pub struct MoveBlocked {
+    pub anchor: VertexCoord,
+}
+
+/// Sent by the merge system when the clump `absorbed` was merged into `survivor`.
+#[derive(Debug, Clone)]
+pub struct MergeEvent {
+    pub survivor: Entity,
+    pub absorbed: Entity,
+    /// The triangles of the absorbed clump, which now belong to the survivor
+    pub triangles: Vec<Entity>,
+    /// The number of triangles of the survivor after the merge
+    pub tile_count: usize,
+    /// The [`LevelInfo::move_count`] of the rotation which made the clumps touch, merges can be
+    /// deferred by the [`MergeBudget`] to later frames
+    pub rotation: u32,
+}
+
+/// Resource mapping the mouse buttons to their actions.
+pub struct MouseActions {
+    /// Places tiles in the level editor
+    pub select: MouseButton,
+    pub rotate_clockwise: MouseButton,
+    pub rotate_counter_clockwise: MouseButton,
+    /// Reserved for context menus, clicking it never rotates
+    pub context: Option<MouseButton>,
+}
+
+impl Default for MouseActions {
+    fn default() -> Self {
+        Self {
+            select: MouseButton::Left,
+            rotate_clockwise: MouseButton::Right,
+            rotate_counter_clockwise: MouseButton::Left,
+            context: None,
+        }
+    }
 }
 
+/// Rotations are blocked if they would move a vertex of a triangle outside `min` and `max`, so
+/// nothing escapes through a gap in the border. Derived from the border when the game starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayfieldBounds {
+    pub min: VertexCoord,
+    pub max: VertexCoord,
+}
+
+impl PlayfieldBounds {
+    /// The bounds of all vertices of `tiles`.
+    pub fn from_tiles(tiles: &[TileCoord]) -> Self {
+        let (min, max) = moves::bounds(tiles.iter().cloned());
+        Self { min, max }
+    }
+
+    pub fn contains(&self, tile: TileCoord) -> bool {
+        moves::within_bounds(tile, (self.min, self.max))
+    }
+}
+
+/// Whether a triangle can't be rotated onto `tile`, because it is taken or out of bounds.
+fn is_blocked(
+    tile: TileCoord,
+    occupied: &HashSet<TileCoord>,
+    bounds: Option<&PlayfieldBounds>,
+) -> bool {
+    occupied.contains(&tile) || bounds.map_or(false, |bounds| !bounds.contains(tile))
+}
+
+/// Keys rotating the selection like the mouse buttons, unless `Left Control` is held for saving
+/// and loading.
+const ROTATE_CLOCKWISE_KEY: KeyCode = KeyCode::E;
+const ROTATE_COUNTER_CLOCKWISE_KEY: KeyCode = KeyCode::Q;
+
 pub struct MagnateRotationPlugin;
 
 impl Plugin for MagnateRotationPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(GameState::Next).with_system(spawn_selector))
+        app.add_event::<ForceSelection>()
+            .add_event::<TileMoved>()
+            .add_event::<MoveBlocked>()
+            .add_event::<MergeEvent>()
+            .init_resource::<ContactRule>()
+            .init_resource::<MouseActions>()
+            .init_resource::<MergeQueue>()
+            .init_resource::<MergeBudget>()
+            .init_resource::<MergeEnabled>()
+            .add_system_set(SystemSet::on_enter(GameState::Next).with_system(spawn_selector))
             .add_system_set(
                 SystemSet::on_update(GameState::Next)
                     // The ordering here is important, because the merge system interacts via commands,
                     // so its changes get picked up by triangle_selection_system only in the next frame,
                     // but it would happily run in the same frame and miss the changes.
-                    .with_system(triangle_selection_system.before(rotation_system))
+                    .with_system(cycle_selection_system.before(triangle_selection_system))
+                    .with_system(
+                        triangle_selection_system
+                            .after(SelectionSet::UpdateIndicator)
+                            .before(rotation_system),
+                    )
                     .with_system(rotation_system.before(merge_system))
-                    .with_system(merge_system),
-            );
+                    .with_system(merge_system)
+                    .with_system(rotation_animation_system.after(rotation_system))
+                    .with_system(rotation_ghost_system.after(rotation_system))
+                    .with_system(pivot_marker_system.after(triangle_selection_system))
+                    .with_system(hover_feedback_system.after(SelectionSet::UpdateSelectables)),
+            )
+            // The jiggle offset is only applied for rendering, so other systems see the rest position
+            .add_system_to_stage(CoreStage::PreUpdate, remove_jiggle_offset)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                jiggle_system.before(TransformSystem::TransformPropagate),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, claim_escape_system);
     }
 }
 
@@ -56,36 +236,201 @@ fn spawn_selector(mut commands: Commands, assets: Res<SpriteAssets>) {
         .insert(SelectionIndicator::new())
         .insert(SelectedTrianglesState::default())
         .insert(Name::new("Triangle Selector"));
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::splat(PIVOT_MARKER_SIZE)),
+                color: PIVOT_MARKER_COLOR,
+                ..Default::default()
+            },
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        .insert(PivotMarker)
+        .insert(Name::new("Pivot Marker"));
+}
+
+const PIVOT_MARKER_SIZE: f32 = 0.12 * TRIANGLE_SIDE;
+const PIVOT_MARKER_COLOR: Color = Color::rgb(1., 0.85, 0.3);
+/// Above the selection indicator
+const PIVOT_MARKER_Z: f32 = 901.;
+
+/// A dot on the vertex the selected triangles rotate around.
+#[derive(Component, Debug, Default, Clone)]
+pub struct PivotMarker;
+
+/// Moves the [`PivotMarker`] to the anchor of the selection, it's hidden without a selection.
+fn pivot_marker_system(
+    selection: Query<&SelectedTrianglesState, Changed<SelectedTrianglesState>>,
+    mut markers: Query<(&mut Transform, &mut Visibility), With<PivotMarker>>,
+    grid: Res<GridConfig>,
+) {
+    let selection = match selection.get_single() {
+        Ok(selection) => selection,
+        Err(_) => return,
+    };
+    let active = !selection.selected_set.is_empty();
+    for (mut transf, mut visibility) in markers.iter_mut() {
+        if active {
+            transf.translation = grid.vertex_pos(selection.anchor).extend(PIVOT_MARKER_Z);
+        }
+        if visibility.is_visible != active {
+            visibility.is_visible = active;
+        }
+    }
+}
+
+/// Selects the next corner of a clump with `Tab` and the previous one with `Shift` + `Tab`. All
+/// corners of a clump come before the ones of the next clump.
+fn cycle_selection_system(
+    selection: Query<&SelectedTrianglesState>,
+    parents: Query<&Parent>,
+    clumps: Query<(Entity, &Children), With<ClumpRoot>>,
+    triangles: Query<&TriangleTile>,
+    keys: Res<Input<KeyCode>>,
+    suppressed: Res<InputSuppressed>,
+    mut forced: EventWriter<ForceSelection>,
+) {
+    if !keys.just_pressed(KeyCode::Tab) || suppressed.any() {
+        return;
+    }
+    let mut corners: Vec<(Entity, VertexCoord)> = clumps
+        .iter()
+        .flat_map(|(clump, children)| {
+            triangles
+                .iter_many(children.iter())
+                .flat_map(|tile| tile.vertices())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .map(move |vertex| (clump, vertex))
+        })
+        .collect();
+    if corners.is_empty() {
+        return;
+    }
+    corners.sort_by_key(|(clump, vertex)| (*clump, vertex.x, vertex.y));
+
+    let current = selection
+        .get_single()
+        .ok()
+        .filter(|selection| selection.forced)
+        .and_then(|selection| {
+            let clump = selection.selected_clumps(&parents).into_iter().next()?;
+            corners
+                .iter()
+                .position(|corner| *corner == (clump, selection.anchor))
+        });
+    let next = match current {
+        Some(i) if keys.pressed(KeyCode::LShift) => (i + corners.len() - 1) % corners.len(),
+        Some(i) => (i + 1) % corners.len(),
+        None => 0,
+    };
+    let (clump, anchor) = corners[next];
+    forced.send(ForceSelection {
+        clump,
+        anchor: Some(anchor),
+    });
 }
 
 /// This system walkes the hierarchy if the vertex selection changed to pre calculate all the
-/// affected triangles.
+/// affected triangles. A [`ForceSelection`] event takes precedence over the cursor selection
+/// until the cursor moves.
+/// `Escape` releases all pinned clumps, in that case it doesn't open the menu, see
+/// [`claim_escape_system`].
 fn triangle_selection_system(
     mut indicator: Query<(
         &mut SelectedTrianglesState,
         &SelectionIndicator,
         ChangeTrackers<SelectionIndicator>,
     )>,
-    parents: Query<(&Parent, &GlobalTransform)>,
-    children: Query<&Children>,
+    parents: Query<&Parent>,
+    handles: Query<(&Selectable, &GlobalTransform)>,
+    children: Query<&Children, With<ClumpRoot>>,
     changed_triangles: Query<Entity, Changed<TriangleTile>>,
+    triangles: Query<&TriangleTile>,
+    mut forced: EventReader<ForceSelection>,
+    mut cursor: EventReader<CursorMoved>,
+    keys: Res<Input<KeyCode>>,
+    mouse_btn: Res<Input<MouseButton>>,
+    actions: Res<MouseActions>,
+    suppressed: Res<InputSuppressed>,
+    grid: Res<GridConfig>,
 ) {
     let triangles_changed = !changed_triangles.is_empty();
+    let shift = keys.pressed(KeyCode::LShift) && !suppressed.any();
+    let pin_clicked = shift && mouse_btn.just_pressed(actions.select);
+    let force = forced.iter().last();
+    let cursor_moved = cursor.iter().count() > 0;
 
     let (mut selection_state, indicator, selection_change) = match indicator.get_single_mut() {
         Ok(x) => x,
-        Err(_) => return,
+        Err(e) => {
+            debug!("No selection indicator: {}", e);
+            return;
+        }
     };
 
+    let clump_triangles = |clump: Entity| -> Vec<Entity> {
+        children.get(clump).map_or(Vec::new(), |c| {
+            c.iter()
+                .filter(|eid| triangles.contains(**eid))
+                .cloned()
+                .collect()
+        })
+    };
+    if let Some(force) = force {
+        let selected = clump_triangles(force.clump);
+        if selected.is_empty() {
+            return;
+        }
+        // Rotate around the left vertex of the first triangle in the clump by default
+        if let Some(anchor) = force.anchor.or_else(|| {
+            let first = triangles.get(selected[0]).ok()?;
+            Some(first.position.0)
+        }) {
+            selection_state.anchor = anchor;
+        }
+        selection_state.selected_set = selected.into_iter().collect();
+        selection_state.forced = true;
+        return;
+    }
+    let cursor_took_over = selection_state.forced && cursor_moved;
+    if selection_state.forced && !cursor_moved {
+        // Follow the clump, it might have absorbed or been absorbed by another one
+        selection_state.selected_set = selection_state
+            .selected_clumps(&parents)
+            .into_iter()
+            .flat_map(clump_triangles)
+            .collect();
+        // Gone with a reload
+        selection_state.forced = !selection_state.selected_set.is_empty();
+        return;
+    }
+    selection_state.forced = false;
+
+    let pins_released = keys.just_pressed(KeyCode::Escape) && !selection_state.pinned.is_empty();
+    if pins_released {
+        selection_state.pinned.clear();
+    }
+
     // only update when the selection or the triangles changed
-    if !(triangles_changed || selection_change.is_changed()) {
+    if !(triangles_changed
+        || selection_change.is_changed()
+        || pin_clicked
+        || pins_released
+        || cursor_took_over)
+    {
         return;
     }
+    // Forget merged clumps
+    selection_state
+        .pinned
+        .retain(|clump| children.get(*clump).is_ok());
 
     let selected_triggers: Vec<_> = indicator
         .selected_triggers
         .iter()
-        .filter_map(|eid| parents.get(*eid).ok())
+        .filter_map(|eid| Some((parents.get(*eid).ok()?, handles.get(*eid).ok()?)))
         .collect();
 
     if selected_triggers.is_empty() {
@@ -95,21 +440,32 @@ fn triangle_selection_system(
     }
 
     // This way I don't have to update another coordinate in the triangle vertices.
-    let anchor: VertexCoord = FromWorldPosition::from_world_pos(
-        selected_triggers
-            .first()
-            .expect("vector is not empty")
-            .1
-            .translation()
-            .truncate(),
+    let (selectable, handle_transf) = selected_triggers.first().expect("vector is not empty").1;
+    let anchor: VertexCoord = FromWorldPosition::from_world_pos_in(
+        selectable.placement(handle_transf).translation().truncate(),
+        &grid,
     );
 
-    // Entity id of all triangles that are either parent of a selector or siblings of a parent of a selector
+    // Clumps of the triangles that are parent of a selector
     // All triangles must have a parent for this to work
-    let triangles_to_be_rotated: HashSet<Entity> = selected_triggers
+    let hovered_clumps: HashSet<Entity> = selected_triggers
         .iter()
         .filter_map(|(selector_par, _)| parents.get(selector_par.get()).ok())
-        .filter_map(|(triangle_par, _)| children.get(triangle_par.get()).ok())
+        .map(|triangle_par| triangle_par.get())
+        .collect();
+    // Clicking or sweeping over corners with Shift held adds their clumps. Clicks never unpin,
+    // since sweeping onto the corner already pinned the clump.
+    if pin_clicked || (shift && selection_change.is_changed()) {
+        selection_state
+            .pinned
+            .extend(hovered_clumps.iter().copied());
+    }
+
+    // Entity id of all triangles in the hovered or pinned clumps
+    let triangles_to_be_rotated: HashSet<Entity> = hovered_clumps
+        .iter()
+        .chain(selection_state.pinned.iter())
+        .filter_map(|clump| children.get(*clump).ok())
         .flat_map(|clump_children| clump_children.iter())
         .cloned()
         .collect();
@@ -118,120 +474,1555 @@ fn triangle_selection_system(
     selection_state.selected_set = triangles_to_be_rotated;
 }
 
+/// Claims `Escape` while clumps are pinned, so that it releases them instead of opening the menu.
+/// Runs after the update, so the menu sees the pins from the time the key was pressed.
+fn claim_escape_system(
+    indicator: Query<&SelectedTrianglesState>,
+    mut claimed: ResMut<EscapeClaimed>,
+) {
+    claimed.0 = indicator
+        .iter()
+        .any(|selection| !selection.pinned.is_empty());
+}
+
+/// The mouse buttons and keys which rotate the selected triangles.
+#[derive(SystemParam)]
+pub struct RotationInput<'w, 's> {
+    mouse_btn: Res<'w, Input<MouseButton>>,
+    keys: Res<'w, Input<KeyCode>>,
+    actions: Res<'w, MouseActions>,
+    suppressed: Res<'w, InputSuppressed>,
+    editor: Res<'w, EditorEnabled>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> RotationInput<'w, 's> {
+    /// The direction of the rotation requested in this frame, if any.
+    fn direction(&self) -> Option<RotationDir> {
+        let (mouse_btn, keys, actions) = (&self.mouse_btn, &self.keys, &self.actions);
+        if self.suppressed.any() {
+            return None;
+        }
+        if keys.pressed(KeyCode::LShift) {
+            // Shift + click pins clumps instead
+            return None;
+        }
+        if keys.pressed(KeyCode::LAlt) && self.editor.0 {
+            // Alt + click copies and pastes regions in the level editor
+            return None;
+        }
+        if let Some(context) = actions.context {
+            if mouse_btn.just_pressed(context) {
+                return None;
+            }
+        }
+        let key_pressed = |key| !keys.pressed(KeyCode::LControl) && keys.just_pressed(key);
+        if mouse_btn.just_pressed(actions.rotate_counter_clockwise)
+            || key_pressed(ROTATE_COUNTER_CLOCKWISE_KEY)
+        {
+            Some(RotationDir::CounterClockwise)
+        } else if mouse_btn.just_pressed(actions.rotate_clockwise)
+            || key_pressed(ROTATE_CLOCKWISE_KEY)
+        {
+            Some(RotationDir::Clockwise)
+        } else {
+            None
+        }
+    }
+}
+
 /// This system rotates selected triangles on mouse click
 fn rotation_system(
-    mouse_btn: Res<Input<MouseButton>>,
-    selection: Query<&SelectedTrianglesState>,
+    input: RotationInput,
+    selection: Query<(Entity, &SelectedTrianglesState)>,
     mut triangles: Query<(Entity, &mut Transform, &mut TriangleTile)>,
     mut commands: Commands,
     hint: Query<Entity, (With<RotationHint>, Without<SoftDespawned>)>,
+    mut hints: Query<(&TutorialHint, &mut Visibility)>,
     time: Res<Time>,
+    mut moved: EventWriter<TileMoved>,
+    mut blocked: EventWriter<MoveBlocked>,
+    mut shake: EventWriter<CameraShake>,
+    bounds: Option<Res<PlayfieldBounds>>,
+    mut level: ResMut<LevelInfo>,
+    grid: Res<GridConfig>,
 ) {
-    if !mouse_btn.any_just_pressed([MouseButton::Left, MouseButton::Right]) {
-        return;
-    }
+    let dir = match input.direction() {
+        Some(dir) => dir,
+        None => return,
+    };
 
-    let selection = selection
-        .get_single()
-        .expect("Indicator hasn't been spawned yet!");
-
-    let mut update_set: Vec<(Entity, TileCoord)> = Vec::new();
-    for (eid, _, coord) in triangles.iter_many(selection.selected_set.iter()) {
-        let new_vertex: TileCoord = if mouse_btn.just_pressed(MouseButton::Left) {
-            // Counter clockwise
-            coord.position.rotated_counter_clockwise(selection.anchor)
-        } else if mouse_btn.just_pressed(MouseButton::Right) {
-            // Clockwise
-            coord.position.rotated_clockwise(selection.anchor)
-        } else {
-            // Do nothing
-            coord.position
-        };
-        // delay updating until all collision have been checked
-        update_set.push((eid, new_vertex));
+    let (indicator, selection) = match selection.get_single() {
+        Ok(selection) => selection,
+        Err(e) => {
+            debug!("No selection to rotate: {}", e);
+            return;
+        }
+    };
 
-        // collision check
-        for (other_id, _, other) in triangles.iter() {
-            if !selection.selected_set.contains(&other_id) && new_vertex == other.position {
-                // todo: visual indicator
+    let (selected_ids, selected_tiles): (Vec<Entity>, Vec<TileCoord>) = triangles
+        .iter_many(selection.selected_set.iter())
+        .map(|(eid, _, tile)| (eid, tile.position))
+        .unzip();
+    let occupied: HashSet<TileCoord> = triangles.iter().map(|(_, _, tile)| tile.position).collect();
+
+    // delay updating until all collision have been checked
+    let update_set: Vec<(Entity, TileCoord)> =
+        match try_rotate(&selected_tiles, selection.anchor, dir, |tile| {
+            is_blocked(tile, &occupied, bounds.as_deref())
+        }) {
+            Some(new_tiles) => selected_ids.into_iter().zip(new_tiles).collect(),
+            None => {
                 warn!("Something is in the way!");
+                commands
+                    .entity(indicator)
+                    .insert(BlockedFeedback::default());
+                blocked.send(MoveBlocked {
+                    anchor: selection.anchor,
+                });
+                shake.send(CameraShake {
+                    intensity: 3.,
+                    duration: 0.15,
+                });
                 return;
             }
-        }
-    }
+        };
 
-    if let Ok(id) = hint.get_single() {
-        if !update_set.is_empty() {
+    if !update_set.is_empty() {
+        level.move_count += 1;
+        // Only the hints shown so far, not the ones this rotation reveals
+        let shown = hint
+            .iter()
+            .filter(|id| hints.get(*id).map_or(true, |(_, vis)| vis.is_visible));
+        for id in shown {
             commands.entity(id).insert(SoftDespawned {
                 death_time: time.time_since_startup(),
             });
         }
+        trigger_hints(&mut hints, HintTrigger::OnFirstRotate);
     }
 
-    // Commit updates
+    // Commit updates, the transforms follow with an animation
+    let anchor_world = grid.vertex_pos(selection.anchor);
     for (eid, new_vertex) in update_set {
         if let Ok((_, mut transf, mut coord)) = triangles.get_mut(eid) {
+            moved.send(TileMoved {
+                entity: eid,
+                from: coord.position,
+                to: new_vertex,
+            });
+            // Skips the rest of a running animation
+            let from = coord.to_world_pos_in(&grid);
+            *transf = from;
             coord.position = new_vertex;
-            *transf = coord.to_world_pos();
+            commands.entity(eid).insert(RotationAnimation {
+                from,
+                start_angle: 0.,
+                target_angle: dir.angle(),
+                anchor_world,
+                elapsed: 0.,
+            });
+        }
+    }
+}
+
+/// Seconds a rotation takes to animate
+const ROTATION_DURATION: f32 = 0.15;
+
+/// Turns a moved triangle from its previous placement `from` around `anchor_world`. Its
+/// [`TriangleTile`] is already at the new position, so only the transform lags behind.
+#[derive(Component, Debug, Clone)]
+pub struct RotationAnimation {
+    pub from: Transform,
+    /// Angles around the z axis in radians, relative to `from`
+    pub start_angle: f32,
+    pub target_angle: f32,
+    pub anchor_world: Vec2,
+    pub elapsed: f32,
+}
+
+impl RotationAnimation {
+    /// The placement after `progress` from 0 to 1 of the animation.
+    pub fn transform_at(&self, progress: f32) -> Transform {
+        let angle = self.start_angle + (self.target_angle - self.start_angle) * progress;
+        let rotation = Quat::from_rotation_z(angle);
+        let pivot = self.anchor_world.extend(self.from.translation.z);
+        Transform {
+            translation: pivot + rotation * (self.from.translation - pivot),
+            rotation: rotation * self.from.rotation,
+            scale: self.from.scale,
+        }
+    }
+}
+
+/// Moves the transforms of rotated triangles, ending exactly at their tile.
+fn rotation_animation_system(
+    mut commands: Commands,
+    mut animating: Query<(
+        Entity,
+        &mut RotationAnimation,
+        &TriangleTile,
+        &mut Transform,
+    )>,
+    time: Res<Time>,
+    grid: Res<GridConfig>,
+) {
+    for (id, mut animation, tile, mut transf) in animating.iter_mut() {
+        animation.elapsed += time.delta_seconds();
+        if animation.elapsed >= ROTATION_DURATION {
+            *transf = tile.to_world_pos_in(&grid);
+            commands.entity(id).remove::<RotationAnimation>();
+        } else {
+            *transf = animation.transform_at(animation.elapsed / ROTATION_DURATION);
         }
     }
 }
 
+/// Seconds the indicator jiggles after a blocked rotation
+const JIGGLE_DURATION: f32 = 0.4;
+/// Initial horizontal amplitude of the jiggle in world units
+const JIGGLE_AMPLITUDE: f32 = 8.;
+/// Angular frequency of the jiggle in radians per second
+const JIGGLE_FREQUENCY: f32 = 45.;
+/// Exponential decay of the jiggle amplitude per second
+const JIGGLE_DAMPING: f32 = 8.;
+
+/// Shakes the entity horizontally with a damped sine, inserted on the [`SelectionIndicator`] by
+/// the [`rotation_system`] when a rotation is blocked. Removed once the jiggle is over.
+#[derive(Component, Default, Debug, Clone)]
+pub struct BlockedFeedback {
+    pub elapsed: f32,
+    /// The currently applied horizontal offset
+    offset: f32,
+}
+
+impl BlockedFeedback {
+    fn offset_at(elapsed: f32) -> f32 {
+        JIGGLE_AMPLITUDE * (-JIGGLE_DAMPING * elapsed).exp() * (JIGGLE_FREQUENCY * elapsed).sin()
+    }
+}
+
+fn remove_jiggle_offset(mut jiggling: Query<(&mut BlockedFeedback, &mut Transform)>) {
+    for (mut feedback, mut transf) in jiggling.iter_mut() {
+        transf.translation.x -= feedback.offset;
+        feedback.offset = 0.;
+    }
+}
+
+fn jiggle_system(
+    mut commands: Commands,
+    mut jiggling: Query<(Entity, &mut BlockedFeedback, &mut Transform)>,
+    time: Res<Time>,
+) {
+    for (id, mut feedback, mut transf) in jiggling.iter_mut() {
+        feedback.elapsed += time.delta_seconds();
+        if feedback.elapsed >= JIGGLE_DURATION {
+            commands.entity(id).remove::<BlockedFeedback>();
+            continue;
+        }
+        feedback.offset = BlockedFeedback::offset_at(feedback.elapsed);
+        transf.translation.x += feedback.offset;
+    }
+}
+
+const HOVER_DOT_SIZE: f32 = 0.1 * TRIANGLE_SIDE;
+const HOVER_DOT_COLOR: Color = Color::rgba(1., 1., 1., 0.6);
+/// Seconds until a hover dot is fully grown
+const HOVER_DOT_GROW_DURATION: f32 = 0.1;
+/// Below the selection indicator
+const HOVER_DOT_Z: f32 = 899.;
+
+/// A dot on a hovered vertex handle, which shows that the vertex can be clicked.
+#[derive(Component, Debug, Clone)]
+pub struct HoverDot {
+    pub handle: Entity,
+}
+
+/// Spawns a [`HoverDot`] when the cursor enters a vertex handle and removes it once it leaves.
+/// The dot grows in, unless [`Settings::reduced_motion`] is set, and follows its handle while the
+/// triangle rotates.
+fn hover_feedback_system(
+    mut commands: Commands,
+    changed: Query<Entity, Changed<Selectable>>,
+    handles: Query<(&Selectable, &GlobalTransform)>,
+    mut dots: Query<(Entity, &HoverDot, &mut Transform)>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+) {
+    let mut hovered = HashSet::new();
+    for (id, dot, mut transf) in dots.iter_mut() {
+        match handles.get(dot.handle) {
+            Ok((selectable, handle_transf)) if selectable.is_selected => {
+                hovered.insert(dot.handle);
+                let placement = selectable.placement(handle_transf);
+                transf.translation = placement.translation().truncate().extend(HOVER_DOT_Z);
+                let grown = transf.scale.x + time.delta_seconds() / HOVER_DOT_GROW_DURATION;
+                transf.scale = Vec3::splat(grown.min(1.));
+            }
+            // The cursor left or the triangle is gone
+            _ => commands.entity(id).despawn(),
+        }
+    }
+
+    for handle in changed.iter() {
+        let (selectable, handle_transf) = match handles.get(handle) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if !selectable.is_selected || hovered.contains(&handle) {
+            continue;
+        }
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    custom_size: Some(Vec2::splat(HOVER_DOT_SIZE)),
+                    color: HOVER_DOT_COLOR,
+                    ..Default::default()
+                },
+                transform: Transform::from_translation(
+                    selectable
+                        .placement(handle_transf)
+                        .translation()
+                        .truncate()
+                        .extend(HOVER_DOT_Z),
+                )
+                .with_scale(if settings.reduced_motion {
+                    Vec3::ONE
+                } else {
+                    Vec3::ZERO
+                }),
+                ..Default::default()
+            })
+            .insert(HoverDot { handle });
+    }
+}
+
+/// A translucent preview of where a selected triangle lands when rotated in direction `dir`.
+#[derive(Component, Debug, Clone)]
+pub struct RotationGhost {
+    pub dir: RotationDir,
+    /// The rotation is blocked by the same check as in the [`rotation_system`]
+    pub blocked: bool,
+}
+
+/// Respawns the [`RotationGhost`]s for both directions whenever the selection or any triangle
+/// changed. Blocked rotations get red ghosts.
+fn rotation_ghost_system(
+    mut commands: Commands,
+    selection: Query<(
+        &SelectedTrianglesState,
+        ChangeTrackers<SelectedTrianglesState>,
+    )>,
+    changed_triangles: Query<(), Changed<TriangleTile>>,
+    triangles: Query<(Entity, &TriangleTile)>,
+    ghosts: Query<Entity, With<RotationGhost>>,
+    assets: Res<AssetHandles>,
+    bounds: Option<Res<PlayfieldBounds>>,
+    grid: Res<GridConfig>,
+) {
+    let (selection, selection_change) = match selection.get_single() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    if !selection_change.is_changed() && changed_triangles.is_empty() {
+        return;
+    }
+    for id in ghosts.iter() {
+        commands.entity(id).despawn();
+    }
+
+    let selected_tiles: Vec<TileCoord> = triangles
+        .iter()
+        .filter(|(id, _)| selection.selected_set.contains(id))
+        .map(|(_, tile)| tile.position)
+        .collect();
+    if selected_tiles.is_empty() {
+        return;
+    }
+    let occupied: HashSet<TileCoord> = triangles.iter().map(|(_, tile)| tile.position).collect();
+
+    for dir in RotationDir::ALL {
+        let blocked = try_rotate(&selected_tiles, selection.anchor, dir, |tile| {
+            is_blocked(tile, &occupied, bounds.as_deref())
+        })
+        .is_none();
+        let material = if blocked {
+            assets.blocked_ghost_material.clone()
+        } else {
+            assets.ghost_material.clone()
+        };
+        for &tile in selected_tiles.iter() {
+            let target = TriangleTile {
+                position: dir.rotate(tile, selection.anchor),
+            };
+            // Behind the triangles, which might already cover the target
+            let mut transform = target.to_world_pos_in(&grid);
+            transform.translation.z -= 1.;
+            commands
+                .spawn_bundle(MaterialMesh2dBundle {
+                    mesh: assets.triangle_mesh.clone().into(),
+                    material: material.clone(),
+                    transform,
+                    ..Default::default()
+                })
+                .insert(RotationGhost { dir, blocked });
+        }
+    }
+}
+
+/// Clump merges waiting to be applied by the merge system in a stable order.
+#[derive(Default)]
+pub struct MergeQueue {
+    /// The merges with the rotation which caused them
+    pending: VecDeque<(Entity, Entity, u32)>,
+    /// Clumps that were already merged, mapped to the clump that absorbed them
+    absorbed: HashMap<Entity, Entity>,
+}
+
+impl MergeQueue {
+    /// Queues merges of the second clump into the first one, caused by the rotation `rotation`.
+    pub fn push(&mut self, mut merges: Vec<(Entity, Entity)>, rotation: u32) {
+        merges.sort();
+        self.pending
+            .extend(merges.into_iter().map(|(p1, p2)| (p1, p2, rotation)));
+    }
+
+    /// Drops the merges caused by the rotation `rotation`, e.g. when it's undone.
+    pub fn forget(&mut self, rotation: u32) {
+        self.pending.retain(|&(_, _, cause)| cause != rotation);
+    }
+
+    fn resolve(&self, mut clump: Entity) -> Entity {
+        while let Some(&survivor) = self.absorbed.get(&clump) {
+            clump = survivor;
+        }
+        clump
+    }
+
+    /// Takes at most `budget` merges from the queue. Clumps that were already absorbed are
+    /// replaced by their survivor and merges of a clump with itself are dropped.
+    pub fn pop_batch(&mut self, budget: Option<usize>) -> Vec<(Entity, Entity, u32)> {
+        let mut batch = Vec::new();
+        while budget.map_or(true, |budget| batch.len() < budget) {
+            let (p1, p2, rotation) = match self.pending.pop_front() {
+                Some(merge) => merge,
+                None => break,
+            };
+            let (p1, p2) = (self.resolve(p1), self.resolve(p2));
+            if p1 == p2 {
+                continue;
+            }
+            self.absorbed.insert(p2, p1);
+            batch.push((p1, p2, rotation));
+        }
+        if self.pending.is_empty() {
+            self.absorbed.clear();
+        }
+        batch
+    }
+}
+
+/// Whether touching clumps are merged.
+pub struct MergeEnabled(pub bool);
+
+impl Default for MergeEnabled {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// The maximum number of merges applied per frame. With `None` all merges happen immediately,
+/// otherwise they are deferred to the following frames one budget at a time.
+#[derive(Default)]
+pub struct MergeBudget(pub Option<usize>);
+
 // This system merges clumps of TriangleTiles that were just moved
 fn merge_system(
     mut commands: Commands,
-    changed_triangles: Query<(Entity, &TriangleTile), Changed<TriangleTile>>,
-    all_triangles: Query<(Entity, &TriangleTile)>,
+    changed_triangles: Query<(Entity, &TriangleTile, Option<&TileColor>), Changed<TriangleTile>>,
+    all_triangles: Query<(Entity, &TriangleTile, Option<&TileColor>)>,
     parents: Query<&Parent>,
-    children: Query<&Children>,
-    mut hint: Query<&mut Visibility, With<ReloadHint>>,
+    children: Query<&Children, With<ClumpRoot>>,
+    transforms: Query<(&Transform, Option<&GlobalTransform>)>,
+    mut hints: Query<(&TutorialHint, &mut Visibility)>,
+    contact_rule: Res<ContactRule>,
+    mut queue: ResMut<MergeQueue>,
+    budget: Res<MergeBudget>,
+    enabled: Res<MergeEnabled>,
+    level: Res<LevelInfo>,
+    mut dirty: EventWriter<BoardDirty>,
+    mut merged: EventWriter<MergeEvent>,
+    mut shake: EventWriter<CameraShake>,
 ) {
-    let all_changed: HashSet<Entity> = changed_triangles.iter().map(|(id, _)| id).collect();
-    if all_changed.is_empty() {
+    if !enabled.0 {
         return;
     }
+    let all_changed: HashSet<Entity> = changed_triangles.iter().map(|(id, _, _)| id).collect();
 
-    // Also includes some of the changed triangles
-    let all_neighbors: HashMap<TileCoord, Entity> = changed_triangles
-        .iter()
-        .flat_map(|(id, p)| p.position.iter_neighbors().zip(std::iter::repeat(id)))
-        .collect();
+    // Also includes some of the changed triangles. Several moved triangles, possibly of
+    // different clumps, can touch the same tile.
+    let mut all_neighbors: HashMap<TileCoord, Vec<(Entity, TileColor)>> = HashMap::new();
+    for (id, p, color) in changed_triangles.iter() {
+        let color = color.copied().unwrap_or_default();
+        for contact in contact_rule.contacts(p.position) {
+            all_neighbors.entry(contact).or_default().push((id, color));
+        }
+    }
 
     // Set of all clump pairs that have to be merged. First entry is the just changed one.
+    // Pairs sharing a clump are merged into a single survivor by the queue.
     let mut merges: HashSet<(Entity, Entity)> = HashSet::new();
 
-    for (other, tile) in all_triangles.iter() {
+    for (other, tile, other_color) in all_triangles.iter() {
         if all_changed.contains(&other) {
             // don't consider any changed triangles
             continue;
         }
-        if let Some(&tri) = all_neighbors.get(&tile.position) {
+        let other_color = other_color.copied().unwrap_or_default();
+        for &(tri, color) in all_neighbors.get(&tile.position).into_iter().flatten() {
+            if color != other_color {
+                // Differently colored triangles rest against each other without sticking
+                continue;
+            }
             // tri and other are neighbors now, because tri moved here
             let p1 = parents.get(tri).map(Parent::get);
             let p2 = parents.get(other).map(Parent::get);
             if let (Ok(p1), Ok(p2)) = (p1, p2) {
-                merges.insert((p1, p2));
+                if children.contains(p1) && children.contains(p2) {
+                    merges.insert((p1, p2));
+                }
             }
         }
     }
 
-    // Show hint when first merge occurs
+    // Show hints when first merge occurs
     if !merges.is_empty() {
-        if let Ok(mut vis) = hint.get_single_mut() {
-            if !vis.is_visible {
-                vis.is_visible = true;
-            }
-        }
+        trigger_hints(&mut hints, HintTrigger::OnFirstMerge);
+        queue.push(merges.into_iter().collect(), level.move_count);
     }
 
+    // Triangles that were moved to a clump in this frame, since the commands aren't applied yet
+    let mut moved: HashMap<Entity, Vec<Entity>> = HashMap::new();
+    // Local transforms of those triangles relative to their new clump
+    let mut moved_locals: HashMap<Entity, Transform> = HashMap::new();
+    let global = |id: Entity| match transforms.get(id) {
+        Ok((_, Some(global))) => *global,
+        _ => GlobalTransform::identity(),
+    };
+
     // Apply merges
-    for (p1, p2) in merges {
-        if let Ok(new_tiles) = children.get(p2) {
-            // fixme: This breaks if two moved clumps try to claim the same tile
-            commands
-                .entity(p1)
-                .push_children(new_tiles.iter().as_slice());
-            commands.entity(p2).despawn();
+    for (p1, p2, rotation) in queue.pop_batch(budget.0) {
+        if children.get(p1).is_err() && !moved.contains_key(&p1) {
+            // The surviving clump doesn't exist anymore, e.g. after a reload
+            continue;
         }
+        let mut new_tiles: Vec<Entity> = match children.get(p2) {
+            Ok(c) => c.to_vec(),
+            Err(_) => continue,
+        };
+        new_tiles.extend(moved.remove(&p2).unwrap_or_default());
+
+        // Keep the world placement of the moved triangles
+        let (old_parent, new_parent) = (global(p2), global(p1));
+        for &tile in new_tiles.iter() {
+            let local = match moved_locals.get(&tile) {
+                Some(local) => *local,
+                None => match transforms.get(tile) {
+                    Ok((local, _)) => *local,
+                    Err(_) => continue,
+                },
+            };
+            let local = reparented_transform(&local, &old_parent, &new_parent);
+            moved_locals.insert(tile, local);
+            commands.entity(tile).insert(local);
+        }
+
+        let survivor_count =
+            children.get(p1).map_or(0, |c| c.len()) + moved.get(&p1).map_or(0, Vec::len);
+        commands.entity(p1).push_children(&new_tiles);
+        commands.entity(p2).despawn();
+        merged.send(MergeEvent {
+            survivor: p1,
+            absorbed: p2,
+            triangles: new_tiles.clone(),
+            tile_count: survivor_count + new_tiles.len(),
+            rotation,
+        });
+        moved.entry(p1).or_default().extend(new_tiles);
+    }
+    if !moved.is_empty() {
+        dirty.send(BoardDirty);
+        shake.send(CameraShake {
+            intensity: 5.,
+            duration: 0.25,
+        });
+    }
+}
+
+/// The local transform of a child with the local transform `local` that keeps its world
+/// placement when it is moved from `old_parent` to `new_parent`.
+pub fn reparented_transform(
+    local: &Transform,
+    old_parent: &GlobalTransform,
+    new_parent: &GlobalTransform,
+) -> Transform {
+    if old_parent == new_parent {
+        return *local;
+    }
+    let world = old_parent.compute_matrix() * local.compute_matrix();
+    Transform::from_matrix(new_parent.compute_matrix().inverse() * world)
+}
+
+#[test]
+fn test_contact_rule() {
+    use crate::tilemap::TriangleOrient;
+
+    let tile = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    // Only shares the vertex (1, 0)
+    let vertex_touching = (VertexCoord::X, TriangleOrient::PointingUp);
+    let edge_touching = (VertexCoord::ZERO, TriangleOrient::PointingDown);
+
+    let edge = ContactRule::SharedEdge.contacts(tile);
+    assert!(!edge.contains(&vertex_touching));
+    assert!(edge.contains(&edge_touching));
+
+    let vertex = ContactRule::SharedVertex.contacts(tile);
+    assert_eq!(vertex.len(), 9);
+    assert!(vertex.contains(&vertex_touching));
+    assert!(!vertex.contains(&edge_touching));
+
+    assert_eq!(ContactRule::Both.contacts(tile).len(), 12);
+}
+
+#[test]
+fn test_merge_queue() {
+    let (a, b, c) = (
+        Entity::from_raw(0),
+        Entity::from_raw(1),
+        Entity::from_raw(2),
+    );
+    let mut queue = MergeQueue::default();
+    queue.push(vec![(c, a), (b, c), (a, b)], 1);
+
+    assert_eq!(queue.pop_batch(Some(1)), vec![(a, b, 1)]);
+    // b was absorbed by a
+    assert_eq!(queue.pop_batch(Some(1)), vec![(a, c, 1)]);
+    // c was absorbed by a as well
+    assert_eq!(queue.pop_batch(Some(1)), vec![]);
+
+    // Merges of an undone rotation are dropped
+    queue.push(vec![(a, b)], 2);
+    queue.push(vec![(b, c)], 3);
+    queue.forget(2);
+    assert_eq!(queue.pop_batch(None), vec![(b, c, 3)]);
+}
+
+#[cfg(test)]
+fn merge_test_world(contact_rule: ContactRule) -> World {
+    let mut world = World::new();
+    world.insert_resource(contact_rule);
+    world.insert_resource(MergeQueue::default());
+    world.insert_resource(MergeBudget::default());
+    world.insert_resource(MergeEnabled::default());
+    world.insert_resource(LevelInfo::default());
+    world.insert_resource(Events::<BoardDirty>::default());
+    world.insert_resource(Events::<MergeEvent>::default());
+    world.insert_resource(Events::<CameraShake>::default());
+    world
+}
+
+/// Spawns a clump with a single triangle and returns the triangle.
+#[cfg(test)]
+fn spawn_test_clump(world: &mut World, position: TileCoord, color: u8) -> Entity {
+    let triangle = world
+        .spawn()
+        .insert(TriangleTile { position })
+        .insert(TileColor(color))
+        .insert(Transform::default())
+        .id();
+    world.spawn().insert(ClumpRoot).push_children(&[triangle]);
+    triangle
+}
+
+#[test]
+fn test_merge_into_shared_clump() {
+    use crate::tilemap::TriangleOrient;
+
+    let mut world = merge_test_world(ContactRule::default());
+    let center = spawn_test_clump(
+        &mut world,
+        (VertexCoord::ZERO, TriangleOrient::PointingUp),
+        0,
+    );
+    let right = spawn_test_clump(
+        &mut world,
+        (VertexCoord::new(5, 0), TriangleOrient::PointingDown),
+        0,
+    );
+    let left = spawn_test_clump(
+        &mut world,
+        (VertexCoord::new(-5, 0), TriangleOrient::PointingDown),
+        0,
+    );
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(merge_system);
+    stage.run(&mut world);
+
+    // Both move next to the center triangle in the same frame
+    world.get_mut::<TriangleTile>(right).unwrap().position =
+        (VertexCoord::ZERO, TriangleOrient::PointingDown);
+    world.get_mut::<TriangleTile>(left).unwrap().position =
+        (VertexCoord::new(-1, 1), TriangleOrient::PointingDown);
+    stage.run(&mut world);
+
+    let parent = |world: &World, id: Entity| world.get::<Parent>(id).unwrap().get();
+    let survivor = parent(&world, center);
+    assert_eq!(parent(&world, right), survivor);
+    assert_eq!(parent(&world, left), survivor);
+    assert_eq!(world.get::<Children>(survivor).unwrap().len(), 3);
+    let mut clumps = world.query_filtered::<(), With<ClumpRoot>>();
+    assert_eq!(clumps.iter(&world).count(), 1);
+
+    let events = world.resource::<Events<MergeEvent>>();
+    let mut reader = events.get_reader();
+    let counts: Vec<usize> = reader.iter(events).map(|e| e.tile_count).collect();
+    assert_eq!(counts, vec![2, 3]);
+}
+
+#[test]
+fn test_merge_only_same_color() {
+    use crate::tilemap::TriangleOrient;
+
+    let mut world = merge_test_world(ContactRule::default());
+    let center = spawn_test_clump(
+        &mut world,
+        (VertexCoord::ZERO, TriangleOrient::PointingUp),
+        1,
+    );
+    let plain = spawn_test_clump(
+        &mut world,
+        (VertexCoord::new(5, 0), TriangleOrient::PointingDown),
+        0,
+    );
+    let colored = spawn_test_clump(
+        &mut world,
+        (VertexCoord::new(-5, 0), TriangleOrient::PointingDown),
+        1,
+    );
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(merge_system);
+    stage.run(&mut world);
+
+    world.get_mut::<TriangleTile>(plain).unwrap().position =
+        (VertexCoord::ZERO, TriangleOrient::PointingDown);
+    world.get_mut::<TriangleTile>(colored).unwrap().position =
+        (VertexCoord::new(-1, 1), TriangleOrient::PointingDown);
+    stage.run(&mut world);
+
+    let parent = |world: &World, id: Entity| world.get::<Parent>(id).unwrap().get();
+    assert_eq!(parent(&world, colored), parent(&world, center));
+    assert_ne!(parent(&world, plain), parent(&world, center));
+    let mut clumps = world.query_filtered::<(), With<ClumpRoot>>();
+    assert_eq!(clumps.iter(&world).count(), 2);
+}
+
+#[test]
+fn test_merge_contact_rule() {
+    use crate::tilemap::TriangleOrient;
+
+    let merged = |contact_rule: ContactRule| {
+        let mut world = merge_test_world(contact_rule);
+        let center = spawn_test_clump(
+            &mut world,
+            (VertexCoord::ZERO, TriangleOrient::PointingUp),
+            0,
+        );
+        let mover = spawn_test_clump(
+            &mut world,
+            (VertexCoord::new(5, 0), TriangleOrient::PointingUp),
+            0,
+        );
+
+        let mut stage = SystemStage::single_threaded();
+        stage.add_system(merge_system);
+        stage.run(&mut world);
+
+        // Only shares the vertex (1, 0) with the center triangle
+        world.get_mut::<TriangleTile>(mover).unwrap().position =
+            (VertexCoord::X, TriangleOrient::PointingUp);
+        stage.run(&mut world);
+
+        let parent = |id: Entity| world.get::<Parent>(id).unwrap().get();
+        parent(center) == parent(mover)
+    };
+
+    assert!(!merged(ContactRule::SharedEdge));
+    assert!(merged(ContactRule::SharedVertex));
+    assert!(merged(ContactRule::Both));
+}
+
+#[test]
+fn test_reparented_transform() {
+    use crate::tilemap::TriangleOrient;
+
+    let old_parent = GlobalTransform::identity();
+    let new_parent = GlobalTransform::from(Transform {
+        translation: Vec3::new(100., 50., 0.),
+        scale: Vec3::splat(2.),
+        ..Default::default()
+    });
+    let tile = TriangleTile {
+        position: (VertexCoord::new(1, -2), TriangleOrient::PointingDown),
+    };
+    let local = tile.to_world_pos();
+
+    let reparented = reparented_transform(&local, &old_parent, &new_parent);
+    let world = new_parent.mul_transform(reparented);
+    assert!(world.translation().distance(local.translation) < 1e-3);
+    // The corners of the flipped triangle stay in place as well
+    let corner = Vec3::new(TRIANGLE_SIDE, 0., 0.);
+    assert!(world.mul_vec3(corner).distance(local.mul_vec3(corner)) < 1e-3);
+
+    // Identical parents don't touch the transform at all
+    assert_eq!(
+        reparented_transform(&local, &new_parent, &new_parent),
+        local
+    );
+}
+
+/// Creates a world with a single triangle clump and the right mouse button pressed.
+/// Returns the world, the triangle and its clump.
+#[cfg(test)]
+fn rotation_test_world() -> (World, Entity, Entity) {
+    use crate::tilemap::TriangleOrient;
+
+    let mut world = World::new();
+    let mut mouse_btn = Input::<MouseButton>::default();
+    mouse_btn.press(MouseButton::Right);
+    world.insert_resource(mouse_btn);
+    world.insert_resource(MouseActions::default());
+    world.insert_resource(InputSuppressed::default());
+    world.insert_resource(Input::<KeyCode>::default());
+    world.insert_resource(Time::default());
+    world.insert_resource(Events::<ForceSelection>::default());
+    world.insert_resource(Events::<CursorMoved>::default());
+    world.insert_resource(Events::<TileMoved>::default());
+    world.insert_resource(Events::<MoveBlocked>::default());
+    world.insert_resource(Events::<CameraShake>::default());
+    world.insert_resource(LevelInfo::default());
+    world.insert_resource(GridConfig::default());
+    world.insert_resource(EditorEnabled::default());
+
+    world
+        .spawn()
+        .insert(SelectionIndicator::new())
+        .insert(SelectedTrianglesState::default());
+    let tile = TriangleTile {
+        position: (VertexCoord::ZERO, TriangleOrient::PointingUp),
+    };
+    let triangle = world.spawn().insert(tile.to_world_pos()).insert(tile).id();
+    let clump = world
+        .spawn()
+        .insert(ClumpRoot)
+        .push_children(&[triangle])
+        .id();
+
+    (world, triangle, clump)
+}
+
+/// Runs the selection and rotation systems once.
+#[cfg(test)]
+fn run_rotation(world: &mut World) {
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(triangle_selection_system.before(rotation_system))
+        .add_system(rotation_system);
+    stage.run(world);
+}
+
+#[test]
+fn test_forced_selection() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+    run_rotation(&mut world);
+
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+}
+
+#[test]
+fn test_alt_click_outside_editor() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world.resource_mut::<Input<KeyCode>>().press(KeyCode::LAlt);
+    let mut rotate = |world: &mut World| {
+        world
+            .resource_mut::<Events<ForceSelection>>()
+            .send(ForceSelection {
+                clump,
+                anchor: None,
+            });
+        run_rotation(world);
+        world.get::<TriangleTile>(triangle).unwrap().position
+    };
+
+    // Alt + click only copies regions in the editor
+    world.insert_resource(EditorEnabled(true));
+    assert_eq!(
+        rotate(&mut world),
+        (VertexCoord::ZERO, TriangleOrient::PointingUp)
+    );
+    world.insert_resource(EditorEnabled(false));
+    assert_eq!(
+        rotate(&mut world),
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+}
+
+#[test]
+fn test_tile_moved() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+    run_rotation(&mut world);
+
+    assert_eq!(world.resource::<LevelInfo>().move_count, 1);
+    let events = world.resource::<Events<TileMoved>>();
+    let mut reader = events.get_reader();
+    let moved: Vec<&TileMoved> = reader.iter(events).collect();
+    assert_eq!(moved.len(), 1);
+    assert_eq!(moved[0].entity, triangle);
+    assert_eq!(
+        moved[0].from,
+        (VertexCoord::ZERO, TriangleOrient::PointingUp)
+    );
+    assert_eq!(
+        moved[0].to,
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+}
+
+#[test]
+fn test_playfield_bounds() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    // The triangle fits, but not after rotating it clockwise
+    let bounds = PlayfieldBounds::from_tiles(&[(VertexCoord::ZERO, TriangleOrient::PointingUp)]);
+    assert_eq!(bounds.max, VertexCoord::ONE);
+    world.insert_resource(bounds);
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+    run_rotation(&mut world);
+
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingUp)
+    );
+    let events = world.resource::<Events<MoveBlocked>>();
+    assert_eq!(events.get_reader().iter(events).count(), 1);
+}
+
+#[test]
+fn test_keyboard_rotation() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world.insert_resource(Input::<MouseButton>::default());
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+
+    // Control + E is left to the save slots
+    let mut keys = Input::<KeyCode>::default();
+    keys.press(KeyCode::LControl);
+    keys.press(ROTATE_CLOCKWISE_KEY);
+    world.insert_resource(keys);
+    run_rotation(&mut world);
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingUp)
+    );
+
+    let mut keys = Input::<KeyCode>::default();
+    keys.press(ROTATE_CLOCKWISE_KEY);
+    world.insert_resource(keys);
+    run_rotation(&mut world);
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+}
+
+#[test]
+fn test_mouse_actions() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world.resource_mut::<MouseActions>().rotate_clockwise = MouseButton::Middle;
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+
+    // Right click does nothing anymore
+    run_rotation(&mut world);
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingUp)
+    );
+
+    let mut mouse_btn = Input::<MouseButton>::default();
+    mouse_btn.press(MouseButton::Middle);
+    world.insert_resource(mouse_btn);
+    run_rotation(&mut world);
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+}
+
+#[test]
+fn test_cycle_selection() {
+    let (mut world, _, _) = rotation_test_world();
+    world.insert_resource(Input::<MouseButton>::default());
+    // Clumps without triangles have no corners
+    world.spawn().insert(ClumpRoot);
+    let mut keys = Input::<KeyCode>::default();
+    keys.press(KeyCode::Tab);
+    world.insert_resource(keys);
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(cycle_selection_system.before(triangle_selection_system))
+        .add_system(triangle_selection_system);
+    let mut anchors = Vec::new();
+    for _ in 0..4 {
+        stage.run(&mut world);
+        let mut indicator = world.query::<&SelectedTrianglesState>();
+        anchors.push(indicator.single(&world).anchor);
+    }
+    let corners = [VertexCoord::ZERO, VertexCoord::Y, VertexCoord::X];
+    assert_eq!(anchors, [corners.as_slice(), &corners[..1]].concat());
+
+    world
+        .resource_mut::<Input<KeyCode>>()
+        .press(KeyCode::LShift);
+    stage.run(&mut world);
+    let mut indicator = world.query::<&SelectedTrianglesState>();
+    assert_eq!(indicator.single(&world).anchor, VertexCoord::X);
+}
+
+#[test]
+fn test_forced_selection_kept() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: Some(VertexCoord::X),
+        });
+    // Every run rotates clockwise around the forced anchor
+    run_rotation(&mut world);
+    run_rotation(&mut world);
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::X, TriangleOrient::PointingUp)
+    );
+
+    // Moving the cursor gives the selection back to it, which selects nothing here
+    world
+        .resource_mut::<Events<CursorMoved>>()
+        .send(CursorMoved {
+            id: bevy::window::WindowId::primary(),
+            position: Vec2::ZERO,
+        });
+    run_rotation(&mut world);
+    let mut indicator = world.query::<&SelectedTrianglesState>();
+    let selection = indicator.single(&world);
+    assert!(!selection.forced);
+    assert!(selection.selected_set.is_empty());
+}
+
+#[test]
+fn test_selected_clumps() {
+    use bevy::ecs::system::SystemState;
+
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    let sibling = world
+        .spawn()
+        .insert(TriangleTile {
+            position: (VertexCoord::ZERO, TriangleOrient::PointingDown),
+        })
+        .id();
+    world.entity_mut(clump).push_children(&[sibling]);
+
+    let selection = SelectedTrianglesState {
+        selected_set: [triangle, sibling].into_iter().collect(),
+        anchor: VertexCoord::ZERO,
+        ..Default::default()
+    };
+    let mut state: SystemState<Query<&Parent>> = SystemState::new(&mut world);
+    let parents = state.get(&world);
+    assert_eq!(
+        selection.selected_clumps(&parents),
+        [clump].into_iter().collect()
+    );
+}
+
+#[test]
+fn test_multi_clump_rotation() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, _) = rotation_test_world();
+    // Lies where the first triangle rotates to
+    let tile = TriangleTile {
+        position: (VertexCoord::ZERO, TriangleOrient::PointingDown),
+    };
+    let other = world.spawn().insert(tile.to_world_pos()).insert(tile).id();
+    world.spawn().insert(ClumpRoot).push_children(&[other]);
+
+    let mut indicator = world.query::<&mut SelectedTrianglesState>();
+    let mut selection = indicator.single_mut(&mut world);
+    selection.selected_set = [triangle, other].into_iter().collect();
+    selection.anchor = VertexCoord::ZERO;
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(rotation_system);
+    stage.run(&mut world);
+
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+    assert_eq!(
+        world.get::<TriangleTile>(other).unwrap().position,
+        (VertexCoord::new(0, -1), TriangleOrient::PointingUp)
+    );
+}
+
+#[test]
+fn test_rotation_ghosts() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, _) = rotation_test_world();
+    world.insert_resource(AssetHandles::default());
+    // Lies where the triangle rotates to clockwise
+    let tile = TriangleTile {
+        position: (VertexCoord::ZERO, TriangleOrient::PointingDown),
+    };
+    world.spawn().insert(tile.to_world_pos()).insert(tile);
+
+    let mut indicator = world.query::<&mut SelectedTrianglesState>();
+    let mut selection = indicator.single_mut(&mut world);
+    selection.selected_set = [triangle].into_iter().collect();
+    selection.anchor = VertexCoord::ZERO;
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(rotation_ghost_system);
+    stage.run(&mut world);
+
+    let mut ghosts = world.query::<&RotationGhost>();
+    let mut states: Vec<(RotationDir, bool)> = ghosts
+        .iter(&world)
+        .map(|ghost| (ghost.dir, ghost.blocked))
+        .collect();
+    states.sort_by_key(|(dir, _)| *dir == RotationDir::CounterClockwise);
+    assert_eq!(
+        states,
+        vec![
+            (RotationDir::Clockwise, true),
+            (RotationDir::CounterClockwise, false)
+        ]
+    );
+
+    indicator.single_mut(&mut world).selected_set.clear();
+    stage.run(&mut world);
+    assert_eq!(ghosts.iter(&world).count(), 0);
+}
+
+#[test]
+fn test_rotation_animation() {
+    use crate::tilemap::TriangleOrient;
+    use std::time::{Duration, Instant};
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+    run_rotation(&mut world);
+
+    // The tile is updated right away, but the triangle didn't move yet
+    let from = TriangleTile {
+        position: (VertexCoord::ZERO, TriangleOrient::PointingUp),
     }
+    .to_world_pos();
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+    assert_eq!(*world.get::<Transform>(triangle).unwrap(), from);
+    let animation = world.get::<RotationAnimation>(triangle).unwrap();
+    assert_eq!(animation.target_angle, RotationDir::Clockwise.angle());
+
+    let start = Instant::now();
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(rotation_animation_system);
+    let mut run_at = |world: &mut World, secs: f32| {
+        world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs_f32(secs));
+        stage.run(world);
+    };
+    run_at(&mut world, 0.);
+    run_at(&mut world, ROTATION_DURATION / 2.);
+    // Turned around the anchor, which is the left vertex of the triangle
+    let transf = *world.get::<Transform>(triangle).unwrap();
+    assert!(transf.translation.distance(from.translation) < 1e-3);
+    let (_, angle) = transf.rotation.to_axis_angle();
+    assert!((angle - std::f32::consts::FRAC_PI_6).abs() < 1e-3);
+
+    run_at(&mut world, 1.);
+    let tile = world.get::<TriangleTile>(triangle).unwrap().clone();
+    assert_eq!(
+        *world.get::<Transform>(triangle).unwrap(),
+        tile.to_world_pos()
+    );
+    assert!(world.get::<RotationAnimation>(triangle).is_none());
+}
+
+#[test]
+fn test_blocked_feedback() {
+    use crate::tilemap::TriangleOrient;
+    use std::time::{Duration, Instant};
+
+    let (mut world, triangle, _) = rotation_test_world();
+    // Lies where the triangle rotates to
+    let tile = TriangleTile {
+        position: (VertexCoord::ZERO, TriangleOrient::PointingDown),
+    };
+    world.spawn().insert(tile.to_world_pos()).insert(tile);
+    let rest = Vec3::new(5., 5., 900.);
+    let mut indicator = world.query::<(Entity, &mut SelectedTrianglesState)>();
+    let (indicator, mut selection) = indicator.single_mut(&mut world);
+    selection.selected_set = [triangle].into_iter().collect();
+    selection.anchor = VertexCoord::ZERO;
+    world
+        .entity_mut(indicator)
+        .insert(Transform::from_translation(rest));
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(rotation_system);
+    stage.run(&mut world);
+    assert!(world.get::<BlockedFeedback>(indicator).is_some());
+
+    let start = Instant::now();
+    let mut jiggle = SystemStage::single_threaded();
+    jiggle
+        .add_system(remove_jiggle_offset.before(jiggle_system))
+        .add_system(jiggle_system);
+    let mut run_at = |world: &mut World, secs: f32| {
+        world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs_f32(secs));
+        jiggle.run(world);
+    };
+    run_at(&mut world, 0.);
+    run_at(&mut world, 0.03);
+    let offset = world.get::<Transform>(indicator).unwrap().translation - rest;
+    assert!(offset.x.abs() > 1. && offset.x.abs() <= JIGGLE_AMPLITUDE);
+    assert_eq!(offset.truncate().y, 0.);
+
+    run_at(&mut world, 1.);
+    let transf = world.get::<Transform>(indicator).unwrap();
+    assert!(transf.translation.distance(rest) < 1e-4);
+    assert!(world.get::<BlockedFeedback>(indicator).is_none());
+}
+
+#[test]
+fn test_click_on_ui() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    world.insert_resource(bevy_point_selection::PointerBlocked::default());
+    world.spawn().insert(Interaction::Clicked);
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(crate::detect_ui_focus)
+        .add_system(triangle_selection_system.after(crate::detect_ui_focus))
+        .add_system(rotation_system.after(triangle_selection_system));
+    stage.run(&mut world);
+
+    assert!(world.resource::<InputSuppressed>().ui);
+    assert!(world.resource::<bevy_point_selection::PointerBlocked>().0);
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingUp)
+    );
+}
+
+#[test]
+fn test_missing_indicator() {
+    let (mut world, triangle, _) = rotation_test_world();
+    let mut indicator = world.query_filtered::<Entity, With<SelectionIndicator>>();
+    let indicator = indicator.single(&world);
+    world.despawn(indicator);
+
+    run_rotation(&mut world);
+    assert!(world.get::<TriangleTile>(triangle).is_some());
+}
+
+#[test]
+fn test_sweep_pinning() {
+    use crate::tilemap::TriangleOrient;
+
+    let (mut world, triangle, clump) = rotation_test_world();
+    let tile = TriangleTile {
+        position: (VertexCoord::new(-1, 0), TriangleOrient::PointingUp),
+    };
+    let other = world
+        .spawn()
+        .insert(tile.to_world_pos())
+        .insert(tile.clone())
+        .id();
+    let other_clump = world.spawn().insert(ClumpRoot).push_children(&[other]).id();
+    // Both triangles have a handle on the vertex they share
+    let shared = GlobalTransform::from_translation(VertexCoord::ZERO.to_world_pos().translation);
+    let handles = [triangle, other].map(|triangle| {
+        let handle = world
+            .spawn()
+            .insert(Selectable::new(1.))
+            .insert(shared)
+            .id();
+        world.entity_mut(triangle).push_children(&[handle]);
+        handle
+    });
+    world
+        .resource_mut::<Input<KeyCode>>()
+        .press(KeyCode::LShift);
+
+    world.insert_resource(EscapeClaimed::default());
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system(triangle_selection_system)
+        .add_system(claim_escape_system.after(triangle_selection_system));
+    let mut indicator = world.query::<(&mut SelectionIndicator, &SelectedTrianglesState)>();
+    for handle in handles {
+        indicator.single_mut(&mut world).0.selected_triggers = [handle].into_iter().collect();
+        stage.run(&mut world);
+    }
+    let (_, selection) = indicator.single(&world);
+    assert_eq!(selection.pinned, [clump, other_clump].into_iter().collect());
+    assert_eq!(
+        selection.selected_set,
+        [triangle, other].into_iter().collect()
+    );
+
+    // Both clumps turn around the shared vertex once Shift is released
+    world
+        .resource_mut::<Input<KeyCode>>()
+        .release(KeyCode::LShift);
+    run_rotation(&mut world);
+    assert_eq!(
+        world.get::<TriangleTile>(triangle).unwrap().position,
+        (VertexCoord::ZERO, TriangleOrient::PointingDown)
+    );
+    assert_ne!(
+        world.get::<TriangleTile>(other).unwrap().position,
+        tile.position
+    );
+
+    // Clicking the corner the sweep just pinned keeps it pinned
+    world
+        .resource_mut::<Input<KeyCode>>()
+        .press(KeyCode::LShift);
+    indicator.single_mut(&mut world).0.selected_triggers = [handles[0]].into_iter().collect();
+    stage.run(&mut world);
+    let mut mouse_btn = Input::<MouseButton>::default();
+    mouse_btn.press(MouseButton::Left);
+    world.insert_resource(mouse_btn);
+    stage.run(&mut world);
+    let pinned = [clump, other_clump].into_iter().collect();
+    assert_eq!(indicator.single(&world).1.pinned, pinned);
+    assert!(world.resource::<EscapeClaimed>().0);
+
+    // Escape releases all pins instead of opening the menu
+    world.insert_resource(Input::<MouseButton>::default());
+    world
+        .resource_mut::<Input<KeyCode>>()
+        .press(KeyCode::Escape);
+    stage.run(&mut world);
+    let (_, selection) = indicator.single(&world);
+    assert!(selection.pinned.is_empty());
+    assert_eq!(selection.selected_set, [triangle].into_iter().collect());
+    // The next press opens the menu again
+    assert!(!world.resource::<EscapeClaimed>().0);
+}
+
+#[test]
+fn test_pivot_marker() {
+    let (mut world, triangle, _) = rotation_test_world();
+    let marker = world
+        .spawn()
+        .insert(PivotMarker)
+        .insert(Transform::default())
+        .insert(Visibility { is_visible: false })
+        .id();
+    let anchor = VertexCoord::new(1, 0);
+    let mut indicator = world.query::<&mut SelectedTrianglesState>();
+    let mut selection = indicator.single_mut(&mut world);
+    selection.selected_set = [triangle].into_iter().collect();
+    selection.anchor = anchor;
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(pivot_marker_system);
+    stage.run(&mut world);
+    assert!(world.get::<Visibility>(marker).unwrap().is_visible);
+    assert_eq!(
+        world
+            .get::<Transform>(marker)
+            .unwrap()
+            .translation
+            .truncate(),
+        anchor.to_world_pos().translation.truncate()
+    );
+
+    indicator.single_mut(&mut world).selected_set.clear();
+    stage.run(&mut world);
+    assert!(!world.get::<Visibility>(marker).unwrap().is_visible);
+}
+
+#[test]
+fn test_hover_dot() {
+    use std::time::{Duration, Instant};
+
+    let mut world = World::new();
+    let start = Instant::now();
+    let mut time = Time::default();
+    time.update_with_instant(start);
+    world.insert_resource(time);
+    world.insert_resource(Settings::default());
+    let position = Vec3::new(30., 40., 0.);
+    // The dot sits on the handle's offset, like on the corners of a triangle
+    let mut selectable = Selectable::new(10.).with_offset(Vec2::new(5., 0.));
+    selectable.is_selected = true;
+    let handle = world
+        .spawn()
+        .insert(selectable)
+        .insert(GlobalTransform::from_translation(position))
+        .id();
+
+    let mut stage = SystemStage::single_threaded();
+    stage.add_system(hover_feedback_system);
+    let mut run_at = |world: &mut World, secs: f32| {
+        world
+            .resource_mut::<Time>()
+            .update_with_instant(start + Duration::from_secs_f32(secs));
+        stage.run(world);
+    };
+    run_at(&mut world, 0.);
+    let mut dots = world.query::<(&HoverDot, &Transform)>();
+    let (dot, transf) = dots.single(&world);
+    assert_eq!(dot.handle, handle);
+    assert_eq!(
+        transf.translation.truncate(),
+        position.truncate() + Vec2::new(5., 0.)
+    );
+    assert_eq!(transf.scale, Vec3::ZERO);
+
+    // Staying on the vertex keeps the dot, which grows in
+    run_at(&mut world, HOVER_DOT_GROW_DURATION / 2.);
+    let (_, transf) = dots.single(&world);
+    assert!((transf.scale.x - 0.5).abs() < 1e-3);
+    run_at(&mut world, HOVER_DOT_GROW_DURATION * 2.);
+    let (_, transf) = dots.single(&world);
+    assert_eq!(transf.scale, Vec3::ONE);
+
+    world.get_mut::<Selectable>(handle).unwrap().is_selected = false;
+    run_at(&mut world, 1.);
+    assert_eq!(dots.iter(&world).count(), 0);
+
+    // With reduced motion the dot shows up at full size
+    world.resource_mut::<Settings>().reduced_motion = true;
+    world.get_mut::<Selectable>(handle).unwrap().is_selected = true;
+    run_at(&mut world, 1.1);
+    let (_, transf) = dots.single(&world);
+    assert_eq!(transf.scale, Vec3::ONE);
+}
+
+#[test]
+fn test_rotation_hints() {
+    use crate::level::{HintPosition, HintSprite};
+
+    let (mut world, _, clump) = rotation_test_world();
+    let mut spawn_hint = |trigger| {
+        world
+            .spawn()
+            .insert(TutorialHint {
+                sprite: HintSprite::Rotate,
+                position: HintPosition::Vertex(VertexCoord::ZERO),
+                trigger,
+            })
+            .insert(RotationHint)
+            .insert(Visibility {
+                is_visible: trigger == HintTrigger::OnLoad,
+            })
+            .id()
+    };
+    let shown = spawn_hint(HintTrigger::OnLoad);
+    let revealed = spawn_hint(HintTrigger::OnFirstRotate);
+    world
+        .resource_mut::<Events<ForceSelection>>()
+        .send(ForceSelection {
+            clump,
+            anchor: None,
+        });
+    run_rotation(&mut world);
+
+    assert!(world.get::<SoftDespawned>(shown).is_some());
+    // The second hint only appears now, so it stays
+    assert!(world.get::<SoftDespawned>(revealed).is_none());
+    assert!(world.get::<Visibility>(revealed).unwrap().is_visible);
 }
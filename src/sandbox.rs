@@ -0,0 +1,18 @@
+//! The practice sandbox, a reserved built-in level to try out rotations without consequences.
+//! Press `P` to enter it and `R` to go back to the current level.
+
+use bevy::prelude::*;
+
+use crate::{history::MoveHistory, level_editor::EditorEnabled, rotation::MergeEnabled};
+
+/// Name of the sandbox level.
+pub const SANDBOX_LEVEL: &str = "sandbox";
+
+/// Switches the modes of the sandbox on or off: merging is disabled and the editor enabled. Called
+/// whenever a level is loaded, so leaving the sandbox turns the editor off again. Also resets the
+/// history, since its entities are gone with the previous level.
+pub fn apply_sandbox(world: &mut World, active: bool) {
+    world.insert_resource(MergeEnabled(!active));
+    world.insert_resource(EditorEnabled(active));
+    world.insert_resource(MoveHistory::default());
+}
@@ -1,32 +1,70 @@
+use std::sync::{Arc, Mutex};
 #[cfg(not(target_arch = "wasm32"))]
-use std::{io::Write, path::PathBuf};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
 
-use bevy::{ecs::system::CommandQueue, prelude::*, utils::HashMap};
+use bevy::{
+    ecs::system::CommandQueue,
+    prelude::*,
+    sprite::MaterialMesh2dBundle,
+    utils::{HashMap, HashSet},
+};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    level::{LevelInfo, ReloadHint, SoftDespawned},
-    level_editor::{spawn_immovable, spawn_rune, spawn_solo_triangle},
-    tilemap::{Immovable, RuneTile, TileCoord, TriangleTile},
-    AssetHandles, GameState, SpriteAssets,
+    analytics::LOG_NAME,
+    camera::{fit_camera_to_level, CameraSweep, LevelIntro},
+    level::{
+        spawn_hint, BoardDirty, LevelId, LevelInfo, LevelLabel, ReloadHint, SoftDespawned,
+        TutorialHint, SPAWN_STAGGER,
+    },
+    level_editor::{
+        spawn_clump, spawn_rune, spawn_solo_triangle, EditorEnabled, EditorPrefs, PREFS_NAME,
+        RUNE_GLYPHS, SELECTABLE_RADIUS,
+    },
+    moves,
+    rotation::ContactRule,
+    sandbox::{apply_sandbox, SANDBOX_LEVEL},
+    settings::SETTINGS_NAME,
+    summary::{Progress, PROGRESS_NAME},
+    tilemap::{
+        ClumpRoot, GridConfig, Immovable, RuneTile, TileColor, TileCoord, TileVertices,
+        TransformInWorld, TriangleTile, VertexCoord, TRIANGLE_Z,
+    },
+    AssetHandles, GameState, SpriteAssets, LEVEL_LABEL_COLOR,
 };
 
-const LEVELS: &[&'static str] = &[
-    include_str!("../levels/0-empty.json"),
-    include_str!("../levels/1-tutorial.json"),
-    include_str!("../levels/2-walls-berni-3.json"),
-    include_str!("../levels/3-merge-intro.json"),
-    include_str!("../levels/4-hexagon.json"),
-    include_str!("../levels/5-circle-berni-4.json"),
-    include_str!("../levels/6-nook-berni-5.json"),
-    include_str!("../levels/7-wall-hole.json"),
-    include_str!("../levels/end.json"),
+/// The built-in levels in the order of `levels/manifest.json`, embedded by the build script.
+const LEVELS: &[&str] = include!(concat!(env!("OUT_DIR"), "/levels.rs"));
+
+const SANDBOX: &str = include_str!("../levels/sandbox.json");
+
+/// File names of the built-in levels in `levels/`, so they aren't deleted.
+const LEVEL_FILES: &[&str] = include!(concat!(env!("OUT_DIR"), "/level_files.rs"));
+
+/// Files saved next to the levels which aren't levels.
+const RESERVED_NAMES: [&str; 5] = [
+    "manifest",
+    PREFS_NAME,
+    PROGRESS_NAME,
+    SETTINGS_NAME,
+    LOG_NAME,
 ];
 
+/// Index of the built-in level shown after the last real level.
+pub fn end_level_index() -> usize {
+    LEVELS.len() - 1
+}
+
 /// Save and load levels on the fly.
 /// Press a number `0`-`9` to load a level.
 /// Press `Left Control` + a number `0`-`9` to save as a level.
+/// Press `Left Control` + `C` to copy the level to the clipboard and `Left Control` + `V` to
+/// load a level from the clipboard.
+/// Press `Delete` + a number `0`-`9` to delete a saved level.
 ///
 /// Note: If a level is built-in, then loading a level will always load the built-in level
 /// and not the saved one. Built-in Level 0 is garanteed to be empty, the game starts with
@@ -41,26 +79,197 @@ impl Plugin for MagnateSaveGamePlugin {
         app.add_system_set(
             SystemSet::on_update(GameState::Next)
                 .with_system(save_system.exclusive_system())
-                .with_system(load_system.exclusive_system()),
+                .with_system(load_system.exclusive_system())
+                .with_system(finish_loading.exclusive_system())
+                .with_system(finish_saving)
+                .with_system(clipboard_system.exclusive_system())
+                .with_system(delete_system),
         )
-        .init_resource::<LevelInfo>();
+        .init_resource::<LevelInfo>()
+        .init_resource::<LoadingOptions>()
+        .add_event::<LevelLoaded>();
+    }
+}
+
+/// Name of levels loaded with [`level_from_string`]
+const PASTED_LEVEL: &str = "pasted";
+
+/// Sent by [`spawn_level`] whenever a level was (re)loaded.
+#[derive(Debug, Clone)]
+pub struct LevelLoaded {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveError {
+    /// There is no level with this name
+    NotFound(String),
+    /// The level data isn't a valid [`SaveGame`]
+    Malformed(String),
+    /// The level was saved by a newer version of the game
+    UnsupportedVersion(u32),
+    /// The level has tiles which can't be placed like this, see [`validate_savegame`]
+    Invalid(Vec<LevelError>),
+    /// The level is built-in or not a level at all, so it can't be changed
+    Protected(String),
+    /// There already is a level with this name
+    AlreadyExists(String),
+    /// Reading or writing the level failed
+    Io(String),
+}
+
+/// A problem with the tiles of a level found by [`validate_savegame`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelError {
+    DuplicateTriangle(TileCoord),
+    DuplicateImmovable(TileCoord),
+    DuplicateRune(TileCoord),
+    /// A triangle and an immovable are on the same tile
+    TriangleOnImmovable(TileCoord),
+    /// The rune can never be lit, because an immovable is on top
+    RuneUnderImmovable(TileCoord),
+    /// A rune requires the same cell twice
+    DuplicateRequired(TileCoord),
+    /// The rune can never be lit, because an immovable is on one of its required cells
+    RequiredUnderImmovable(TileCoord),
+}
+
+impl std::fmt::Display for LevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LevelError::DuplicateTriangle(t) => write!(f, "two triangles on {:?}", t),
+            LevelError::DuplicateImmovable(t) => write!(f, "two immovables on {:?}", t),
+            LevelError::DuplicateRune(t) => write!(f, "two runes on {:?}", t),
+            LevelError::TriangleOnImmovable(t) => write!(f, "triangle on immovable {:?}", t),
+            LevelError::RuneUnderImmovable(t) => write!(f, "rune under immovable {:?}", t),
+            LevelError::DuplicateRequired(t) => write!(f, "rune requires {:?} twice", t),
+            LevelError::RequiredUnderImmovable(t) => {
+                write!(f, "rune requires {:?} under immovable", t)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::NotFound(name) => write!(f, "level {} not found", name),
+            SaveError::Malformed(e) => write!(f, "malformed level: {}", e),
+            SaveError::UnsupportedVersion(v) => write!(f, "unsupported level version {}", v),
+            SaveError::Invalid(errors) => {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "invalid level: {}", errors.join(", "))
+            }
+            SaveError::Protected(name) => write!(f, "level {} is protected", name),
+            SaveError::AlreadyExists(name) => write!(f, "level {} already exists", name),
+            SaveError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Resource to configure how levels are loaded.
+#[derive(Debug, Default, Clone)]
+pub struct LoadingOptions {
+    /// Show broken levels as an error instead of keeping the previous level
+    pub strict: bool,
+}
+
+/// Version of the level format written by [`save_level`]. Files without a version are version
+/// `0`, which only lacks the version tag.
+const SAVE_VERSION: u32 = 2;
+
+/// Upgrades level data of the version at its index to the next version.
+const MIGRATIONS: [fn(&mut serde_json::Value); SAVE_VERSION as usize] = [migrate_v0, migrate_v1];
+
+/// All fields added since are optional, so only the version tag is new.
+fn migrate_v0(_save: &mut serde_json::Value) {}
+
+/// Runes used to get a random glyph on every load, now they keep the one they got once.
+fn migrate_v1(save: &mut serde_json::Value) {
+    let runes = save.get_mut("runes").and_then(|runes| runes.as_array_mut());
+    for (i, rune) in runes.into_iter().flatten().enumerate() {
+        if let Some(fields) = rune.as_object_mut() {
+            fields
+                .entry("rune_index")
+                .or_insert_with(|| (i % RUNE_GLYPHS).into());
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct SaveGame {
+/// The level format, see [`SAVE_VERSION`].
+#[derive(Serialize, Deserialize, Default)]
+pub struct SaveGame {
+    #[serde(default)]
+    version: u32,
     triangles: Vec<(TriangleTile, Entity)>,
     immovables: Vec<TileCoord>,
     runes: Vec<RuneTile>,
+    #[serde(default)]
+    contact_rule: ContactRule,
+    #[serde(default)]
+    hints: Vec<TutorialHint>,
+    #[serde(default)]
+    intro: Option<CameraSweep>,
+    /// The number of moves the level can be solved in
+    #[serde(default)]
+    par: Option<u32>,
+    /// The [`TileColor`] of every triangle which isn't the default color
+    #[serde(default)]
+    triangle_colors: Vec<(TileCoord, TileColor)>,
+    /// The [`TileColor`] of every rune which isn't the default color
+    #[serde(default)]
+    rune_colors: Vec<(TileCoord, TileColor)>,
+    /// Placement of the grid if it differs from the default one
+    #[serde(default)]
+    grid: Option<GridConfig>,
 }
 
-pub fn save_level(world: &mut World, as_name: &str) {
-    // Serialize level data
-    let mut tris_query = world.query::<(&TriangleTile, &Parent)>();
-    let triangles = tris_query
-        .iter(world)
-        .map(|(t, p)| (t.clone(), p.get()))
-        .collect::<Vec<(TriangleTile, Entity)>>();
+impl SaveGame {
+    fn all_tiles(&self) -> impl Iterator<Item = TileCoord> + '_ {
+        self.triangles
+            .iter()
+            .map(|(tile, _)| tile.position)
+            .chain(self.immovables.iter().cloned())
+            .chain(self.runes.iter().map(|rune| rune.position))
+    }
+
+    /// The minimum and maximum vertex of all tiles in the level. An empty level has
+    /// both at zero.
+    pub fn bounds(&self) -> (VertexCoord, VertexCoord) {
+        moves::bounds(self.all_tiles())
+    }
+
+    /// The mean world position of the centers of all tiles in the level. An empty level
+    /// has it at the origin.
+    pub fn centroid_world(&self) -> Vec2 {
+        let (sum, count) = self
+            .all_tiles()
+            .flat_map(|tile| tile.world_vertices())
+            .fold((Vec2::ZERO, 0), |(sum, count), v| (sum + v, count + 1));
+        if count == 0 {
+            Vec2::ZERO
+        } else {
+            sum / count as f32
+        }
+    }
+}
+
+/// The level data of the current world.
+pub fn collect_save(world: &mut World) -> SaveGame {
+    let mut clumps_query = world.query_filtered::<(Entity, &Children), With<ClumpRoot>>();
+    let mut tris_query = world.query::<(&TriangleTile, Option<&TileColor>)>();
+    let mut triangles: Vec<(TriangleTile, Entity)> = Vec::new();
+    let mut triangle_colors: Vec<(TileCoord, TileColor)> = Vec::new();
+    for (clump, children) in clumps_query.iter(world) {
+        for &child in children.iter() {
+            if let Ok((tile, color)) = tris_query.get(world, child) {
+                triangles.push((tile.clone(), clump));
+                if let Some(&color) = color.filter(|&&color| color != TileColor::default()) {
+                    triangle_colors.push((tile.position, color));
+                }
+            }
+        }
+    }
 
     let mut immov_query = world.query_filtered::<&TriangleTile, With<Immovable>>();
     let immovables = immov_query
@@ -68,63 +277,292 @@ pub fn save_level(world: &mut World, as_name: &str) {
         .map(|t| t.position)
         .collect::<Vec<TileCoord>>();
 
-    let mut runes_query = world.query::<&RuneTile>();
+    let mut runes_query = world.query::<(&RuneTile, Option<&TileColor>)>();
     let runes = runes_query
         .iter(world)
-        .map(|t| t.clone())
+        .map(|(t, _)| t.clone())
         .collect::<Vec<RuneTile>>();
+    let rune_colors = runes_query
+        .iter(world)
+        .filter_map(|(t, color)| Some((t.position, *color?)))
+        .filter(|(_, color)| *color != TileColor::default())
+        .collect::<Vec<(TileCoord, TileColor)>>();
+
+    let contact_rule = world
+        .get_resource::<ContactRule>()
+        .copied()
+        .unwrap_or_default();
+
+    let mut hints_query = world.query_filtered::<&TutorialHint, Without<SoftDespawned>>();
+    let hints = hints_query
+        .iter(world)
+        .cloned()
+        .collect::<Vec<TutorialHint>>();
 
-    let save = SaveGame {
+    let intro = world
+        .get_resource::<LevelIntro>()
+        .and_then(|intro| intro.0.clone());
+
+    SaveGame {
+        version: SAVE_VERSION,
         triangles,
         runes,
         immovables,
-    };
-
-    let ser = serde_json::to_string(&save);
-
-    match ser {
-        Ok(data) => write_json(data, as_name.to_string().as_str()),
-        Err(e) => warn!("Failed to serialize save file: {:?}", e),
-    };
+        contact_rule,
+        hints,
+        intro,
+        par: world
+            .get_resource::<LevelInfo>()
+            .and_then(|level| level.par),
+        triangle_colors,
+        rune_colors,
+        grid: world
+            .get_resource::<GridConfig>()
+            .copied()
+            .filter(|grid| *grid != GridConfig::default()),
+    }
 }
 
-/// Replaces the world content with the level named `name`. Numerical names are the
-/// prebuilt levels.
-pub fn spawn_level(world: &mut World, name: &str) {
-    let deser = match read_json(name) {
-        Ok(data) => serde_json::from_str::<SaveGame>(&data),
-        Err(_) => {
-            warn!("Failed to read save file: {}", name);
-            return;
+/// Saves the current level in the background, see [`PendingSave`].
+pub fn save_level(world: &mut World, as_name: &str) {
+    let save = collect_save(world);
+    if let Err(errors) = validate_savegame(&save) {
+        for e in errors {
+            warn!("Saving invalid level {}: {}", as_name, e);
         }
-    };
+    }
 
-    let save = match deser {
+    let data = match serde_json::to_string(&save) {
         Ok(data) => data,
         Err(e) => {
-            warn!("Failed to deserialize save file: {:?}", e);
+            warn!("Failed to serialize save file: {:?}", e);
             return;
         }
     };
+    let name = as_name.to_string();
+    let done = run_io(move || write_json(data, &name));
+    world.spawn().insert(PendingSave {
+        name: as_name.to_string(),
+        done,
+    });
+}
+
+/// The result of an IO operation which might still be running, see [`run_io`].
+pub struct IoResult<T>(Arc<Mutex<Option<T>>>);
+
+impl<T> IoResult<T> {
+    /// Takes the result once it is available.
+    pub fn take(&self) -> Option<T> {
+        self.0.lock().ok()?.take()
+    }
+}
+
+/// Where file IO of the save games runs, so callers don't need to care about the platform.
+pub trait IoBackend {
+    fn run<T: Send + 'static>(io: impl FnOnce() -> T + Send + 'static) -> IoResult<T>;
+}
+
+/// Runs IO on the [`IoTaskPool`](bevy::tasks::IoTaskPool), so slow disks don't stall the frame.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TaskPoolIo;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl IoBackend for TaskPoolIo {
+    fn run<T: Send + 'static>(io: impl FnOnce() -> T + Send + 'static) -> IoResult<T> {
+        let slot = Arc::new(Mutex::new(None));
+        let task_slot = slot.clone();
+        bevy::tasks::IoTaskPool::get()
+            .spawn(async move {
+                let result = io();
+                if let Ok(mut slot) = task_slot.lock() {
+                    *slot = Some(result);
+                }
+            })
+            .detach();
+        IoResult(slot)
+    }
+}
+
+/// Runs IO right away, `LocalStorage` is effectively instant.
+#[cfg(target_arch = "wasm32")]
+pub struct ImmediateIo;
+
+#[cfg(target_arch = "wasm32")]
+impl IoBackend for ImmediateIo {
+    fn run<T: Send + 'static>(io: impl FnOnce() -> T + Send + 'static) -> IoResult<T> {
+        IoResult(Arc::new(Mutex::new(Some(io()))))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+type PlatformIo = TaskPoolIo;
+#[cfg(target_arch = "wasm32")]
+type PlatformIo = ImmediateIo;
+
+/// Runs `io` with the [`IoBackend`] of the platform.
+pub fn run_io<T: Send + 'static>(io: impl FnOnce() -> T + Send + 'static) -> IoResult<T> {
+    PlatformIo::run(io)
+}
+
+/// A level which is written in the background, despawned once it's done.
+#[derive(Component)]
+pub struct PendingSave {
+    pub name: String,
+    pub done: IoResult<()>,
+}
+
+/// A saved level which is read in the background and loaded once it's available.
+#[derive(Component)]
+pub struct PendingLoad {
+    pub name: String,
+    pub data: IoResult<Result<String, ()>>,
+    /// Whether the player reloaded with `R`, which dismisses the reload hints once it's loaded.
+    pub manual_reload: bool,
+}
+
+fn finish_saving(mut commands: Commands, pending: Query<(Entity, &PendingSave)>) {
+    for (id, save) in pending.iter() {
+        if save.done.take().is_some() {
+            info!("Saved level {}", save.name);
+            commands.entity(id).despawn();
+        }
+    }
+}
+
+fn finish_loading(world: &mut World) {
+    let mut pending = world.query::<(Entity, &PendingLoad)>();
+    let ready: Vec<(Entity, String, bool, Result<String, ()>)> = pending
+        .iter(world)
+        .filter_map(|(id, load)| {
+            Some((id, load.name.clone(), load.manual_reload, load.data.take()?))
+        })
+        .collect();
+    for (id, name, manual_reload, data) in ready {
+        world.despawn(id);
+        let result = data
+            .map_err(|_| SaveError::NotFound(name.clone()))
+            .and_then(|data| spawn_level_data(world, &name, &data));
+        apply_load(world, &name, result, manual_reload);
+    }
+}
+
+/// Loads built-in levels right away and saved ones in the background, see [`PendingLoad`].
+/// A load which is still pending is dropped, so it can't replace the newer level later on.
+fn start_loading(world: &mut World, name: &str, manual_reload: bool) {
+    let mut pending = world.query_filtered::<Entity, With<PendingLoad>>();
+    let pending: Vec<Entity> = pending.iter(world).collect();
+    for id in pending {
+        world.despawn(id);
+    }
+
+    if builtin_level(name).is_some() {
+        let result = spawn_level(world, name);
+        apply_load(world, name, result, manual_reload);
+        return;
+    }
+    let owned = name.to_string();
+    let data = run_io(move || read_json(&owned));
+    world.spawn().insert(PendingLoad {
+        name: name.to_string(),
+        data,
+        manual_reload,
+    });
+}
+
+fn apply_load(world: &mut World, name: &str, result: Result<(), SaveError>, manual_reload: bool) {
+    if result.is_ok() && manual_reload {
+        dismiss_reload_hints(world);
+    }
+    report_load(world, name, result);
+}
+
+/// The player knows how to reload now, so the reload hints fade out.
+fn dismiss_reload_hints(world: &mut World) {
+    let mut hint_query =
+        world.query_filtered::<Entity, (With<ReloadHint>, Without<SoftDespawned>)>();
+    let time = world.resource::<Time>().time_since_startup();
+    let hints: Vec<Entity> = hint_query.iter(world).collect();
+    for id in hints {
+        world
+            .entity_mut(id)
+            .insert(SoftDespawned { death_time: time });
+    }
+}
+
+/// Replaces the world content with the level named `name`. Numerical names are the
+/// prebuilt levels.
+/// If the level can't be loaded, the world stays unchanged.
+pub fn spawn_level(world: &mut World, name: &str) -> Result<(), SaveError> {
+    let data = read_json(name).map_err(|_| SaveError::NotFound(name.to_string()))?;
+    spawn_level_data(world, name, &data)
+}
+
+/// The current level as a string to share, e.g. via the clipboard.
+pub fn level_to_string(world: &mut World) -> String {
+    serde_json::to_string(&collect_save(world)).unwrap_or_else(|e| {
+        warn!("Failed to serialize level: {:?}", e);
+        String::new()
+    })
+}
+
+/// Replaces the world content with a level from [`level_to_string`].
+/// If the level can't be loaded, the world stays unchanged.
+pub fn level_from_string(world: &mut World, data: &str) -> Result<(), SaveError> {
+    spawn_level_data(world, PASTED_LEVEL, data)
+}
+
+/// Like [`spawn_level`], but with the level data of the level named `name`.
+fn spawn_level_data(world: &mut World, name: &str, data: &str) -> Result<(), SaveError> {
+    let save = parse_save(data)?;
+    validate_savegame(&save).map_err(SaveError::Invalid)?;
 
     clear_world(world);
+    world.insert_resource(save.contact_rule);
+    if let Some(mut progress) = world.get_resource_mut::<Progress>() {
+        progress.start_attempt();
+    }
 
     // Spawn level data
+    let grid = save.grid.unwrap_or_default();
+    world.insert_resource(grid);
+    let immovables_mesh = match world.get_resource_mut::<Assets<Mesh>>() {
+        Some(mut meshes) if !save.immovables.is_empty() => {
+            Some(meshes.add(build_clump_mesh(&save.immovables, &grid)))
+        }
+        _ => None,
+    };
     let assets = world.resource::<AssetHandles>();
+    let handle_radius = world
+        .get_resource::<EditorPrefs>()
+        .map_or(SELECTABLE_RADIUS, |prefs| prefs.handle_radius);
 
     let mut command_queue = CommandQueue::default();
     let mut commands = Commands::new(&mut command_queue, world);
     // old clump id mapped to new triangle ids
     let mut clumps: HashMap<Entity, Vec<Entity>> = HashMap::new();
 
+    let triangle_colors: HashMap<TileCoord, TileColor> = save.triangle_colors.into_iter().collect();
+    let rune_colors: HashMap<TileCoord, TileColor> = save.rune_colors.into_iter().collect();
+
+    // Pieces grow one after another, first the triangles, then the runes
+    let triangle_count = save.triangles.len();
+
     // Spawn triangles
-    for (tile, old_clump_id) in save.triangles {
+    for (i, (tile, old_clump_id)) in save.triangles.into_iter().enumerate() {
+        let color = triangle_colors
+            .get(&tile.position)
+            .copied()
+            .unwrap_or_default();
         let trig = spawn_solo_triangle(
             &mut commands,
             tile.position,
+            &grid,
             assets.triangle_mesh.clone(),
-            assets.triangle_material.clone(),
+            assets.material_for(color),
+            handle_radius,
+            i as f32 * SPAWN_STAGGER,
         );
+        commands.entity(trig).insert(color);
         match clumps.get_mut(&old_clump_id) {
             Some(v) => v.push(trig),
             None => {
@@ -135,47 +573,189 @@ pub fn spawn_level(world: &mut World, name: &str) {
 
     // Spawn triangle clump parents
     for (_, children) in clumps {
+        spawn_clump(&mut commands, &children);
+    }
+
+    // Spawn immovables, which are rendered as a single mesh
+    for &position in save.immovables.iter() {
+        let tile = TriangleTile { position };
         commands
-            .spawn()
-            .insert_bundle(TransformBundle::default())
-            .insert_bundle(VisibilityBundle::default())
-            .push_children(&children);
+            .spawn_bundle(TransformBundle::from_transform(tile.to_world_pos_in(&grid)))
+            .insert(tile)
+            .insert(Immovable);
+    }
+    if let Some(mesh) = immovables_mesh {
+        commands
+            .spawn_bundle(MaterialMesh2dBundle {
+                mesh: mesh.into(),
+                material: assets.immovable_material.clone(),
+                transform: Transform::from_xyz(0., 0., TRIANGLE_Z),
+                ..Default::default()
+            })
+            .insert(Name::new("Immovables"))
+            .insert(Immovable);
     }
 
-    // Spawn immovables
-    for coord in save.immovables {
-        spawn_immovable(
+    // Spawn runes
+    let sprites = world.resource::<SpriteAssets>();
+    for (i, rune) in save.runes.into_iter().enumerate() {
+        let color = rune_colors.get(&rune.position).copied().unwrap_or_default();
+        let entity = spawn_rune(
             &mut commands,
-            coord,
-            assets.triangle_mesh.clone(),
-            assets.immovable_material.clone(),
+            RuneTile {
+                rune_index: rune.rune_index % RUNE_GLYPHS,
+                ..rune
+            },
+            &grid,
+            sprites.runes.clone(),
+            (triangle_count + i) as f32 * SPAWN_STAGGER,
         );
+        commands.entity(entity).insert(color);
     }
 
-    // Spawn runes
-    let sprites = world.resource::<SpriteAssets>();
-    for rune in save.runes {
-        spawn_rune(&mut commands, rune.position, sprites.runes.clone());
+    // Spawn tutorial hints
+    for hint in save.hints.iter() {
+        spawn_hint(&mut commands, hint, sprites, &grid);
     }
 
     command_queue.apply(world);
+    // Before the intro, which sweeps back to the fitted view
+    fit_camera_to_level(world);
 
-    // Update level name
-    let mut text = world.query::<&mut Text>();
-    if let Ok(mut text) = text.get_single_mut(world) {
-        if let Some(mut section) = text.sections.first_mut() {
-            section.value = String::from(name);
+    if let Some(sweep) = &save.intro {
+        if let Some(mut sweeps) = world.get_resource_mut::<Events<CameraSweep>>() {
+            sweeps.send(sweep.clone());
         }
     }
+    world.insert_resource(LevelIntro(save.intro));
+    if let Some(mut level) = world.get_resource_mut::<LevelInfo>() {
+        level.move_count = 0;
+        level.par = save.par;
+    }
+    if let Some(mut dirty) = world.get_resource_mut::<Events<BoardDirty>>() {
+        dirty.send(BoardDirty);
+    }
+    if let Some(mut loaded) = world.get_resource_mut::<Events<LevelLoaded>>() {
+        loaded.send(LevelLoaded {
+            name: name.to_string(),
+        });
+    }
+
+    // A pasted level stays in the sandbox, so shared levels can be edited there
+    let sandbox = match name {
+        PASTED_LEVEL => world.get_resource::<EditorEnabled>().map_or(false, |e| e.0),
+        _ => name == SANDBOX_LEVEL,
+    };
+    apply_sandbox(world, sandbox);
+    set_level_label(world, name, LEVEL_LABEL_COLOR);
+    Ok(())
 }
 
-pub fn clear_world(world: &mut World) {
-    let mut current_tris = world.query_filtered::<&Parent, With<TriangleTile>>();
-    // Collection is necessary to prevent concurrent modification
-    let current_clumps: Vec<Entity> = current_tris.iter(world).map(|p| p.get()).collect();
-    for clump in current_clumps {
-        despawn_with_children_recursive(world, clump);
+/// Like [`spawn_level`], but logs errors. With [`LoadingOptions::strict`] the world is cleared
+/// and the error is shown in the level label instead of keeping the previous level.
+pub fn load_level(world: &mut World, name: &str) {
+    let result = spawn_level(world, name);
+    report_load(world, name, result);
+}
+
+fn report_load(world: &mut World, name: &str, result: Result<(), SaveError>) {
+    if let Err(e) = result {
+        warn!("Failed to load level: {}", e);
+
+        let strict = world
+            .get_resource::<LoadingOptions>()
+            .map_or(false, |options| options.strict);
+        if strict {
+            clear_world(world);
+            set_level_label(world, &format!("{}?", name), Color::RED);
+        }
+    }
+}
+
+fn set_level_label(world: &mut World, text: &str, color: Color) {
+    let mut label = world.query_filtered::<&mut Text, With<LevelLabel>>();
+    if let Ok(mut label) = label.get_single_mut(world) {
+        if let Some(mut section) = label.sections.first_mut() {
+            section.value = String::from(text);
+            section.style.color = color;
+        }
+    }
+}
+
+/// Checks that no two tiles of the same kind overlap and that no triangle or rune is covered by
+/// an immovable.
+pub fn validate_savegame(save: &SaveGame) -> Result<(), Vec<LevelError>> {
+    let mut errors = Vec::new();
+
+    let mut triangles: HashSet<TileCoord> = HashSet::new();
+    for (tile, _) in save.triangles.iter() {
+        if !triangles.insert(tile.position) {
+            errors.push(LevelError::DuplicateTriangle(tile.position));
+        }
     }
+    let mut immovables: HashSet<TileCoord> = HashSet::new();
+    for &position in save.immovables.iter() {
+        if !immovables.insert(position) {
+            errors.push(LevelError::DuplicateImmovable(position));
+        } else if triangles.contains(&position) {
+            errors.push(LevelError::TriangleOnImmovable(position));
+        }
+    }
+    let mut runes: HashSet<TileCoord> = HashSet::new();
+    for rune in save.runes.iter() {
+        if !runes.insert(rune.position) {
+            errors.push(LevelError::DuplicateRune(rune.position));
+        } else if immovables.contains(&rune.position) {
+            errors.push(LevelError::RuneUnderImmovable(rune.position));
+        }
+        let mut required: HashSet<TileCoord> = HashSet::new();
+        for &cell in rune.required.iter() {
+            if !required.insert(cell) {
+                errors.push(LevelError::DuplicateRequired(cell));
+            } else if immovables.contains(&cell) {
+                errors.push(LevelError::RequiredUnderImmovable(cell));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parses level data of any supported version, older versions are migrated first.
+fn parse_save(data: &str) -> Result<SaveGame, SaveError> {
+    let mut value: serde_json::Value =
+        serde_json::from_str(data).map_err(|e| SaveError::Malformed(e.to_string()))?;
+    migrate_save(&mut value)?;
+    serde_json::from_value(value).map_err(|e| SaveError::Malformed(e.to_string()))
+}
+
+/// Upgrades level data to the [`SAVE_VERSION`].
+fn migrate_save(save: &mut serde_json::Value) -> Result<(), SaveError> {
+    let version = match save.get("version") {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| SaveError::Malformed(format!("invalid version {}", v)))?,
+    };
+    if version > SAVE_VERSION {
+        return Err(SaveError::UnsupportedVersion(version));
+    }
+    for migrate in &MIGRATIONS[version as usize..] {
+        migrate(save);
+    }
+    if let Some(fields) = save.as_object_mut() {
+        fields.insert(String::from("version"), SAVE_VERSION.into());
+    }
+    Ok(())
+}
+
+pub fn clear_world(world: &mut World) {
+    clear_movable_triangles(world);
 
     let mut current_immovables = world.query_filtered::<Entity, With<Immovable>>();
     let current_immovables: Vec<Entity> = current_immovables.iter(world).collect();
@@ -188,41 +768,49 @@ pub fn clear_world(world: &mut World) {
     for rune in current_runes {
         despawn_with_children_recursive(world, rune);
     }
+
+    let mut current_hints = world.query_filtered::<Entity, With<TutorialHint>>();
+    let current_hints: Vec<Entity> = current_hints.iter(world).collect();
+    for hint in current_hints {
+        despawn_with_children_recursive(world, hint);
+    }
+}
+
+/// Despawns the clumps of movable triangles, but keeps immovables and runes.
+pub fn clear_movable_triangles(world: &mut World) {
+    let mut current_clumps = world.query_filtered::<Entity, With<ClumpRoot>>();
+    // Collection is necessary to prevent concurrent modification
+    let current_clumps: Vec<Entity> = current_clumps.iter(world).collect();
+    for clump in current_clumps {
+        despawn_with_children_recursive(world, clump);
+    }
 }
 
 /// System to load levels when pressing either the number buttons for a specific level
 /// or R to restart the current one.
 fn load_system(world: &mut World) {
     let keys = world.resource::<Input<KeyCode>>();
-    let is_modifier_down = keys.pressed(KeyCode::LControl);
+    let is_modifier_down = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::Delete);
     if is_modifier_down {
-        // dont load when saving
+        // dont load when saving or deleting
         return;
     }
     let jump_to_level_key = get_just_pressed_num(keys);
     let manual_reload = keys.just_pressed(KeyCode::R);
+    if keys.just_pressed(KeyCode::P) {
+        start_loading(world, SANDBOX_LEVEL, false);
+        return;
+    }
 
     let mut lvl = world.resource_mut::<LevelInfo>();
     let next_level_reload = lvl.should_reload;
 
     if let Some(key) = jump_to_level_key {
-        lvl.current = key;
-        spawn_level(world, key.to_string().as_str());
+        lvl.current = LevelId::from_name(&key.to_string());
+        start_loading(world, key.to_string().as_str(), false);
     } else if next_level_reload || manual_reload {
-        let curr = lvl.current;
-        spawn_level(world, curr.to_string().as_str());
-
-        if manual_reload {
-            // Remove hint
-            let mut hint_query =
-                world.query_filtered::<Entity, (With<ReloadHint>, Without<SoftDespawned>)>();
-            let time = world.resource::<Time>().time_since_startup();
-            if let Ok(id) = hint_query.get_single(&world) {
-                world
-                    .entity_mut(id)
-                    .insert(SoftDespawned { death_time: time });
-            }
-        }
+        let curr = lvl.current.to_string();
+        start_loading(world, &curr, manual_reload);
 
         // Reset
         world.resource_mut::<LevelInfo>().should_reload = false;
@@ -246,14 +834,143 @@ fn save_system(world: &mut World) {
     save_level(world, level_name.to_string().as_str());
 }
 
+/// System to delete a saved level when pressing `Delete` + a number button.
+fn delete_system(keys: Res<Input<KeyCode>>) {
+    if !keys.pressed(KeyCode::Delete) {
+        return;
+    }
+    if let Some(slot) = get_just_pressed_num(&keys) {
+        if let Err(e) = delete_level(&slot.to_string()) {
+            warn!("Failed to delete level: {}", e);
+        }
+    }
+}
+
+/// Copies the current level to the clipboard with `Left Control` + `C` and loads a level from
+/// the clipboard with `Left Control` + `V`.
+fn clipboard_system(world: &mut World) {
+    let keys = world.resource::<Input<KeyCode>>();
+    if keys.pressed(KeyCode::LControl) {
+        let (copy, paste) = (keys.just_pressed(KeyCode::C), keys.just_pressed(KeyCode::V));
+        if copy {
+            copy_to_clipboard(level_to_string(world));
+        } else if paste {
+            request_paste();
+        }
+    }
+
+    if let Some(data) = take_pasted() {
+        match level_from_string(world, &data) {
+            Ok(()) => info!("Loaded level from the clipboard"),
+            Err(e) => warn!("Failed to paste level: {}", e),
+        }
+    }
+}
+
+thread_local! {
+    /// Clipboard text which was requested by [`request_paste`], on the web it arrives later.
+    static PASTED: std::cell::RefCell<Option<String>> = Default::default();
+}
+
+fn take_pasted() -> Option<String> {
+    PASTED.with(|pasted| pasted.borrow_mut().take())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn copy_to_clipboard(text: String) {
+    match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text)) {
+        Ok(()) => info!("Copied level to the clipboard"),
+        Err(e) => warn!("Failed to copy to the clipboard: {}", e),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn request_paste() {
+    match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+        Ok(text) => PASTED.with(|pasted| *pasted.borrow_mut() = Some(text)),
+        Err(e) => warn!("Failed to read the clipboard: {}", e),
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn web_clipboard() -> Option<web_sys::Clipboard> {
+    let clipboard = web_sys::window().and_then(|w| w.navigator().clipboard());
+    if clipboard.is_none() {
+        warn!("The clipboard is unavailable");
+    }
+    clipboard
+}
+
+#[cfg(target_arch = "wasm32")]
+fn copy_to_clipboard(text: String) {
+    if let Some(clipboard) = web_clipboard() {
+        // The promise only tells whether it worked
+        let _ = clipboard.write_text(&text);
+        info!("Copied level to the clipboard");
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn request_paste() {
+    use wasm_bindgen::{closure::Closure, JsValue};
+
+    let clipboard = match web_clipboard() {
+        Some(c) => c,
+        None => return,
+    };
+    let on_read = Closure::once(|text: JsValue| {
+        if let Some(text) = text.as_string() {
+            PASTED.with(|pasted| *pasted.borrow_mut() = Some(text));
+        }
+    });
+    let on_error = Closure::once(|e: JsValue| warn!("Failed to read the clipboard: {:?}", e));
+    let _ = clipboard.read_text().then2(&on_read, &on_error);
+    // The callbacks are called by the browser later on
+    on_read.forget();
+    on_error.forget();
+}
+
+/// Directory of the saved levels on PC
+#[cfg(not(target_arch = "wasm32"))]
+pub const LEVELS_DIR: &str = "levels";
+
 #[cfg(not(target_arch = "wasm32"))]
 fn json_path(name: &str) -> PathBuf {
-    std::path::Path::new("levels")
-        .join(name)
-        .with_extension("json")
+    json_path_in(Path::new(LEVELS_DIR), name)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn json_path_in(dir: &Path, name: &str) -> PathBuf {
+    dir.join(name).with_extension("json")
+}
+
+/// Name of the exported copy of the built-in level `i`. Plain numbers would be shadowed by the
+/// built-in levels, so the copies are saved levels which can be loaded and edited.
+#[cfg(not(target_arch = "wasm32"))]
+fn export_name(i: usize) -> String {
+    format!("level-{}", i)
+}
+
+/// Writes the built-in levels to `dir` named by [`export_name`], so they can be used as a
+/// starting point for new levels. Existing files are only overwritten with `force`.
+/// Returns the number of written levels.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn export_levels(dir: &Path, force: bool) -> std::io::Result<usize> {
+    std::fs::create_dir_all(dir)?;
+    let mut written = 0;
+    for (i, data) in LEVELS.iter().enumerate() {
+        let path = json_path_in(dir, &export_name(i));
+        if path.exists() && !force {
+            info!("Skipping existing {}", path.display());
+            continue;
+        }
+        std::fs::write(&path, data)?;
+        written += 1;
+    }
+    Ok(written)
 }
 
-fn write_json(data: String, name: &str) {
+pub fn write_json(data: String, name: &str) {
     // from https://github.com/rparrett/pixie_wrangler/blob/main/src/save.rs
     #[cfg(not(target_arch = "wasm32"))]
     {
@@ -271,32 +988,164 @@ fn write_json(data: String, name: &str) {
     }
     #[cfg(target_arch = "wasm32")]
     {
-        let window = match web_sys::window() {
-            Some(w) => w,
-            None => return,
-        };
-
-        let storage = match window.local_storage() {
-            Ok(Some(s)) => s,
-            _ => return,
+        let storage = match local_storage() {
+            Some(s) => s,
+            None => {
+                FALLBACK_STORE.with(|store| store.borrow_mut().set(name, data));
+                return;
+            }
         };
 
         if let Err(e) = storage.set_item(name, data.as_str()) {
             warn!("Failed to store save file: {:?}", e);
+            FALLBACK_STORE.with(|store| store.borrow_mut().set(name, data));
+            return;
         }
     }
 
     info!("Wrote to save file {}", name);
 }
 
-fn read_json(name: &str) -> Result<String, ()> {
-    // Read static levels if existing. They have the numberic names starting from "0".
-    let as_num: Result<usize, _> = name.parse();
-    if let Ok(i) = as_num {
-        if let Some(data) = LEVELS.get(i) {
-            info!("Read static save state {}", name);
-            return Ok(String::from(*data));
+/// Whether `name` is a built-in level or a file which isn't a level. These can't be deleted or
+/// renamed.
+pub fn is_protected(name: &str) -> bool {
+    builtin_level(name).is_some() || LEVEL_FILES.contains(&name) || RESERVED_NAMES.contains(&name)
+}
+
+/// Names of the saved levels, sorted. Built-in levels and other files aren't listed.
+pub fn list_saved_levels() -> Vec<String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut names = saved_levels_in(Path::new(LEVELS_DIR));
+    #[cfg(target_arch = "wasm32")]
+    let mut names = {
+        let mut names = FALLBACK_STORE.with(|store| store.borrow().names());
+        if let Some(storage) = local_storage() {
+            let len = storage.length().unwrap_or(0);
+            names.extend((0..len).filter_map(|i| storage.key(i).ok().flatten()));
         }
+        names.retain(|name| !is_protected(name));
+        names
+    };
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn saved_levels_in(dir: &Path) -> Vec<String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to list saved levels: {:?}", e);
+            return Vec::new();
+        }
+    };
+    entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .filter(|name| !is_protected(name))
+        .collect()
+}
+
+/// Deletes the saved level `name`.
+pub fn delete_level(name: &str) -> Result<(), SaveError> {
+    if is_protected(name) {
+        return Err(SaveError::Protected(name.to_string()));
+    }
+    remove_json(name)?;
+    info!("Deleted level {}", name);
+    Ok(())
+}
+
+/// Renames the saved level `old` to `new`, existing levels are never overwritten. The game has no
+/// text input to type a new name, it is called by `--rename-level` and on the web from the
+/// browser console.
+pub fn rename_level(old: &str, new: &str) -> Result<(), SaveError> {
+    if let Some(name) = [old, new].into_iter().find(|name| is_protected(name)) {
+        return Err(SaveError::Protected(name.to_string()));
+    }
+    if read_json(new).is_ok() {
+        return Err(SaveError::AlreadyExists(new.to_string()));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    std::fs::rename(json_path(old), json_path(new)).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => SaveError::NotFound(old.to_string()),
+        _ => SaveError::Io(e.to_string()),
+    })?;
+    #[cfg(target_arch = "wasm32")]
+    match local_storage() {
+        Some(storage) => {
+            let data = match storage.get_item(old) {
+                Ok(Some(data)) => data,
+                _ => return Err(SaveError::NotFound(old.to_string())),
+            };
+            storage
+                .set_item(new, &data)
+                .map_err(|e| SaveError::Io(format!("{:?}", e)))?;
+            storage
+                .remove_item(old)
+                .map_err(|e| SaveError::Io(format!("{:?}", e)))?;
+        }
+        None => {
+            let data = FALLBACK_STORE
+                .with(|store| store.borrow_mut().remove(old))
+                .ok_or_else(|| SaveError::NotFound(old.to_string()))?;
+            FALLBACK_STORE.with(|store| store.borrow_mut().set(new, data));
+        }
+    }
+
+    info!("Renamed level {} to {}", old, new);
+    Ok(())
+}
+
+fn remove_json(name: &str) -> Result<(), SaveError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::fs::remove_file(json_path(name)).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => SaveError::NotFound(name.to_string()),
+            _ => SaveError::Io(e.to_string()),
+        })
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        // Moves items from the fallback store first
+        let stored = match local_storage() {
+            Some(storage) => {
+                let existed = matches!(storage.get_item(name), Ok(Some(_)));
+                storage
+                    .remove_item(name)
+                    .map_err(|e| SaveError::Io(format!("{:?}", e)))?;
+                existed
+            }
+            None => false,
+        };
+        let in_memory = FALLBACK_STORE.with(|store| store.borrow_mut().remove(name));
+        if stored || in_memory.is_some() {
+            Ok(())
+        } else {
+            Err(SaveError::NotFound(name.to_string()))
+        }
+    }
+}
+
+/// The data of the built-in level `name`. They have the numeric names starting from "0", and
+/// there is the sandbox.
+fn builtin_level(name: &str) -> Option<&'static str> {
+    if name == SANDBOX_LEVEL {
+        return Some(SANDBOX);
+    }
+    name.parse::<usize>()
+        .ok()
+        .and_then(|i| LEVELS.get(i).copied())
+}
+
+pub fn read_json(name: &str) -> Result<String, ()> {
+    // Read static levels if existing
+    if let Some(data) = builtin_level(name) {
+        info!("Read static save state {}", name);
+        return Ok(String::from(data));
     }
 
     // from https://github.com/rparrett/pixie_wrangler/blob/main/src/save.rs
@@ -312,14 +1161,13 @@ fn read_json(name: &str) -> Result<String, ()> {
     }
     #[cfg(target_arch = "wasm32")]
     {
-        let window = match web_sys::window() {
-            Some(w) => w,
-            None => return Err(()),
-        };
-
-        let storage = match window.local_storage() {
-            Ok(Some(s)) => s,
-            _ => return Err(()),
+        let storage = match local_storage() {
+            Some(s) => s,
+            None => {
+                return FALLBACK_STORE
+                    .with(|store| store.borrow().get(name))
+                    .ok_or(());
+            }
         };
 
         let item = match storage.get_item(name) {
@@ -331,6 +1179,68 @@ fn read_json(name: &str) -> Result<String, ()> {
     }
 }
 
+/// Key value store in memory, used on the web when `LocalStorage` isn't available, e.g. in
+/// private browsing. Its items are moved to `LocalStorage` once that works again.
+#[cfg(any(target_arch = "wasm32", test))]
+#[derive(Default)]
+struct MemoryStore {
+    items: HashMap<String, String>,
+}
+
+#[cfg(any(target_arch = "wasm32", test))]
+impl MemoryStore {
+    fn set(&mut self, name: &str, data: String) {
+        self.items.insert(name.to_string(), data);
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.items.get(name).cloned()
+    }
+
+    fn remove(&mut self, name: &str) -> Option<String> {
+        self.items.remove(name)
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.items.keys().cloned().collect()
+    }
+
+    /// Removes and returns all items.
+    fn drain(&mut self) -> Vec<(String, String)> {
+        self.items.drain().collect()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+    static FALLBACK_STORE: std::cell::RefCell<MemoryStore> = Default::default();
+    static PERSISTENCE_WARNED: std::cell::Cell<bool> = Default::default();
+}
+
+/// Returns `LocalStorage` if it is available. Otherwise warns once that saves won't be
+/// persisted. Items saved in the meantime are flushed as soon as it is available again.
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    let storage = match web_sys::window().map(|w| w.local_storage()) {
+        Some(Ok(Some(s))) => s,
+        _ => {
+            if !PERSISTENCE_WARNED.with(|warned| warned.replace(true)) {
+                warn!("LocalStorage is unavailable, saves are only kept for this session!");
+            }
+            return None;
+        }
+    };
+
+    let pending = FALLBACK_STORE.with(|store| store.borrow_mut().drain());
+    for (name, data) in pending {
+        if let Err(e) = storage.set_item(&name, &data) {
+            warn!("Failed to store save file: {:?}", e);
+            FALLBACK_STORE.with(|store| store.borrow_mut().set(&name, data));
+        }
+    }
+    Some(storage)
+}
+
 fn get_just_pressed_num(keys: &Input<KeyCode>) -> Option<usize> {
     // UUUGGGLYYYY
     if keys.just_pressed(KeyCode::Key0) {
@@ -365,3 +1275,472 @@ fn get_just_pressed_num(keys: &Input<KeyCode>) -> Option<usize> {
     }
     None
 }
+
+#[test]
+fn test_savegame_bounds() {
+    use crate::tilemap::{TriangleOrient, X_DIR, Y_DIR};
+
+    let empty = SaveGame::default();
+    assert_eq!(empty.bounds(), (VertexCoord::ZERO, VertexCoord::ZERO));
+    assert_eq!(empty.centroid_world(), Vec2::ZERO);
+
+    let single = SaveGame {
+        triangles: vec![],
+        immovables: vec![(VertexCoord::ZERO, TriangleOrient::PointingUp)],
+        ..Default::default()
+    };
+    assert_eq!(single.bounds(), (VertexCoord::ZERO, VertexCoord::ONE));
+    let center = VertexCoord::ZERO.to_world_pos().translation.truncate() + (X_DIR + Y_DIR) / 3.;
+    assert!(single.centroid_world().distance(center) < 1e-3);
+
+    let spread = SaveGame {
+        triangles: vec![(
+            TriangleTile {
+                position: (VertexCoord::new(-2, 1), TriangleOrient::PointingUp),
+            },
+            Entity::from_raw(0),
+        )],
+        immovables: vec![(VertexCoord::new(3, 0), TriangleOrient::PointingDown)],
+        runes: vec![RuneTile {
+            position: (VertexCoord::new(0, -2), TriangleOrient::PointingUp),
+            rune_index: 0,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    assert_eq!(
+        spread.bounds(),
+        (VertexCoord::new(-2, -2), VertexCoord::new(4, 2))
+    );
+    // Every tile has three vertices, so it's the mean of the tile centers
+    let center = spread
+        .all_tiles()
+        .map(|tile| tile.world_center())
+        .sum::<Vec2>()
+        / 3.;
+    assert!(spread.centroid_world().distance(center) < 1e-3);
+}
+
+#[test]
+fn test_immovables_mesh() {
+    use bevy::sprite::Mesh2dHandle;
+
+    use crate::level::SpawnAnimation;
+
+    let mut app = crate::headless::build_app();
+    app.insert_resource(Assets::<Mesh>::default());
+    spawn_level(&mut app.world, "3").unwrap();
+    app.update();
+
+    // Sorted by the depth of the entity, since the vertices are baked at z = 0
+    let mut meshes = app
+        .world
+        .query_filtered::<(Entity, &Transform), (With<Mesh2dHandle>, With<Immovable>)>();
+    let (mesh, transf) = meshes.single(&app.world);
+    assert_eq!(transf.translation.z, TRIANGLE_Z);
+    assert!(app.world.get::<SpawnAnimation>(mesh).is_none());
+}
+
+#[test]
+fn test_memory_store() {
+    let mut store = MemoryStore::default();
+    assert_eq!(store.get("1"), None);
+
+    store.set("1", String::from("first"));
+    store.set("1", String::from("second"));
+    assert_eq!(store.get("1"), Some(String::from("second")));
+    assert_eq!(store.remove("1"), Some(String::from("second")));
+    assert_eq!(store.remove("1"), None);
+    store.set("1", String::from("second"));
+    assert_eq!(store.names(), vec![String::from("1")]);
+
+    assert_eq!(
+        store.drain(),
+        vec![(String::from("1"), String::from("second"))]
+    );
+    assert_eq!(store.get("1"), None);
+}
+
+#[test]
+fn test_loading_errors() {
+    assert_eq!(
+        spawn_level(&mut World::new(), "missing-level"),
+        Err(SaveError::NotFound(String::from("missing-level")))
+    );
+    assert!(matches!(
+        parse_save("{\"triangles\": [}"),
+        Err(SaveError::Malformed(_))
+    ));
+    assert!(parse_save(LEVELS[0]).is_ok());
+    assert_eq!(parse_save(LEVELS[1]).unwrap().par, Some(4));
+    assert_eq!(
+        parse_save("{\"version\": 99, \"triangles\": []}").err(),
+        Some(SaveError::UnsupportedVersion(99))
+    );
+    assert_eq!(
+        parse_save("{\"triangles\": [], \"immovables\": [], \"runes\": [], \"par\": 4}")
+            .map(|save| save.par),
+        Ok(Some(4))
+    );
+    assert_eq!(
+        parse_save(
+            "{\"triangles\": [], \"immovables\": [], \"runes\": [], \
+            \"grid\": {\"origin\": [0, 0], \"side\": 60}}"
+        )
+        .map(|save| save.grid),
+        Ok(Some(GridConfig {
+            origin: Vec2::ZERO,
+            side: 60.
+        }))
+    );
+}
+
+#[test]
+fn test_pending_io() {
+    bevy::tasks::IoTaskPool::init(bevy::tasks::TaskPool::default);
+    let result = run_io(|| 4);
+    let value = loop {
+        if let Some(value) = result.take() {
+            break value;
+        }
+        std::thread::yield_now();
+    };
+    assert_eq!(value, 4);
+    assert_eq!(result.take(), None);
+
+    let mut world = World::new();
+    world.insert_resource(AssetHandles::default());
+    world.insert_resource(SpriteAssets::default());
+    let waiting = world
+        .spawn()
+        .insert(PendingLoad {
+            name: String::from("waiting"),
+            data: IoResult(Arc::new(Mutex::new(None))),
+            manual_reload: false,
+        })
+        .id();
+    let loaded = world
+        .spawn()
+        .insert(PendingLoad {
+            name: String::from("loaded"),
+            data: IoResult(Arc::new(Mutex::new(Some(Ok(String::from(LEVELS[1])))))),
+            manual_reload: false,
+        })
+        .id();
+    finish_loading(&mut world);
+    assert!(world.get_entity(waiting).is_some());
+    assert!(world.get_entity(loaded).is_none());
+    let mut triangles = world.query::<&TriangleTile>();
+    assert!(triangles.iter(&world).count() > 0);
+
+    // A later load drops the one which is still being read
+    start_loading(&mut world, "1", false);
+    assert!(world.get_entity(waiting).is_none());
+}
+
+#[test]
+fn test_protected_levels() {
+    for name in [
+        "1",
+        "1-tutorial",
+        "end",
+        SANDBOX_LEVEL,
+        "manifest",
+        SETTINGS_NAME,
+    ] {
+        assert_eq!(
+            delete_level(name),
+            Err(SaveError::Protected(String::from(name)))
+        );
+    }
+    assert_eq!(
+        delete_level("missing-level"),
+        Err(SaveError::NotFound(String::from("missing-level")))
+    );
+    assert_eq!(
+        rename_level("missing-level", "3"),
+        Err(SaveError::Protected(String::from("3")))
+    );
+    assert_eq!(
+        rename_level("missing-level", "other-missing-level"),
+        Err(SaveError::NotFound(String::from("missing-level")))
+    );
+}
+
+#[test]
+fn test_rune_glyph_migration() {
+    let old = r#"{"version": 1, "triangles": [], "immovables": [], "runes": [
+        {"position": [[0, 0], "PointingUp"]},
+        {"position": [[1, 0], "PointingUp"], "rune_index": 4},
+        {"position": [[2, 0], "PointingUp"]}
+    ]}"#;
+    let glyphs: Vec<usize> = parse_save(old)
+        .unwrap()
+        .runes
+        .iter()
+        .map(|rune| rune.rune_index)
+        .collect();
+    assert_eq!(glyphs, vec![0, 4, 2]);
+}
+
+#[test]
+fn test_level_hints() {
+    let mut world = World::new();
+    world.insert_resource(AssetHandles::default());
+    world.insert_resource(SpriteAssets::default());
+    let mut hints = world.query::<(&TutorialHint, &Visibility)>();
+
+    spawn_level(&mut world, "1").unwrap();
+    let spawned: Vec<_> = hints.iter(&world).collect();
+    assert_eq!(spawned.len(), 1);
+    assert_eq!(spawned[0].0.trigger, crate::level::HintTrigger::OnLoad);
+    assert!(spawned[0].1.is_visible);
+
+    spawn_level(&mut world, "2").unwrap();
+    let spawned: Vec<_> = hints.iter(&world).collect();
+    assert_eq!(spawned.len(), 1);
+    assert_eq!(
+        spawned[0].0.trigger,
+        crate::level::HintTrigger::OnFirstMerge
+    );
+    assert!(!spawned[0].1.is_visible);
+
+    spawn_level(&mut world, "0").unwrap();
+    assert_eq!(hints.iter(&world).count(), 0);
+}
+
+#[test]
+fn test_clear_movable_triangles() {
+    use crate::tilemap::TriangleOrient;
+
+    let mut world = World::new();
+    let position = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let immovable = world
+        .spawn()
+        .insert(TriangleTile { position })
+        .insert(Immovable)
+        .id();
+    let rune = world
+        .spawn()
+        .insert(RuneTile {
+            position,
+            rune_index: 0,
+            ..Default::default()
+        })
+        .id();
+    let triangles = [
+        world.spawn().insert(TriangleTile { position }).id(),
+        world.spawn().insert(TriangleTile { position }).id(),
+    ];
+    let clump = world
+        .spawn()
+        .insert(ClumpRoot)
+        .push_children(&triangles)
+        .id();
+
+    clear_movable_triangles(&mut world);
+    assert!(world.get_entity(immovable).is_some());
+    assert!(world.get_entity(rune).is_some());
+    assert!(world.get_entity(clump).is_none());
+    assert!(triangles.iter().all(|t| world.get_entity(*t).is_none()));
+}
+
+#[test]
+fn test_validate_savegame() {
+    use crate::tilemap::TriangleOrient;
+
+    let up = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let down = (VertexCoord::ZERO, TriangleOrient::PointingDown);
+    let triangle = |position| (TriangleTile { position }, Entity::from_raw(0));
+
+    let valid = SaveGame {
+        triangles: vec![triangle(up)],
+        immovables: vec![down],
+        runes: vec![RuneTile {
+            position: up,
+            rune_index: 0,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    assert_eq!(validate_savegame(&valid), Ok(()));
+
+    let broken = SaveGame {
+        triangles: vec![triangle(up), triangle(up), triangle(down)],
+        immovables: vec![down],
+        runes: vec![
+            RuneTile {
+                position: down,
+                rune_index: 0,
+                ..Default::default()
+            },
+            RuneTile {
+                position: up,
+                rune_index: 0,
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    assert_eq!(
+        validate_savegame(&broken),
+        Err(vec![
+            LevelError::DuplicateTriangle(up),
+            LevelError::TriangleOnImmovable(down),
+            LevelError::RuneUnderImmovable(down),
+        ])
+    );
+
+    let far = (VertexCoord::new(3, 0), TriangleOrient::PointingUp);
+    let large_rune = SaveGame {
+        immovables: vec![down],
+        runes: vec![RuneTile {
+            position: up,
+            required: vec![up, far, far, down],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    assert_eq!(
+        validate_savegame(&large_rune),
+        Err(vec![
+            LevelError::DuplicateRequired(far),
+            LevelError::RequiredUnderImmovable(down),
+        ])
+    );
+
+    assert!(LEVELS
+        .iter()
+        .all(|data| validate_savegame(&parse_save(data).unwrap()).is_ok()));
+}
+
+#[test]
+fn test_level_manifest() {
+    let names: Vec<String> = serde_json::from_str(include_str!("../levels/manifest.json")).unwrap();
+    assert_eq!(names.len(), LEVELS.len());
+    // The built-in levels have no version tag and are migrated
+    assert!(LEVELS
+        .iter()
+        .all(|data| parse_save(data).map(|save| save.version) == Ok(SAVE_VERSION)));
+    // The first level is the empty one
+    assert!(parse_save(LEVELS[0]).unwrap().all_tiles().next().is_none());
+}
+
+#[test]
+fn test_sandbox_mode() {
+    use crate::{history::MoveHistory, rotation::MergeEnabled};
+
+    let mut world = World::new();
+    world.insert_resource(AssetHandles::default());
+    world.insert_resource(SpriteAssets::default());
+
+    spawn_level(&mut world, SANDBOX_LEVEL).unwrap();
+    assert!(!world.resource::<MergeEnabled>().0);
+    assert!(world.resource::<EditorEnabled>().0);
+    assert!(world.resource::<MoveHistory>().enabled);
+
+    spawn_level(&mut world, "1").unwrap();
+    assert!(world.resource::<MergeEnabled>().0);
+    assert!(!world.resource::<EditorEnabled>().0);
+    assert!(world.resource::<MoveHistory>().enabled);
+    let data = level_to_string(&mut world);
+
+    // Pasting keeps the mode
+    level_from_string(&mut world, &data).unwrap();
+    assert!(!world.resource::<EditorEnabled>().0);
+    spawn_level(&mut world, SANDBOX_LEVEL).unwrap();
+    level_from_string(&mut world, &data).unwrap();
+    assert!(!world.resource::<MergeEnabled>().0);
+    assert!(world.resource::<EditorEnabled>().0);
+}
+
+#[test]
+fn test_level_string() {
+    let new_world = || {
+        let mut world = World::new();
+        world.insert_resource(AssetHandles::default());
+        world.insert_resource(SpriteAssets::default());
+        world
+    };
+    let count_triangles = |world: &mut World| {
+        let mut triangles = world.query_filtered::<(), (With<TriangleTile>, With<Parent>)>();
+        triangles.iter(world).count()
+    };
+
+    let mut world = new_world();
+    spawn_level(&mut world, "2").unwrap();
+    let data = level_to_string(&mut world);
+
+    let mut pasted = new_world();
+    level_from_string(&mut pasted, &data).unwrap();
+    assert!(count_triangles(&mut pasted) > 0);
+    assert_eq!(count_triangles(&mut pasted), count_triangles(&mut world));
+
+    assert!(matches!(
+        level_from_string(&mut pasted, "not a level"),
+        Err(SaveError::Malformed(_))
+    ));
+    assert_eq!(count_triangles(&mut pasted), count_triangles(&mut world));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn test_export_levels() {
+    let dir = std::env::temp_dir().join(format!("magnate-export-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(export_levels(&dir, false).unwrap(), LEVELS.len());
+    for i in 0..LEVELS.len() {
+        assert!(!is_protected(&export_name(i)));
+        let data = std::fs::read_to_string(json_path_in(&dir, &export_name(i))).unwrap();
+        assert!(parse_save(&data).is_ok());
+    }
+
+    // Existing files are kept
+    assert_eq!(export_levels(&dir, false).unwrap(), 0);
+    assert_eq!(export_levels(&dir, true).unwrap(), LEVELS.len());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn test_list_saved_levels() {
+    let dir = std::env::temp_dir().join(format!("magnate-list-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["zigzag", "9", "anvil", "manifest", "1", "end"] {
+        std::fs::write(json_path_in(&dir, name), "{}").unwrap();
+    }
+    std::fs::write(dir.join("notes.txt"), "").unwrap();
+    let mut names = saved_levels_in(&dir);
+    names.sort();
+    assert_eq!(names, vec!["9", "anvil", "zigzag"]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_clump_roots() {
+    let mut world = World::new();
+    world.insert_resource(AssetHandles::default());
+    world.insert_resource(SpriteAssets::default());
+    spawn_level(&mut world, "2").unwrap();
+
+    let mut triangles = world.query_filtered::<&Parent, (With<TriangleTile>, Without<Immovable>)>();
+    let parents: Vec<Entity> = triangles.iter(&world).map(|p| p.get()).collect();
+    assert!(!parents.is_empty());
+    assert!(parents.iter().all(|p| world.get::<ClumpRoot>(*p).is_some()));
+
+    let mut immovables = world.query_filtered::<Entity, With<Immovable>>();
+    let immovables: Vec<Entity> = immovables.iter(&world).collect();
+    assert!(!immovables.is_empty());
+    assert!(immovables
+        .iter()
+        .all(|id| world.get::<ClumpRoot>(*id).is_none() && world.get::<Parent>(*id).is_none()));
+
+    let mut clumps = world.query_filtered::<&Children, With<ClumpRoot>>();
+    let clumped: usize = clumps.iter(&world).map(|children| children.len()).sum();
+    assert_eq!(clumped, parents.len());
+}
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    savegame::{read_json, write_json},
+    GameState,
+};
+
+pub(crate) const SETTINGS_NAME: &str = "settings";
+
+/// Player settings, which are persisted between runs.
+/// Press `N` to toggle whether the next level is loaded right after winning, `G` to switch
+/// between the drawn grid and the background image and `O` to toggle reduced motion.
+pub struct MagnateSettingsPlugin;
+
+impl Plugin for MagnateSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Settings::load()).add_system_set(
+            SystemSet::on_update(GameState::Next)
+                .with_system(toggle_auto_advance)
+                .with_system(toggle_procedural_grid)
+                .with_system(toggle_reduced_motion)
+                .with_system(save_settings),
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Load the next level as soon as the win animation ends, otherwise wait for `Enter`
+    pub auto_advance: bool,
+    /// Draw the triangle grid instead of showing the static background image
+    pub procedural_grid: bool,
+    /// Disable animations which aren't needed to play, i.e. camera shakes, confetti and the growing
+    /// hover dot
+    pub reduced_motion: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            auto_advance: true,
+            procedural_grid: false,
+            reduced_motion: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the saved settings or the defaults if there are none.
+    pub fn load() -> Self {
+        match read_json(SETTINGS_NAME) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                warn!("Failed to deserialize settings: {:?}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(data) => write_json(data, SETTINGS_NAME),
+            Err(e) => warn!("Failed to serialize settings: {:?}", e),
+        }
+    }
+}
+
+fn toggle_auto_advance(keys: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+    if keys.just_pressed(KeyCode::N) {
+        settings.auto_advance = !settings.auto_advance;
+        info!("Auto advance: {}", settings.auto_advance);
+    }
+}
+
+fn toggle_procedural_grid(keys: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+    if keys.just_pressed(KeyCode::G) {
+        settings.procedural_grid = !settings.procedural_grid;
+        info!("Procedural grid: {}", settings.procedural_grid);
+    }
+}
+
+fn toggle_reduced_motion(keys: Res<Input<KeyCode>>, mut settings: ResMut<Settings>) {
+    if keys.just_pressed(KeyCode::O) {
+        settings.reduced_motion = !settings.reduced_motion;
+        info!("Reduced motion: {}", settings.reduced_motion);
+    }
+}
+
+fn save_settings(settings: Res<Settings>) {
+    if settings.is_changed() && !settings.is_added() {
+        settings.save();
+    }
+}
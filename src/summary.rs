@@ -0,0 +1,257 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::{LevelCompleted, LevelId, LevelInfo, Playlist},
+    savegame::{read_json, write_json},
+    GameState, SpriteAssets,
+};
+
+pub(crate) const PROGRESS_NAME: &str = "progress";
+
+/// Shows a summary of the whole run after the last level was completed.
+/// Press `Enter` to continue to the end screen.
+///
+/// The [`Progress`] is persisted, so the best results per level survive restarts.
+pub struct MagnateSummaryPlugin;
+
+impl Plugin for MagnateSummaryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Progress::load())
+            .add_system_set(
+                SystemSet::on_update(GameState::Next)
+                    .with_system(track_attempt)
+                    .with_system(record_win)
+                    .with_system(enter_summary),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::Summary).with_system(spawn_summary))
+            .add_system_set(SystemSet::on_update(GameState::Summary).with_system(leave_summary))
+            .add_system_set(SystemSet::on_exit(GameState::Summary).with_system(despawn_summary));
+    }
+}
+
+#[derive(Component, Default, Debug, Clone)]
+pub struct SummaryScreen;
+
+/// The best result of a completed level.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LevelRecord {
+    pub best_moves: u32,
+    /// In seconds
+    pub best_time: f32,
+}
+
+/// Results of all completed built-in levels, as well as the current attempt.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Progress {
+    pub levels: BTreeMap<usize, LevelRecord>,
+    pub total_moves: u32,
+    /// In seconds
+    pub total_time: f32,
+    #[serde(skip)]
+    pub attempt_time: f32,
+}
+
+impl Progress {
+    pub fn load() -> Self {
+        match read_json(PROGRESS_NAME) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                warn!("Failed to deserialize progress: {:?}", e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) {
+        match serde_json::to_string(self) {
+            Ok(data) => write_json(data, PROGRESS_NAME),
+            Err(e) => warn!("Failed to serialize progress: {:?}", e),
+        }
+    }
+
+    /// Resets the current attempt, e.g. when a level is (re)loaded.
+    pub fn start_attempt(&mut self) {
+        self.attempt_time = 0.;
+    }
+
+    /// Resets the totals, the best results per level are kept.
+    pub fn start_run(&mut self) {
+        self.total_moves = 0;
+        self.total_time = 0.;
+    }
+
+    /// Adds the current attempt, which took `moves`, to the totals and updates the best results
+    /// of `level`.
+    pub fn record(&mut self, level: usize, moves: u32) {
+        self.total_moves += moves;
+        self.total_time += self.attempt_time;
+
+        let attempt = LevelRecord {
+            best_moves: moves,
+            best_time: self.attempt_time,
+        };
+        let record = self.levels.entry(level).or_insert(attempt);
+        record.best_moves = record.best_moves.min(attempt.best_moves);
+        record.best_time = record.best_time.min(attempt.best_time);
+    }
+
+    pub fn summary_text(&self) -> String {
+        let mut text = format!(
+            "All levels completed!\nMoves: {}\nTime: {:.0}s\n",
+            self.total_moves, self.total_time
+        );
+        for (level, record) in &self.levels {
+            text += &format!(
+                "\nLevel {}: {} moves, {:.0}s",
+                level, record.best_moves, record.best_time
+            );
+        }
+        text + "\n\nPress Enter to continue"
+    }
+}
+
+/// Times the current attempt. Its moves are counted by the [`LevelInfo`], undone moves don't
+/// count.
+fn track_attempt(mut progress: ResMut<Progress>, time: Res<Time>) {
+    progress.attempt_time += time.delta_seconds();
+}
+
+/// Winning the first level of the playlist starts a new run, so the totals only cover that run.
+fn record_win(
+    mut won: EventReader<LevelCompleted>,
+    playlist: Res<Playlist>,
+    mut progress: ResMut<Progress>,
+) {
+    for won in won.iter() {
+        if playlist.is_first(&won.level) {
+            progress.start_run();
+        }
+        if let LevelId::Builtin(level) = won.level {
+            progress.record(level, won.moves);
+            progress.save();
+        }
+    }
+}
+
+/// Shows the summary once the game advances from the last level of the playlist to the end level.
+fn enter_summary(
+    mut won: EventReader<LevelCompleted>,
+    level: Res<LevelInfo>,
+    playlist: Res<Playlist>,
+    mut state: ResMut<State<GameState>>,
+    mut last_level_won: Local<bool>,
+) {
+    if won.iter().any(|won| playlist.is_last(&won.level)) {
+        *last_level_won = true;
+    }
+    if *last_level_won && level.current == LevelId::end() {
+        *last_level_won = false;
+        let _ = state.push(GameState::Summary);
+    }
+}
+
+fn leave_summary(keys: Res<Input<KeyCode>>, mut state: ResMut<State<GameState>>) {
+    if keys.just_pressed(KeyCode::Return) {
+        let _ = state.pop();
+    }
+}
+
+fn spawn_summary(mut commands: Commands, progress: Res<Progress>, assets: Res<SpriteAssets>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                custom_size: Some(Vec2::new(1280., 720.)),
+                color: Color::rgba(0., 0., 0., 0.6),
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(0., 0., 950.),
+            ..Default::default()
+        })
+        .insert(SummaryScreen)
+        .insert(Name::new("Summary"))
+        .with_children(|builder| {
+            builder.spawn_bundle(Text2dBundle {
+                text: Text::from_section(
+                    progress.summary_text(),
+                    TextStyle {
+                        font: assets.font.clone(),
+                        font_size: 40.,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_xyz(0., 0., 1.),
+                ..Default::default()
+            });
+        });
+}
+
+fn despawn_summary(mut commands: Commands, screens: Query<Entity, With<SummaryScreen>>) {
+    for id in screens.iter() {
+        commands.entity(id).despawn_recursive();
+    }
+}
+
+#[test]
+fn test_progress_record() {
+    let mut progress = Progress::default();
+    progress.attempt_time = 10.;
+    progress.record(1, 5);
+
+    progress.start_attempt();
+    progress.attempt_time = 20.;
+    progress.record(1, 3);
+
+    progress.start_attempt();
+    progress.attempt_time = 4.;
+    progress.record(2, 7);
+
+    assert_eq!(progress.total_moves, 15);
+    assert_eq!(progress.total_time, 34.);
+    assert_eq!(
+        progress.levels[&1],
+        LevelRecord {
+            best_moves: 3,
+            best_time: 10.
+        }
+    );
+    assert_eq!(progress.levels.len(), 2);
+
+    progress.start_run();
+    assert_eq!(progress.total_moves, 0);
+    assert_eq!(progress.total_time, 0.);
+    assert_eq!(progress.levels.len(), 2);
+}
+
+#[test]
+fn test_enter_summary() {
+    let playlist = Playlist(vec![LevelId::Builtin(1), LevelId::Builtin(2)]);
+    let mut world = World::new();
+    world.insert_resource(Events::<LevelCompleted>::default());
+    world.insert_resource(LevelInfo::default());
+    world.insert_resource(playlist);
+    world.insert_resource(State::new(GameState::Next));
+
+    let mut stage = SystemStage::single_threaded();
+    stage
+        .add_system_set(State::<GameState>::get_driver())
+        .add_system_set(SystemSet::on_update(GameState::Next).with_system(enter_summary));
+    let mut finish = |world: &mut World, level: usize, next: LevelId| {
+        world
+            .resource_mut::<Events<LevelCompleted>>()
+            .send(LevelCompleted {
+                level: LevelId::Builtin(level),
+                moves: 1,
+                par_reached: false,
+            });
+        world.resource_mut::<LevelInfo>().current = next;
+        stage.run(world);
+        world.resource::<State<GameState>>().current().clone()
+    };
+
+    assert_eq!(finish(&mut world, 1, LevelId::Builtin(2)), GameState::Next);
+    assert_eq!(finish(&mut world, 2, LevelId::end()), GameState::Summary);
+}
@@ -11,10 +11,11 @@ pub const TRIANGLE_SIDE: f32 = 85.0;
 pub const SQRT3_HALF: f32 = 0.866025404;
 pub const X_DIR: Vec2 = Vec2::new(TRIANGLE_SIDE, 0.);
 pub const Y_DIR: Vec2 = Vec2::new(0.5 * TRIANGLE_SIDE, SQRT3_HALF * TRIANGLE_SIDE);
-const ISO_TO_ORTHO: Mat2 = Mat2::from_cols(X_DIR, Y_DIR);
-
+/// World position of vertex `(0, 0)` which aligns the grid with the background image
 const ZERO_OFFSET: Vec2 = Vec2::new(11., -34.);
-const TRIANGLE_Z: f32 = 500.;
+/// Distance in vertex coordinates within which a position counts as on a grid line
+const GRID_LINE_EPSILON: f32 = 1e-4;
+pub const TRIANGLE_Z: f32 = 500.;
 const RUNE_Z: f32 = 600.;
 
 // there is no IMat :(
@@ -27,9 +28,53 @@ pub enum TriangleOrient {
     PointingDown,
 }
 
+impl TriangleOrient {
+    /// The other orientation, as after a sixth turn.
+    pub fn opposite(self) -> Self {
+        match self {
+            TriangleOrient::PointingUp => TriangleOrient::PointingDown,
+            TriangleOrient::PointingDown => TriangleOrient::PointingUp,
+        }
+    }
+}
+
 #[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
 pub struct RuneTile {
     pub position: TileCoord,
+    /// Glyph in the rune sprite sheet, see [`crate::level_editor::RUNE_GLYPHS`]
+    #[serde(default)]
+    pub rune_index: usize,
+    /// Cells which all have to be covered to light the rune, e.g. for larger runes. Empty for
+    /// runes lit by a triangle on their own cell.
+    #[serde(default)]
+    pub required: Vec<TileCoord>,
+}
+
+impl RuneTile {
+    /// The cells which light the rune, see [`RuneTile::required`].
+    pub fn required_cells(&self) -> &[TileCoord] {
+        if self.required.is_empty() {
+            std::slice::from_ref(&self.position)
+        } else {
+            &self.required
+        }
+    }
+
+    /// Adds `cell` to the [`RuneTile::required`] cells or removes it again. A rune which only
+    /// requires its own cell goes back to the default.
+    pub fn toggle_required(&mut self, cell: TileCoord) {
+        let mut cells = self.required_cells().to_vec();
+        match cells.iter().position(|required| *required == cell) {
+            Some(i) => {
+                cells.remove(i);
+            }
+            None => cells.push(cell),
+        }
+        if cells == [self.position] {
+            cells.clear();
+        }
+        self.required = cells;
+    }
 }
 
 #[derive(Component, Default, Debug, Clone, Serialize, Deserialize)]
@@ -37,27 +82,80 @@ pub struct TriangleTile {
     pub position: TileCoord,
 }
 
+/// Color group of a [`TriangleTile`] or [`RuneTile`]. Runes are only lit by triangles of the same
+/// color. Tiles without this component have the default color `0`, the plain ruby.
+#[derive(Component, Default, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TileColor(pub u8);
+
 #[derive(Component, Default, Debug, Clone)]
 pub struct Immovable;
 
+/// Marks the parent of a clump of movable triangles. Immovables are never part of a clump.
+#[derive(Component, Default, Debug, Clone)]
+pub struct ClumpRoot;
+
+/// Placement of the triangle grid in the world. The default matches the background image and the
+/// size of the triangle sprites, other grids scale the tiles accordingly.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GridConfig {
+    /// World position of vertex `(0, 0)`
+    pub origin: Vec2,
+    /// Side length of the triangles in world units
+    pub side: f32,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            origin: ZERO_OFFSET,
+            side: TRIANGLE_SIDE,
+        }
+    }
+}
+
+impl GridConfig {
+    /// Scale of the tiles compared to the default grid.
+    pub fn scale(&self) -> f32 {
+        self.side / TRIANGLE_SIDE
+    }
+
+    fn iso_to_ortho(&self) -> Mat2 {
+        Mat2::from_cols(X_DIR, Y_DIR) * self.scale()
+    }
+
+    /// World position of a vertex.
+    pub fn vertex_pos(&self, vertex: VertexCoord) -> Vec2 {
+        self.origin + self.iso_to_ortho() * vertex.as_vec2()
+    }
+
+    /// Fractional vertex coordinates of a world position.
+    pub fn iso_pos(&self, pos: Vec2) -> Vec2 {
+        self.iso_to_ortho().inverse() * (pos - self.origin)
+    }
+}
+
 pub trait TransformInWorld {
-    fn to_world_pos(&self) -> Transform;
+    fn to_world_pos_in(&self, grid: &GridConfig) -> Transform;
+
+    /// Like [`TransformInWorld::to_world_pos_in`] with the default [`GridConfig`].
+    fn to_world_pos(&self) -> Transform {
+        self.to_world_pos_in(&GridConfig::default())
+    }
 }
 
 impl TransformInWorld for VertexCoord {
-    fn to_world_pos(&self) -> Transform {
-        let xy = ZERO_OFFSET + ISO_TO_ORTHO * self.as_vec2();
-        Transform::from_translation(xy.extend(0.))
+    fn to_world_pos_in(&self, grid: &GridConfig) -> Transform {
+        Transform::from_translation(grid.vertex_pos(*self).extend(0.))
     }
 }
 
 impl TransformInWorld for TriangleTile {
-    fn to_world_pos(&self) -> Transform {
-        let mut transf = self.position.0.to_world_pos();
+    fn to_world_pos_in(&self, grid: &GridConfig) -> Transform {
+        let mut transf = self.position.0.to_world_pos_in(grid);
         transf.scale = match self.position.1 {
             TriangleOrient::PointingUp => Vec3::ONE,
             TriangleOrient::PointingDown => Vec3::new(1., -1., 1.),
-        };
+        } * grid.scale();
         transf.translation.z = TRIANGLE_Z;
 
         transf
@@ -65,38 +163,52 @@ impl TransformInWorld for TriangleTile {
 }
 
 impl TransformInWorld for RuneTile {
-    fn to_world_pos(&self) -> Transform {
-        let mut transf = self.position.0.to_world_pos();
+    fn to_world_pos_in(&self, grid: &GridConfig) -> Transform {
+        let mut transf = self.position.0.to_world_pos_in(grid);
         transf.translation += match self.position.1 {
             TriangleOrient::PointingUp => (X_DIR + Y_DIR) * 1. / 3.,
             TriangleOrient::PointingDown => (X_DIR - Y_DIR / 2.) * 2. / 3.,
         }
-        .extend(0.);
+        .extend(0.)
+            * grid.scale();
         transf.translation.z = RUNE_Z;
-        transf.scale = Vec3::splat(0.35);
+        transf.scale = Vec3::splat(0.35 * grid.scale());
 
         transf
     }
 }
 
 pub trait FromWorldPosition {
-    fn from_world_pos(pos: Vec2) -> Self;
+    fn from_world_pos_in(pos: Vec2, grid: &GridConfig) -> Self;
+
+    /// Like [`FromWorldPosition::from_world_pos_in`] with the default [`GridConfig`].
+    fn from_world_pos(pos: Vec2) -> Self
+    where
+        Self: Sized,
+    {
+        Self::from_world_pos_in(pos, &GridConfig::default())
+    }
 }
 
 impl FromWorldPosition for VertexCoord {
-    fn from_world_pos(pos: Vec2) -> Self {
-        let xy = ISO_TO_ORTHO.inverse() * (pos - ZERO_OFFSET);
-        xy.round().as_ivec2()
+    fn from_world_pos_in(pos: Vec2, grid: &GridConfig) -> Self {
+        grid.iso_pos(pos).round().as_ivec2()
     }
 }
 
 impl FromWorldPosition for TileCoord {
-    fn from_world_pos(pos: Vec2) -> Self {
-        let xy = ISO_TO_ORTHO.inverse() * (pos - ZERO_OFFSET);
+    /// Positions on a grid line belong to the upwards triangle above or right of it.
+    fn from_world_pos_in(pos: Vec2, grid: &GridConfig) -> Self {
+        let xy = grid.iso_pos(pos);
+        // Snap to grid lines, rounding errors could otherwise move a vertex into the cell below it
+        let on_line = (xy - xy.round())
+            .abs()
+            .cmplt(Vec2::splat(GRID_LINE_EPSILON));
+        let xy = Vec2::select(on_line, xy.round(), xy);
         let base = xy.floor();
         let frac = xy - base;
 
-        if frac.x + frac.y <= 1. {
+        if frac.x + frac.y <= 1. + GRID_LINE_EPSILON {
             (base.as_ivec2(), TriangleOrient::PointingUp)
         } else {
             (base.as_ivec2() + IVec2::Y, TriangleOrient::PointingDown)
@@ -107,6 +219,26 @@ impl FromWorldPosition for TileCoord {
 pub trait RotateAroundVertex {
     fn rotated_clockwise(&self, anchor: VertexCoord) -> Self;
     fn rotated_counter_clockwise(&self, anchor: VertexCoord) -> Self;
+
+    /// Rotates by `steps` sixth turns, clockwise for positive and counter clockwise for negative
+    /// steps. Takes the shorter way around, so six steps are no rotation at all.
+    fn rotated_n(&self, anchor: VertexCoord, steps: i32) -> Self
+    where
+        Self: Clone,
+    {
+        let steps = steps.rem_euclid(6);
+        let mut rotated = self.clone();
+        if steps <= 3 {
+            for _ in 0..steps {
+                rotated = rotated.rotated_clockwise(anchor);
+            }
+        } else {
+            for _ in steps..6 {
+                rotated = rotated.rotated_counter_clockwise(anchor);
+            }
+        }
+        rotated
+    }
 }
 
 impl RotateAroundVertex for TileCoord {
@@ -115,10 +247,11 @@ impl RotateAroundVertex for TileCoord {
         let r = ISO_LEFT_ROT * d.as_vec2();
         let p = anchor + r.round().as_ivec2();
 
-        match self.1 {
-            TriangleOrient::PointingUp => (p, TriangleOrient::PointingDown),
-            TriangleOrient::PointingDown => (p - VertexCoord::Y, TriangleOrient::PointingUp),
-        }
+        let left = match self.1 {
+            TriangleOrient::PointingUp => p,
+            TriangleOrient::PointingDown => p - VertexCoord::Y,
+        };
+        (left, self.1.opposite())
     }
 
     fn rotated_counter_clockwise(&self, anchor: VertexCoord) -> Self {
@@ -126,15 +259,102 @@ impl RotateAroundVertex for TileCoord {
         let r = ISO_LEFT_ROT.inverse() * d.as_vec2();
         let p = anchor + r.round().as_ivec2();
 
+        let left = match self.1 {
+            TriangleOrient::PointingUp => p + VertexCoord::new(-1, 1),
+            TriangleOrient::PointingDown => p,
+        };
+        (left, self.1.opposite())
+    }
+}
+
+pub trait TileVertices {
+    /// The three corners of the triangle, starting with the left one in counter clockwise order.
+    fn vertices(&self) -> [VertexCoord; 3];
+
+    /// The three corners in world space, in the same order as [`TileVertices::vertices`].
+    fn world_vertices_in(&self, grid: &GridConfig) -> [Vec2; 3] {
+        self.vertices().map(|v| grid.vertex_pos(v))
+    }
+
+    /// Like [`TileVertices::world_vertices_in`] with the default [`GridConfig`].
+    fn world_vertices(&self) -> [Vec2; 3] {
+        self.world_vertices_in(&GridConfig::default())
+    }
+
+    /// Center of the triangle in world space.
+    fn world_center_in(&self, grid: &GridConfig) -> Vec2 {
+        self.world_vertices_in(grid).iter().sum::<Vec2>() / 3.
+    }
+
+    /// Like [`TileVertices::world_center_in`] with the default [`GridConfig`].
+    fn world_center(&self) -> Vec2 {
+        self.world_center_in(&GridConfig::default())
+    }
+
+    /// Whether the triangle contains `pos`, by its barycentric coordinates. Points on an edge
+    /// belong to both triangles sharing it.
+    fn contains_world_point_in(&self, pos: Vec2, grid: &GridConfig) -> bool {
+        let [a, b, c] = self.world_vertices_in(grid);
+        let det = (b - a).perp_dot(c - a);
+        let u = (b - pos).perp_dot(c - pos) / det;
+        let v = (c - pos).perp_dot(a - pos) / det;
+        let w = 1. - u - v;
+        // Tolerance for points exactly on an edge
+        let eps = 1e-5;
+        u >= -eps && v >= -eps && w >= -eps
+    }
+
+    /// Like [`TileVertices::contains_world_point_in`] with the default [`GridConfig`].
+    fn contains_world_point(&self, pos: Vec2) -> bool {
+        self.contains_world_point_in(pos, &GridConfig::default())
+    }
+}
+
+impl TileVertices for TileCoord {
+    fn vertices(&self) -> [VertexCoord; 3] {
         match self.1 {
-            TriangleOrient::PointingUp => {
-                (p + VertexCoord::new(-1, 1), TriangleOrient::PointingDown)
-            }
-            TriangleOrient::PointingDown => (p, TriangleOrient::PointingUp),
+            TriangleOrient::PointingUp => [self.0, self.0 + IVec2::X, self.0 + IVec2::Y],
+            TriangleOrient::PointingDown => [self.0, self.0 + IVec2::new(1, -1), self.0 + IVec2::X],
         }
     }
 }
 
+impl TileVertices for TriangleTile {
+    fn vertices(&self) -> [VertexCoord; 3] {
+        self.position.vertices()
+    }
+}
+
+pub trait TranslateTile {
+    /// The same tile moved along the grid by `delta`.
+    fn translated(&self, delta: VertexCoord) -> Self;
+}
+
+impl TranslateTile for TileCoord {
+    fn translated(&self, delta: VertexCoord) -> Self {
+        (self.0 + delta, self.1)
+    }
+}
+
+pub trait TrianglesAroundVertex {
+    /// The six triangles sharing this vertex in counter clockwise order, starting with the
+    /// one to the upper right.
+    fn triangles_around(&self) -> [TileCoord; 6];
+}
+
+impl TrianglesAroundVertex for VertexCoord {
+    fn triangles_around(&self) -> [TileCoord; 6] {
+        [
+            (*self, TriangleOrient::PointingUp),
+            (*self + IVec2::new(-1, 1), TriangleOrient::PointingDown),
+            (*self - IVec2::X, TriangleOrient::PointingUp),
+            (*self - IVec2::X, TriangleOrient::PointingDown),
+            (*self - IVec2::Y, TriangleOrient::PointingUp),
+            (*self, TriangleOrient::PointingDown),
+        ]
+    }
+}
+
 pub trait IterNeighbors {
     type Iter: ExactSizeIterator<Item = Self>;
     fn iter_neighbors(&self) -> Self::Iter;
@@ -175,3 +395,191 @@ fn test_rotation() {
         (VertexCoord::new(0, 0), TriangleOrient::PointingUp)
     );
 }
+
+#[test]
+fn test_rotated_n() {
+    let anchor = VertexCoord::new(1, -1);
+    for orient in [TriangleOrient::PointingUp, TriangleOrient::PointingDown] {
+        let tile = (VertexCoord::new(3, 0), orient);
+        assert_eq!(tile.rotated_n(anchor, 6), tile);
+        assert_eq!(tile.rotated_n(anchor, 0), tile);
+        assert_eq!(tile.rotated_n(anchor, -12), tile);
+        assert_eq!(tile.rotated_n(anchor, 1), tile.rotated_clockwise(anchor));
+        assert_eq!(
+            tile.rotated_n(anchor, -1),
+            tile.rotated_counter_clockwise(anchor)
+        );
+        assert_eq!(tile.rotated_n(anchor, 5), tile.rotated_n(anchor, -1));
+
+        // A half turn mirrors the tile through the anchor
+        let half = tile.rotated_n(anchor, 3);
+        assert_eq!(half, tile.rotated_n(anchor, -3));
+        assert_eq!(half.1, orient.opposite());
+        let mirrored = 2. * anchor.to_world_pos().translation.truncate() - tile.world_center();
+        assert!((half.world_center() - mirrored).length() < 1e-3);
+    }
+    assert_eq!(
+        TriangleOrient::PointingUp.opposite().opposite(),
+        TriangleOrient::PointingUp
+    );
+}
+
+#[test]
+fn test_toggle_required() {
+    let position = (VertexCoord::ZERO, TriangleOrient::PointingUp);
+    let other = (VertexCoord::ZERO, TriangleOrient::PointingDown);
+    let mut rune = RuneTile {
+        position,
+        ..Default::default()
+    };
+    rune.toggle_required(other);
+    assert_eq!(rune.required, vec![position, other]);
+    rune.toggle_required(position);
+    assert_eq!(rune.required, vec![other]);
+    rune.toggle_required(position);
+    rune.toggle_required(other);
+    assert!(rune.required.is_empty());
+    assert_eq!(rune.required_cells(), &[position]);
+}
+
+#[test]
+fn test_from_world_pos_round_trip() {
+    let grids = [
+        GridConfig::default(),
+        GridConfig {
+            origin: Vec2::new(-333.3, 71.7),
+            side: 37.1,
+        },
+    ];
+    for grid in grids {
+        for x in -12..12 {
+            for y in -12..12 {
+                let vertex = VertexCoord::new(x, y);
+                let up = (vertex, TriangleOrient::PointingUp);
+                let down = (vertex, TriangleOrient::PointingDown);
+                for tile in [up, down] {
+                    let center = tile.world_center_in(&grid);
+                    assert_eq!(TileCoord::from_world_pos_in(center, &grid), tile);
+                }
+
+                // Vertices and edges belong to the upwards triangle right of them
+                let corner = TriangleTile { position: up }
+                    .to_world_pos_in(&grid)
+                    .translation
+                    .truncate();
+                assert_eq!(TileCoord::from_world_pos_in(corner, &grid), up);
+                assert_eq!(VertexCoord::from_world_pos_in(corner, &grid), vertex);
+                let [_, right, top] = up.world_vertices_in(&grid);
+                for edge in [
+                    (corner + right) / 2.,
+                    (corner + top) / 2.,
+                    (right + top) / 2.,
+                ] {
+                    assert_eq!(TileCoord::from_world_pos_in(edge, &grid), up);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_translate_tile() {
+    let tile = (VertexCoord::new(1, -2), TriangleOrient::PointingDown);
+    let delta = VertexCoord::new(-3, 4);
+    let moved = tile.translated(delta);
+    assert_eq!(
+        moved,
+        (VertexCoord::new(-2, 2), TriangleOrient::PointingDown)
+    );
+    assert_eq!(moved.translated(-delta), tile);
+
+    // The center moves along with the vertices
+    let shift = delta.to_world_pos().translation - VertexCoord::ZERO.to_world_pos().translation;
+    assert!((moved.world_center() - tile.world_center() - shift.truncate()).length() < 1e-3);
+    assert_eq!(TileCoord::from_world_pos(tile.world_center()), tile);
+
+    // Both orientations are kept, also when crossing the origin
+    for orient in [TriangleOrient::PointingUp, TriangleOrient::PointingDown] {
+        let tile = (VertexCoord::new(2, 1), orient);
+        let moved = tile.translated(VertexCoord::new(-5, -3));
+        assert_eq!(moved.1, orient);
+        assert_eq!(moved.translated(VertexCoord::new(5, 3)), tile);
+        assert_eq!(tile.translated(VertexCoord::ZERO), tile);
+    }
+}
+
+#[test]
+fn test_grid_config() {
+    let grid = GridConfig {
+        origin: Vec2::new(-100., 20.),
+        side: 2. * TRIANGLE_SIDE,
+    };
+    let tile = (VertexCoord::new(3, -1), TriangleOrient::PointingDown);
+    assert_eq!(
+        VertexCoord::ZERO.to_world_pos_in(&grid).translation,
+        Vec3::new(-100., 20., 0.)
+    );
+    assert_eq!(
+        TileCoord::from_world_pos_in(tile.world_center_in(&grid), &grid),
+        tile
+    );
+    assert_eq!(
+        TriangleTile { position: tile }.to_world_pos_in(&grid).scale,
+        Vec3::new(2., -2., 2.)
+    );
+
+    // The default grid is the one of the background image
+    let default = GridConfig::default();
+    assert_eq!(default.vertex_pos(VertexCoord::ZERO), ZERO_OFFSET);
+    assert_eq!(tile.world_center_in(&default), tile.world_center());
+}
+
+#[test]
+fn test_contains_world_point() {
+    let up = (VertexCoord::new(2, -1), TriangleOrient::PointingUp);
+    let down = (VertexCoord::new(2, 0), TriangleOrient::PointingDown);
+    assert!(up.contains_world_point(up.world_center()));
+    assert!(!down.contains_world_point(up.world_center()));
+
+    // Both triangles share the edge from (2, 0) to (3, -1)
+    let edge = (VertexCoord::new(2, 0).to_world_pos().translation
+        + VertexCoord::new(3, -1).to_world_pos().translation)
+        .truncate()
+        / 2.;
+    assert!(up.contains_world_point(edge));
+    assert!(down.contains_world_point(edge));
+    for corner in up.world_vertices() {
+        assert!(up.contains_world_point(corner));
+    }
+
+    // The tile found for a point always contains it
+    for i in 0..40 {
+        for j in 0..40 {
+            let pos = Vec2::new(i as f32, j as f32) * 7.3 - Vec2::splat(140.);
+            assert!(TileCoord::from_world_pos(pos).contains_world_point(pos));
+        }
+    }
+}
+
+#[test]
+fn test_world_vertices() {
+    let tile = TriangleTile {
+        position: (VertexCoord::new(-1, 2), TriangleOrient::PointingDown),
+    };
+    let [left, bottom, right] = tile.world_vertices();
+    assert_eq!(
+        left,
+        VertexCoord::new(-1, 2)
+            .to_world_pos()
+            .translation
+            .truncate()
+    );
+    assert!((right - left - X_DIR).length() < 1e-3);
+    assert!((bottom - left - (X_DIR - Y_DIR)).length() < 1e-3);
+    assert_eq!(tile.world_vertices(), tile.position.world_vertices());
+
+    // Upwards triangles have their tip at the top
+    let [left, right, top] = (VertexCoord::ZERO, TriangleOrient::PointingUp).world_vertices();
+    assert!((right - left - X_DIR).length() < 1e-3);
+    assert!((top - left - Y_DIR).length() < 1e-3);
+}